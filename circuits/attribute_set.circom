@@ -0,0 +1,151 @@
+// Verify whether an attribute behind the given Pedersen commitment is (or is not)
+// in a set committed by a sorted Merkle tree.
+
+pragma circom 2.0.0;
+
+include "circomlib/babyjub.circom";
+include "circomlib/escalarmulany.circom";
+include "circomlib/escalarmulfix.circom";
+include "circomlib/comparators.circom";
+include "merkle_tree.circom";
+include "cmp.circom";
+include "generators_hash.circom";
+
+template AttributeSet(N, L) {
+    // public input
+    signal input Ax;
+    signal input Ay;
+    // scm = hash(root, index, member, hash of the generators and the schema, challenge)
+    signal input scm;
+
+    //private input
+    // seed
+    signal input k;
+    signal input a[N]; // atributes
+    // generators of the CA
+    signal input Gx[N];
+    signal input Gy[N];
+    // hash of the schema of the credential
+    signal input sh;
+    // the attribute in the set
+    signal input index;
+    // 1 for membership, 0 for non-membership
+    signal input member;
+    // merkle proof of the leaf, the lower neighbour is path[0] in a non-membership proof
+    signal input path[L];
+    signal input key;
+    signal input leaf;
+    signal input root;
+    // hash of the challenge of the verifier, 0 if the proof is not bound to one
+    signal input ctx;
+
+    var i;
+    var j;
+    var G[2] = [
+        995203441582195749578291179787384436505546430278305826713579947235728471134,
+        5472060717959818805561601436314318772137091100104008585924551046643952123905
+    ];
+
+    // check A = kG + sum(a[i] * G[i])
+    component k2bits = Num2Bits(254);
+    k2bits.in <== k;
+
+    component kG = EscalarMulFix(254, G);
+    for (i=0; i<254; i++) {
+        kG.e[i] <== k2bits.out[i];
+    }
+
+    component a2bits[N];
+    component aG[N];
+    component addA[N];
+    for(i = 0; i < N; i++) {
+        a2bits[i] = Num2Bits(254);
+        a2bits[i].in <== a[i];
+        aG[i] = EscalarMulAny(254);
+        aG[i].p[0] <== Gx[i];
+        aG[i].p[1] <== Gy[i];
+        for (j=0; j<254; j++) {
+            aG[i].e[j] <== a2bits[i].out[j];
+        }
+
+        addA[i] = BabyAdd();
+        if (i == 0) {
+            addA[i].x1 <== kG.out[0];
+            addA[i].y1 <== kG.out[1];
+        } else {
+            addA[i].x1 <== addA[i-1].xout;
+            addA[i].y1 <== addA[i-1].yout;
+        }
+        addA[i].x2 <== aG[i].out[0];
+        addA[i].y2 <== aG[i].out[1];
+    }
+
+    Ax === addA[N-1].xout;
+    Ay === addA[N-1].yout;
+
+    // select the attribute, the index must be smaller than N
+    component isIndex[N];
+    signal selected[N];
+    var count = 0;
+    for(i = 0; i < N; i++) {
+        isIndex[i] = IsEqual();
+        isIndex[i].in[0] <== index;
+        isIndex[i].in[1] <== i;
+        selected[i] <== isIndex[i].out * a[i];
+        count += isIndex[i].out;
+    }
+    count === 1;
+    // the leaves of a set are the values plus one, as 0 is the left boundary of the
+    // trees of non-membership proofs
+    var value = 1;
+    for(i = 0; i < N; i++) {
+        value += selected[i];
+    }
+
+    // check the leaf in the tree
+    component tree = MerkleTree(L);
+    for(i = 0; i < L; i++) {
+        tree.path[i] <== path[i];
+    }
+    tree.key <== key;
+    tree.value <== leaf;
+    tree.root === root;
+
+    member * (member - 1) === 0;
+
+    // membership: the leaf is the attribute
+    member * (leaf - value) === 0;
+
+    // non-membership: path[0] < attribute < leaf, where the leaf is a right child so
+    // that the two leaves are neighbours
+    component key2bits = Num2Bits(L);
+    key2bits.in <== key;
+    (1 - member) * (1 - key2bits.out[0]) === 0;
+    component cmp1 = Cmp(254);
+    cmp1.in1 <== value;
+    cmp1.in2 <== leaf;
+    (1 - member) * cmp1.out === 0;
+    component cmp2 = Cmp(254);
+    cmp2.in1 <== path[0];
+    cmp2.in2 <== value;
+    (1 - member) * cmp2.out === 0;
+
+    // bind the set to the generators and the schema
+    component caHash = CaHash(N);
+    for(i = 0; i < N; i++) {
+        caHash.Gx[i] <== Gx[i];
+        caHash.Gy[i] <== Gy[i];
+    }
+    caHash.sh <== sh;
+
+    component scmHash = Poseidon(5);
+    scmHash.inputs[0] <== root;
+    scmHash.inputs[1] <== index;
+    scmHash.inputs[2] <== member;
+    scmHash.inputs[3] <== caHash.out;
+    scmHash.inputs[4] <== ctx;
+
+    scmHash.out === scm;
+}
+
+component main {public [Ax, Ay, scm] } = AttributeSet(8, 16);
@@ -0,0 +1,47 @@
+// Hash the generators of the Pedersen commitment of a CA, and the CA with the schema
+// of its credentials.
+
+pragma circom 2.0.0;
+
+include "circomlib/poseidon.circom";
+
+// The hash of the generators of a CA, chained over the points. This must match
+// `generators_hash` in src/ca_client.rs.
+template GeneratorsHash(N) {
+    signal input Gx[N];
+    signal input Gy[N];
+    signal output out;
+
+    assert(N > 0);
+    component hash[N];
+    hash[0] = Poseidon(2);
+    hash[0].inputs[0] <== Gx[0];
+    hash[0].inputs[1] <== Gy[0];
+    for (var i = 1; i < N; i++) {
+        hash[i] = Poseidon(3);
+        hash[i].inputs[0] <== hash[i - 1].out;
+        hash[i].inputs[1] <== Gx[i];
+        hash[i].inputs[2] <== Gy[i];
+    }
+    out <== hash[N - 1].out;
+}
+
+// The hash of a CA, binding its generators to the schema of its credentials. This
+// must match `ca_hash` in src/ca_client.rs.
+template CaHash(N) {
+    signal input Gx[N];
+    signal input Gy[N];
+    // hash of the schema
+    signal input sh;
+    signal output out;
+
+    component gHash = GeneratorsHash(N);
+    for (var i = 0; i < N; i++) {
+        gHash.Gx[i] <== Gx[i];
+        gHash.Gy[i] <== Gy[i];
+    }
+    component hash = Poseidon(2);
+    hash.inputs[0] <== gHash.out;
+    hash.inputs[1] <== sh;
+    out <== hash.out;
+}
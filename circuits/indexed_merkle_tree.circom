@@ -0,0 +1,52 @@
+// Check that the given value is not in the indexed Merkle tree, using the low leaf
+// (lowValue, nextValue, nextIndex) whose range covers the value.
+
+pragma circom 2.0.0;
+
+include "circomlib/poseidon.circom";
+include "circomlib/comparators.circom";
+include "merkle_tree.circom";
+include "cmp.circom";
+
+template IndexedNotIn(n) {
+    signal input value;
+    signal input lowValue;
+    signal input nextValue;
+    signal input nextIndex;
+    signal input path[n];
+    signal input key;
+    signal output root;
+
+    var i;
+
+    // check lowValue < value
+    component cmp1 = Cmp(254);
+    cmp1.in1 <== lowValue;
+    cmp1.in2 <== value;
+    cmp1.out === 0;
+
+    // check value < nextValue, unless the low leaf is the largest one (nextValue = 0)
+    component cmp2 = Cmp(254);
+    cmp2.in1 <== value;
+    cmp2.in2 <== nextValue;
+    component isz = IsZero();
+    isz.in <== nextValue;
+    (1 - isz.out) * cmp2.out === 0;
+
+    // hash of the low leaf
+    component leaf = Poseidon(3);
+    leaf.inputs[0] <== lowValue;
+    leaf.inputs[1] <== nextValue;
+    leaf.inputs[2] <== nextIndex;
+
+    component tree = MerkleTree(n);
+    for(i = 0; i < n; i++) {
+        tree.path[i] <== path[i];
+    }
+    tree.key <== key;
+    tree.value <== leaf.out;
+
+    tree.root ==> root;
+}
+
+// component main {public [root]} = IndexedNotIn(40);
@@ -11,8 +11,53 @@ include "merkle_tree.circom";
 include "cmp.circom";
 include "tpke_dual.circom";
 include "check_point.circom";
+include "generators_hash.circom";
 
-template PedersenCommit() {
+// The hash of the ranges. The first six bounds of l and r are hashed
+// separately, the remaining bounds are absorbed two by two together with the
+// previous hash. This must match `range_hash` in src/user_client.rs.
+template RangeHash(N) {
+    signal input l[N];
+    signal input r[N];
+    signal output out;
+
+    assert(N > 0);
+    var i;
+    var j;
+    var head = N < 6 ? N : 6;
+    var tail = N - head;
+    // at least one round, which only hashes hash1 and hash2 when N <= 6
+    var rounds = tail > 0 ? (tail + 1) \ 2 : 1;
+    var k;
+    var n;
+
+    component hash1 = Poseidon(head);
+    component hash2 = Poseidon(head);
+    for (i = 0; i < head; i++) {
+        hash1.inputs[i] <== l[i];
+        hash2.inputs[i] <== r[i];
+    }
+
+    component absorb[rounds];
+    for (j = 0; j < rounds; j++) {
+        k = head + 2 * j;
+        n = N - k < 2 ? N - k : 2;
+        absorb[j] = Poseidon(2 * n + (j == 0 ? 2 : 1));
+        for (i = 0; i < n; i++) {
+            absorb[j].inputs[i] <== l[k + i];
+            absorb[j].inputs[n + i] <== r[k + i];
+        }
+        if (j == 0) {
+            absorb[j].inputs[2 * n] <== hash1.out;
+            absorb[j].inputs[2 * n + 1] <== hash2.out;
+        } else {
+            absorb[j].inputs[2 * n] <== absorb[j - 1].out;
+        }
+    }
+    out <== absorb[rounds - 1].out;
+}
+
+template PedersenCommit(N) {
     // public input
     signal input Ax;
     signal input Ay;
@@ -21,9 +66,16 @@ template PedersenCommit() {
     //private input
     // seed
     signal input k; 
-    signal input a[8]; // atributes
-    signal input l[8];
-    signal input r[8];
+    signal input a[N]; // atributes
+    signal input l[N];
+    signal input r[N];
+    // generators of the CA
+    signal input Gx[N];
+    signal input Gy[N];
+    // hash of the schema of the credential
+    signal input sh;
+    // hash of the challenge of the verifier, 0 if the proof is not bound to one
+    signal input ctx;
 
     var i;
     var j;
@@ -32,54 +84,13 @@ template PedersenCommit() {
         5472060717959818805561601436314318772137091100104008585924551046643952123905
     ];
 
-    var Gi[8][2];
-    Gi[0] = [
-        3709233544655094114095305628899106162100264105945362915460700051817000217269,
-        12768044360317194929467284938524341882186637443921606345792322228824155198882
-    ];
-
-    Gi[1] = [
-        21114813577525946862493269576609299497532859799554272967322590212668120427567,
-        20691355949215438973895378684185926060668323158547601266162805165283114603642
-    ];
-
-    Gi[2] = [
-        17625328386777005926307709551248024747691659287965267137690207058530731101871,
-        8126690508250741460006030187731943180058104680946803211224658532738519221944
-    ];
+    component l2bits[N];
+    component r2bits[N];
+    component a2bits[N];
+    component lcmp[N];
+    component rcmp[N];
 
-    Gi[3] = [
-        15800272061361453462652215320637269033269242924073537434705176300283727080247,
-        11210986719567668438700578192231096434213495698820271961498463217150294241773
-    ];
-
-    Gi[4] = [
-        1076918765380774143164642194428017826965896759846395500273710191250140564695,
-        6277032803431125082521875329448606153588193729080308891407659126278407332794
-    ];
-
-    Gi[5] = [
-        10083117203619913772511200911994368415824947324922194844889644451106546262377,
-        9406821708785457768010486607710887494561706835413712896651723470895041767510
-    ];
-
-    Gi[6] = [
-        19476208024240268757551070309056276642802163789212938542233843238422269788927,
-        13260462060444046883858963737081405245536577417134462088090858956361601270372
-    ];
-
-    Gi[7] = [
-        11562500462764162036644261303661207031909061010885382076968723100699068566818,
-        10943495341978773641186598690310331845231994940074104220132740812456397912788
-    ];
-
-    component l2bits[8];
-    component r2bits[8];
-    component a2bits[8];
-    component lcmp[8];
-    component rcmp[8];
-
-    for(i = 0; i < 8; i++) {
+    for(i = 0; i < N; i++) {
         l2bits[i] = Num2Bits(254);
         r2bits[i] = Num2Bits(254);
         a2bits[i] = Num2Bits(254);
@@ -107,55 +118,17 @@ template PedersenCommit() {
         kG.e[i] <== k2bits.out[i];
     }
     
-    component aG[8];
-    aG[0] = EscalarMulFix(254, Gi[0]);
-    for (i=0; i<254; i++) {
-        aG[0].e[i] <== a2bits[0].out[i];
-    }
-
-    aG[1] = EscalarMulFix(254, Gi[1]);
-    for (i=0; i<254; i++) {
-        aG[1].e[i] <== a2bits[1].out[i];
-    }
-
-    aG[2] = EscalarMulFix(254, Gi[2]);
-    for (i=0; i<254; i++) {
-        aG[2].e[i] <== a2bits[2].out[i];
-    }
-
-    aG[3] = EscalarMulFix(254, Gi[3]);
-    for (i=0; i<254; i++) {
-        aG[3].e[i] <== a2bits[3].out[i];
-    }
-
-    aG[4] = EscalarMulFix(254, Gi[4]);
-    for (i=0; i<254; i++) {
-        aG[4].e[i] <== a2bits[4].out[i];
-    }
-
-    aG[5] = EscalarMulFix(254, Gi[5]);
-    for (i=0; i<254; i++) {
-        aG[5].e[i] <== a2bits[5].out[i];
-    }
-
-    aG[6] = EscalarMulFix(254, Gi[6]);
-    for (i=0; i<254; i++) {
-        aG[6].e[i] <== a2bits[6].out[i];
-    }
-
-    aG[7] = EscalarMulFix(254, Gi[7]);
-    for (i=0; i<254; i++) {
-        aG[7].e[i] <== a2bits[7].out[i];
+    component aG[N];
+    for(i = 0; i < N; i++) {
+        aG[i] = EscalarMulAny(254);
+        aG[i].p[0] <== Gx[i];
+        aG[i].p[1] <== Gy[i];
+        for (j=0; j<254; j++) {
+            aG[i].e[j] <== a2bits[i].out[j];
+        }
     }
 
-    // for(i = 1; i < 10; i++) {
-    //     aG[i] = EscalarMulFix(254, Gi[i]);
-    //     for (j=0; i<254; j++) {
-    //         aG[i].e[j] <== a2bits[i].out[j];
-    //     }
-    // }
-
-    component addA[8];
+    component addA[N];
 
     addA[0] = BabyAdd();
     addA[0].x1 <== kG.out[0];
@@ -163,7 +136,7 @@ template PedersenCommit() {
     addA[0].x2 <== aG[0].out[0];
     addA[0].y2 <== aG[0].out[1];
 
-    for(i = 1; i < 8; i++) {
+    for(i = 1; i < N; i++) {
         addA[i] = BabyAdd();
         addA[i].x1 <== addA[i-1].xout;
         addA[i].y1 <== addA[i-1].yout;
@@ -171,33 +144,31 @@ template PedersenCommit() {
         addA[i].y2 <== aG[i].out[1];
     }
 
-    Ax === addA[7].xout;
-    Ay === addA[7].yout;
-
-    component hash1 = Poseidon(6);
-    hash1.inputs[0] <== l[0];
-    hash1.inputs[1] <== l[1];
-    hash1.inputs[2] <== l[2];
-    hash1.inputs[3] <== l[3];
-    hash1.inputs[4] <== l[4];
-    hash1.inputs[5] <== l[5];
-    component hash2 = Poseidon(6);
-    hash2.inputs[0] <== r[0];
-    hash2.inputs[1] <== r[1];
-    hash2.inputs[2] <== r[2];
-    hash2.inputs[3] <== r[3];
-    hash2.inputs[4] <== r[4];
-    hash2.inputs[5] <== r[5];
-    component hash3 = Poseidon(6);
-    hash3.inputs[0] <== l[6];
-    hash3.inputs[1] <== l[7];
-    hash3.inputs[2] <== r[6];
-    hash3.inputs[3] <== r[7];
-    hash3.inputs[4] <== hash1.out;
-    hash3.inputs[5] <== hash2.out;
-
-    hash3.out === lrcm;
-}
+    Ax === addA[N-1].xout;
+    Ay === addA[N-1].yout;
+
+    component lrHash = RangeHash(N);
+    for(i = 0; i < N; i++) {
+        lrHash.l[i] <== l[i];
+        lrHash.r[i] <== r[i];
+    }
 
-component main {public [Ax, Ay, lrcm] } = PedersenCommit();
+    // bind the ranges to the generators and the schema, the verifier checks them
+    // with the ones of a trusted CA
+    component caHash = CaHash(N);
+    for(i = 0; i < N; i++) {
+        caHash.Gx[i] <== Gx[i];
+        caHash.Gy[i] <== Gy[i];
+    }
+    caHash.sh <== sh;
+
+    // bind the proof to the challenge, so that it cannot be replayed
+    component lrcmHash = Poseidon(3);
+    lrcmHash.inputs[0] <== lrHash.out;
+    lrcmHash.inputs[1] <== caHash.out;
+    lrcmHash.inputs[2] <== ctx;
+
+    lrcmHash.out === lrcm;
+}
 
+component main {public [Ax, Ay, lrcm] } = PedersenCommit(8);
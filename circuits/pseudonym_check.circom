@@ -53,6 +53,7 @@ template KeyDerive(L1, L2) {
     signal input x; // master key
 
     signal input e;
+    signal input sh; // schema hash
     signal input b; // key
     // attributes
     signal input Ax;
@@ -193,11 +194,12 @@ template KeyDerive(L1, L2) {
     hash1.inputs[2] <== mulFixB.out[0];
     hash1.inputs[3] <== mulFixB.out[1];
 
-    component hash2 = Poseidon(4);
+    component hash2 = Poseidon(5);
     hash2.inputs[0] <== hash1.out;
     hash2.inputs[1] <== Ax;
     hash2.inputs[2] <== Ay;
     hash2.inputs[3] <== e;
+    hash2.inputs[4] <== sh;
 
     component eddsa = EdDSAPoseidonVerifier();
     eddsa.enabled <== 1;
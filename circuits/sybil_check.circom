@@ -33,8 +33,9 @@ template AppKey() {
     // id <-- ss & 1461501637330902918203684832716283019655932542975;
     // Ys <-- (ss >> 160) & 1;
     // C2s <-- (ss >> 161) & 1;
+    // ctx <-- ss >> 162, the challenge of the verifier, bound by ss being public
 
-    component ss2bits = Num2Bits(162);
+    component ss2bits = Num2Bits(250);
     ss2bits.in <== ss;
 
     component YCheck = PointCheck();
@@ -6,6 +6,15 @@ pragma abicoder v2;
 
 import "@openzeppelin/contracts/access/AccessControl.sol";
 
+// Poseidon hash, e.g. the contract generated by poseidon_gencontract of circomlibjs.
+interface IPoseidon3 {
+    function poseidon(uint256[3] memory input) external pure returns (uint256);
+}
+
+interface IPoseidon5 {
+    function poseidon(uint256[5] memory input) external pure returns (uint256);
+}
+
 contract IdentityManager is AccessControl {
     struct G1Point {
         uint256 X;
@@ -232,6 +241,14 @@ contract IdentityManager is AccessControl {
         G1Point[5] IC;
     }
 
+    struct VerifyingKey4 {
+        G1Point alfa1;
+        G2Point beta2;
+        G2Point gamma2;
+        G2Point delta2;
+        G1Point[4] IC;
+    }
+
     struct Proof {
         G1Point A;
         G2Point B;
@@ -308,6 +325,7 @@ contract IdentityManager is AccessControl {
 
     VerifyingKey8 public deriveVK;
     VerifyingKey5 public appkeyVK;
+    VerifyingKey4 public attributeSetVK;
 
     mapping(address => IdentityMeta) public identityInfo;
     mapping(uint256 => address) public identityAddress;
@@ -324,6 +342,19 @@ contract IdentityManager is AccessControl {
     mapping(uint256 => address) public committee;
     mapping(address => uint256) public committeeId;
     mapping(address => mapping(uint256 => uint256)) public appkeys;
+    // expiry of the pending challenges (ctx) of each app, whose id is its address
+    mapping(uint256 => mapping(uint256 => uint256)) public challengeExpiry;
+    // keccak256 of the commitment and the lrcm or scm of the verified presentations,
+    // which are bound to challenges, so that each user presents once per challenge
+    mapping(bytes32 => bool) public presented;
+    // hashes of the generators and schemas of the trusted CAs, see `ca_hash`
+    mapping(uint256 => bool) public trustedCAs;
+
+    IPoseidon3 public poseidon3;
+    IPoseidon5 public poseidon5;
+
+    // number of identity attributes of the identity proof
+    uint256 public constant attributeNum = 8;
 
     uint256 public numOfAddress;
     uint256 public numOfCommittee;
@@ -334,6 +365,12 @@ contract IdentityManager is AccessControl {
 
 
     constructor() {
+        // the verifying keys are written by `Committee::gen_contract` after the trusted
+        // setup, the contract is not deployed with the placeholder keys
+        require(
+            0 != 0 && 0 != 0 && 0 != 0,
+            "Missing verifying keys"
+        );
         _grantRole(DEFAULT_ADMIN_ROLE, msg.sender);
     }
     
@@ -356,6 +393,17 @@ contract IdentityManager is AccessControl {
         emit rootsUpdate(version, rh1, rh2);
     }
     
+    // Configure the Poseidon hashes used to recompute lrcm and scm.
+    function setPoseidon(address poseidon3_, address poseidon5_) external onlyRole(DEFAULT_ADMIN_ROLE) {
+        poseidon3 = IPoseidon3(poseidon3_);
+        poseidon5 = IPoseidon5(poseidon5_);
+    }
+
+    // Trust (or distrust) the CA with the given hash of generators and schema.
+    function setTrustedCA(uint256 caHash, bool trusted) external onlyRole(DEFAULT_ADMIN_ROLE) {
+        trustedCAs[caHash] = trusted;
+    }
+
     // set the threshold
     function setBaseNumber(uint256 num) external onlyRole(DEFAULT_ADMIN_ROLE) {
         baseNumber = num;
@@ -405,6 +453,17 @@ contract IdentityManager is AccessControl {
         }
     }
     
+    // set verification key used in set-membership and non-membership proofs
+    function setAttributeSetVK(VerifyingKey memory vk) external onlyRole(DEFAULT_ADMIN_ROLE) {
+        attributeSetVK.alfa1 = vk.alfa1;
+        attributeSetVK.beta2 = vk.beta2;
+        attributeSetVK.gamma2 = vk.gamma2;
+        attributeSetVK.delta2 = vk.delta2;
+        for (uint i = 0; i < vk.IC.length; ++i) {
+            attributeSetVK.IC[i] = vk.IC[i];
+        }
+    }
+
     // register a pseudonym
     function register(uint256[6] memory input, Proof memory proof) external {
         unchecked {
@@ -413,28 +472,28 @@ contract IdentityManager is AccessControl {
             require(input[5] == rootsHash1 || input[5] == rootsHash2, "invalid root");
 
             bool success;
-            uint256[23] memory ic = [5134660633593042833528719061393118200934776146823219850066318084226671004521, 
-                                    2267952479917080285641444598180230780028944522963408537447562438374065926314,
-                                    16407509247147829170196141982734887097229943562806400832080443077548094155758,
-                                    21596473358960641493638972487083159603153525942204706963721273625984982264259,
+            uint256[23] memory ic = [0, 
+                                    0,
+                                    0,
+                                    0,
                                     input[0],
-                                    6925175765785236950432228413894149495433938267345794109161445000316813397019,
-                                    9978092574929667375508427641215895800063704027577168759932197805619175578922,
+                                    0,
+                                    0,
                                     input[1],
-                                    11962465937233845977929587248665436890011299511766842400356310965882084745369,
-                                    11852795729143130906245893142164683297154503493128266529386606845786002795090,
+                                    0,
+                                    0,
                                     input[2],
-                                    7923608251787970531204788411020658796659787904994396956991822523952612974164,
-                                    18530733978805246092347566020785953317755478010272555080991234289771805922217,
+                                    0,
+                                    0,
                                     input[3],
-                                    5789407266548303026993138575911294527436021624687210030222316410853233583004,
-                                    19461761860828311688721409768021034472165726286827528692572295578999643941121,
+                                    0,
+                                    0,
                                     input[4],
-                                    113420956973449116039189466045708416412636738422786822404691558193761880485,
-                                    10931001532875784697790335823017150997085694863324556575508636835092439518676,
+                                    0,
+                                    0,
                                     input[5],
-                                    19213230504260099809941390966646840619093245128690369913102821461920973005336,
-                                    15043819801595154591063362955695378087285118325664561591513210509122893701695,
+                                    0,
+                                    0,
                                     uint256(uint160(msg.sender))];
 
             uint256[4] memory addInput = [ic[0], ic[1], 0, 0];
@@ -474,26 +533,26 @@ contract IdentityManager is AccessControl {
                 mstore(add(input_ptr, 128), mload(add(p, 192)))
                 mstore(add(input_ptr, 160), mload(add(p, 224)))
 
-                mstore(add(input_ptr, 192), 15004680706511599626910328362966724055512206210679380669568875056581533717585)
-                mstore(add(input_ptr, 224), 12793303693169677763646490080736798058449472703770947246638652750917306945672)
-                mstore(add(input_ptr, 256), 13097065549454820534121743293232923520157567628377343002556327061631672698839)
-                mstore(add(input_ptr, 288), 16895363494149120493723056270615717759070118594067567018265725986466550985361)
-                mstore(add(input_ptr, 320), 16486531148173516340100091740479150866101900379431723930312369778071641638548)
-                mstore(add(input_ptr, 352), 17511194961672245380296015188312502808479795239469992960128839962609136281142)
+                mstore(add(input_ptr, 192), 0)
+                mstore(add(input_ptr, 224), 0)
+                mstore(add(input_ptr, 256), 0)
+                mstore(add(input_ptr, 288), 0)
+                mstore(add(input_ptr, 320), 0)
+                mstore(add(input_ptr, 352), 0)
 
                 mstore(add(input_ptr, 384), mload(addInput))
                 mstore(add(input_ptr, 416), mload(add(addInput, 32)))
-                mstore(add(input_ptr, 448), 14777375175169188157832131510591300470451954576366387619180996124618844908687)
-                mstore(add(input_ptr, 480), 11981441507299507102470311465607116257748363010408937132648026575661830000097)
-                mstore(add(input_ptr, 512), 8355834854554503153303977692833755282736122888963222431148819254910459580129)
-                mstore(add(input_ptr, 544), 14374981404404253893489930945664891321128987089025408333474411970725811850577)
+                mstore(add(input_ptr, 448), 0)
+                mstore(add(input_ptr, 480), 0)
+                mstore(add(input_ptr, 512), 0)
+                mstore(add(input_ptr, 544), 0)
 
                 mstore(add(input_ptr, 576), mload(add(p, 256)))
                 mstore(add(input_ptr, 608), mload(add(p, 288)))
-                mstore(add(input_ptr, 640), 16205141538991564918569756141729310008779425547092684699822198443287220923422)
-                mstore(add(input_ptr, 672), 14999972388366711006205615781095694617429565602724419962838787097035899700495)
-                mstore(add(input_ptr, 704), 17269859523655542065343367422616573200895913101393606225638100595793707828721)
-                mstore(add(input_ptr, 736), 13138917696645637545721069837184928280359742958705300500475876763920128987116)
+                mstore(add(input_ptr, 640), 0)
+                mstore(add(input_ptr, 672), 0)
+                mstore(add(input_ptr, 704), 0)
+                mstore(add(input_ptr, 736), 0)
 
                 success := staticcall(
                     sub(gas(), 2000),
@@ -519,26 +578,26 @@ contract IdentityManager is AccessControl {
     }
     
     // verif non-sybil proof
-    function _verifyAppkey(address user, uint256 appkey, uint256 appid, Proof memory proof) private view returns (bool) {
+    function _verifyAppkey(address user, uint256 appkey, uint256 appid, uint256 ctx, Proof memory proof) private view returns (bool) {
         unchecked {
             IdentityMeta memory meta = identityInfo[user]; 
             
-            uint256 ss = (appid & 0x00ffffffffffffffffffffffffffffffffffffffff) + ((tpkePub.x & 1) << 160) + (((meta.ei >> 1) & 1) << 161);
+            uint256 ss = (appid & 0x00ffffffffffffffffffffffffffffffffffffffff) + ((tpkePub.x & 1) << 160) + (((meta.ei >> 1) & 1) << 161) + ((ctx & 0xffffffffffffffffffffff) << 162);
 
             bool success;
-            uint256[14] memory ic = [8681695336520126535119556549165341721423351312473992662099525921787530151585, 
-                                    12374319359237741558473010548892797514389872488972723436464920039814544356679,
-                                    13379349358787856777102530992872225424183700471726396385047031145537253120618,
-                                    18452089298715675211781302447207012819430427580814844745351214037454502820170,
+            uint256[14] memory ic = [0, 
+                                    0,
+                                    0,
+                                    0,
                                     appkey,
-                                    11326354145213689504308823610299014028168702771822166239178222991067410244321,
-                                    20740843430536100519679230788138585400402030838247720549740404770691261555584,
+                                    0,
+                                    0,
                                     ss,
-                                    3066323588738464520825717934203092400801812635638664141868679461516145223478,
-                                    3946948058574856131005170333627780776887033056551063128086399291198465356590,
+                                    0,
+                                    0,
                                     tpkePub.y,
-                                    10609616205342156538225677282455559079476313795998787927728371992524810519801,
-                                    4328252514927666684694372614903554151348173166365361362379403845001882363198,
+                                    0,
+                                    0,
                                     meta.C2];
  
             uint256[4] memory addInput = [ic[0], ic[1], 0, 0];
@@ -577,26 +636,26 @@ contract IdentityManager is AccessControl {
                 mstore(add(input_ptr, 128), mload(add(p, 192)))
                 mstore(add(input_ptr, 160), mload(add(p, 224)))
 
-                mstore(add(input_ptr, 192), 5206401496556851562670317152043658774341610659687039266022786915302561785775)
-                mstore(add(input_ptr, 224), 14024044104912601359321570025531668481012700754598402622596027436436951186030)
-                mstore(add(input_ptr, 256), 11587614424353515804126452619658501018799388867535989391657967304749651242094)
-                mstore(add(input_ptr, 288), 2278142985214772475755058964612504654991486960695249856499852466767482771700)
-                mstore(add(input_ptr, 320), 16423960841969181958300717999987700214876903485299997238946390133587982964526)
-                mstore(add(input_ptr, 352), 4194947490766889838262625241935850584032639549994838290238071714738619085191)
+                mstore(add(input_ptr, 192), 0)
+                mstore(add(input_ptr, 224), 0)
+                mstore(add(input_ptr, 256), 0)
+                mstore(add(input_ptr, 288), 0)
+                mstore(add(input_ptr, 320), 0)
+                mstore(add(input_ptr, 352), 0)
 
                 mstore(add(input_ptr, 384), mload(addInput))
                 mstore(add(input_ptr, 416), mload(add(addInput, 32)))
-                mstore(add(input_ptr, 448), 4422351400425110778272910363335403758636324961559845492787788451679573469610)
-                mstore(add(input_ptr, 480), 9262006424273923686784441527336999112784359539970941821252718359588993024577)
-                mstore(add(input_ptr, 512), 15316414987326097708688648720229009223342872797323168774985472769536620323853)
-                mstore(add(input_ptr, 544), 14808066007352459609647288910650046740826146516946968899415447127415389166479)
+                mstore(add(input_ptr, 448), 0)
+                mstore(add(input_ptr, 480), 0)
+                mstore(add(input_ptr, 512), 0)
+                mstore(add(input_ptr, 544), 0)
 
                 mstore(add(input_ptr, 576), mload(add(p, 256)))
                 mstore(add(input_ptr, 608), mload(add(p, 288)))
-                mstore(add(input_ptr, 640), 10852659904564012410202905745144849052505185111242142102989755104597444077299)
-                mstore(add(input_ptr, 672), 20914531760414482914325177373272580492176957530777621907979367234316410095242)
-                mstore(add(input_ptr, 704), 2326776246568318616235239342488121144967415672643713588789909725633667289456)
-                mstore(add(input_ptr, 736), 15672502731658008787285140340453255737078324222641564237988234198627619400790)
+                mstore(add(input_ptr, 640), 0)
+                mstore(add(input_ptr, 672), 0)
+                mstore(add(input_ptr, 704), 0)
+                mstore(add(input_ptr, 736), 0)
 
                 success := staticcall(
                     sub(gas(), 2000),
@@ -621,16 +680,16 @@ contract IdentityManager is AccessControl {
     function _verifyIdentity(uint256 Ax, uint256 Ay, uint256 lrcm, Proof memory proof) private view returns (bool) {
         unchecked {
             bool success;
-            uint256[11] memory ic = [21608667410711496812882370846235082219809050337084794157590759186282637585344, 
-                                    8384970959349601680244718450283120484320605195854625117378385026013772292021,
-                                    12930562548973283330975689962732440629491891663905338974653083221514519265377,
-                                    8541024393053639618711371261917675935279102263704358351483488059897820955759,
+            uint256[11] memory ic = [0, 
+                                    0,
+                                    0,
+                                    0,
                                     Ax,
-                                    10609360200435912527995129309664390933531648009707591003577368789200152017717,
-                                    14180162505845651140956990310649386789331886882569317105763202670045449480192,
+                                    0,
+                                    0,
                                     Ay,
-                                    16062641557297711677740458410901445049075987379407037317083259568574001699538,
-                                    11777838497844809403393627126455609270770258213121070946946596242813847185727,
+                                    0,
+                                    0,
                                     lrcm];
  
             uint256[4] memory addInput = [ic[0], ic[1], 0, 0];
@@ -669,26 +728,26 @@ contract IdentityManager is AccessControl {
                 mstore(add(input_ptr, 128), mload(add(p, 192)))
                 mstore(add(input_ptr, 160), mload(add(p, 224)))
 
-                mstore(add(input_ptr, 192), 12384001975923688725549742349895603667455215160914868304700754701164505642909)
-                mstore(add(input_ptr, 224), 5975026680599206326232695526971437334542177145566564851398527296992076315415)
-                mstore(add(input_ptr, 256), 13944611227234014573879338971726596196482551298890971838068705721028877203212)
-                mstore(add(input_ptr, 288), 4409934194387708543469033564486655569509854572986772143714411625202809918632)
-                mstore(add(input_ptr, 320), 11527400980324097681578115660111103028502187793758840400974727595583940835735)
-                mstore(add(input_ptr, 352), 14351083625812150963808759664503111953747850513047595535267643152793025353683)
+                mstore(add(input_ptr, 192), 0)
+                mstore(add(input_ptr, 224), 0)
+                mstore(add(input_ptr, 256), 0)
+                mstore(add(input_ptr, 288), 0)
+                mstore(add(input_ptr, 320), 0)
+                mstore(add(input_ptr, 352), 0)
 
                 mstore(add(input_ptr, 384), mload(addInput))
                 mstore(add(input_ptr, 416), mload(add(addInput, 32)))
-                mstore(add(input_ptr, 448), 11941059669465163061853561469820256147437355156173567052685494753916367262469)
-                mstore(add(input_ptr, 480), 16035318089773333271556401822323971304811351096893027030165750226014290706105)
-                mstore(add(input_ptr, 512), 18387868787256835186818573036147744300492166721630369292388153019641797323074)
-                mstore(add(input_ptr, 544), 14073326839883679256308089936028750274974042315401580191367769290002278472958)
+                mstore(add(input_ptr, 448), 0)
+                mstore(add(input_ptr, 480), 0)
+                mstore(add(input_ptr, 512), 0)
+                mstore(add(input_ptr, 544), 0)
 
                 mstore(add(input_ptr, 576), mload(add(p, 256)))
                 mstore(add(input_ptr, 608), mload(add(p, 288)))
-                mstore(add(input_ptr, 640), 19708058406394478327149338044055579191693306402236207449279510091971281200620)
-                mstore(add(input_ptr, 672), 5240669643643026443632390885206189492435129263715881989848944311893357519426)
-                mstore(add(input_ptr, 704), 19413083123968390435388334866014389827628549280403785341773303949986021491035)
-                mstore(add(input_ptr, 736), 3808730047386445717105598783484442401127247732841039383203424346271425442004)
+                mstore(add(input_ptr, 640), 0)
+                mstore(add(input_ptr, 672), 0)
+                mstore(add(input_ptr, 704), 0)
+                mstore(add(input_ptr, 736), 0)
 
                 success := staticcall(
                     sub(gas(), 2000),
@@ -709,21 +768,74 @@ contract IdentityManager is AccessControl {
         }
     }
     
-    // Verify the identity assertion.
-    function verifyIdentity(uint256 Ax, uint256 Ay, uint256 lrcm, Proof memory proof) external {
+    // Verify the identity assertion. lrcm is recomputed from the ranges, the CA and
+    // the challenge, so that only credentials of a trusted CA are accepted.
+    function verifyIdentity(uint256 Ax, uint256 Ay, uint256 rangeHash, uint256 caHash, uint256 ctx, Proof memory proof) external {
+        require(trustedCAs[caHash], "Untrusted CA!");
+        uint256 lrcm = poseidon3.poseidon([rangeHash, caHash, ctx]);
+        bytes32 key = keccak256(abi.encode(Ax, Ay, lrcm));
+        require(!presented[key], "Replayed Proof!");
         require(_verifyIdentity(Ax, Ay, lrcm, proof), "Invalid Proof!");
+        presented[key] = true;
         emit IdentityVerified(Ax, Ay, lrcm);
     }
     
+    // verify set-membership or non-membership proof
+    function _verifyAttributeSet(uint256 Ax, uint256 Ay, uint256 scm, Proof memory proof) private view returns (bool) {
+        uint256 snark_scalar_field = 21888242871839275222246405745257275088548364400416034343698204186575808495617;
+        uint256[3] memory input = [Ax, Ay, scm];
+        G1Point memory vk_x = attributeSetVK.IC[0];
+        for (uint256 i = 0; i < 3; i++) {
+            require(input[i] < snark_scalar_field, "verifier-gte-snark-scalar-field");
+            vk_x = addition(vk_x, scalar_mul(attributeSetVK.IC[i + 1], input[i]));
+        }
+        return pairingProd4(
+            negate(proof.A),
+            proof.B,
+            attributeSetVK.alfa1,
+            attributeSetVK.beta2,
+            vk_x,
+            attributeSetVK.gamma2,
+            proof.C,
+            attributeSetVK.delta2
+        );
+    }
+
+    // Verify that an attribute is (or is not) in a set. scm is recomputed from the root
+    // of the set, the attribute, the CA and the challenge, so that only credentials of a
+    // trusted CA are accepted.
+    function verifyAttributeSet(uint256 Ax, uint256 Ay, uint256 root, uint256 index, uint256 member, uint256 caHash, uint256 ctx, Proof memory proof) external {
+        require(trustedCAs[caHash], "Untrusted CA!");
+        uint256 scm = poseidon5.poseidon([root, index, member, caHash, ctx]);
+        bytes32 key = keccak256(abi.encode(Ax, Ay, scm));
+        require(!presented[key], "Replayed Proof!");
+        require(_verifyAttributeSet(Ax, Ay, scm, proof), "Invalid Proof!");
+        presented[key] = true;
+        emit AttributeSetVerified(Ax, Ay, scm);
+    }
+
     // verify non-sybil proof
-    function verifyAppkey(address user, uint256 appkey, uint256 appid, Proof memory proof) external view returns (bool) {
-        return _verifyAppkey(user, appkey, appid, proof);
+    function verifyAppkey(address user, uint256 appkey, uint256 appid, uint256 ctx, Proof memory proof) external view returns (bool) {
+        return _verifyAppkey(user, appkey, appid, ctx, proof);
     }
     
-    // do sybil-resistance
-    function setAppkey(uint256 user, uint256 appkey, uint256 appid, Proof memory proof) external {
+    // Issue a challenge of the calling app for sybil resistance, accepted once by
+    // setAppkey until it expires.
+    function issueChallenge(uint256 ctx, uint256 expiry) external {
+        uint256 appid = uint256(uint160(msg.sender));
+        require(ctx != 0 && expiry >= block.timestamp, "Invalid Challenge!");
+        require(challengeExpiry[appid][ctx] == 0, "Issued Challenge!");
+        challengeExpiry[appid][ctx] = expiry;
+        emit ChallengeIssued(appid, ctx, expiry);
+    }
+
+    // do sybil-resistance, the proof must be bound to a pending challenge of the app,
+    // which is consumed
+    function setAppkey(uint256 user, uint256 appkey, uint256 appid, uint256 ctx, Proof memory proof) external {
         unchecked {
-            require(_verifyAppkey(address(uint160(user)), appkey, appid, proof), "Invalid Proof!");
+            require(challengeExpiry[appid][ctx] >= block.timestamp, "Invalid Challenge!");
+            require(_verifyAppkey(address(uint160(user)), appkey, appid, ctx, proof), "Invalid Proof!");
+            delete challengeExpiry[appid][ctx];
             // appkeys[address(uint160(user))][appid] = appkey;
             emit AppkeySet(address(uint160(user)), appid, appkey);
         }
@@ -739,9 +851,11 @@ contract IdentityManager is AccessControl {
     }
 
     event AppkeySet(address indexed user, uint256 appid, uint256 appkey);
+    event ChallengeIssued(uint256 indexed appid, uint256 ctx, uint256 expiry);
     event UserRegister(address indexed user, IdentityFullMeta meta);
     event UserMarked(uint256 indexed c1y, address user);
     event rootsUpdate(uint256 version, uint256 root1, uint256 root2);
     event AddressRevoke(address indexed user);
     event IdentityVerified(uint256 indexed Ax, uint256 indexed Ay, uint256 lrcm);
+    event AttributeSetVerified(uint256 indexed Ax, uint256 indexed Ay, uint256 scm);
 }
@@ -0,0 +1,861 @@
+// This file is the gas-optimized version of the identity management contract.
+
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.6;
+pragma abicoder v2;
+
+import "@openzeppelin/contracts/access/AccessControl.sol";
+
+// Poseidon hash, e.g. the contract generated by poseidon_gencontract of circomlibjs.
+interface IPoseidon3 {
+    function poseidon(uint256[3] memory input) external pure returns (uint256);
+}
+
+interface IPoseidon5 {
+    function poseidon(uint256[5] memory input) external pure returns (uint256);
+}
+
+contract IdentityManager is AccessControl {
+    struct G1Point {
+        uint256 X;
+        uint256 Y;
+    }
+    // Encoding of field elements is: X[0] * z + X[1]
+    struct G2Point {
+        uint256[2] X;
+        uint256[2] Y;
+    }
+
+    /// @return the generator of G1
+    function P1() internal pure returns (G1Point memory) {
+        return G1Point(1, 2);
+    }
+
+    /// @return the generator of G2
+    function P2() internal pure returns (G2Point memory) {
+        // Original code point
+        return
+            G2Point(
+                [
+                    11559732032986387107991004021392285783925812861821192530917403151452391805634,
+                    10857046999023057135944570762232829481370756359578518086990519993285655852781
+                ],
+                [
+                    4082367875863433681332203403145435568316851327593401208105741076214120093531,
+                    8495653923123431417604973247489272438418190587263600148770280649306958101930
+                ]
+            );
+
+        /*
+        // Changed by Jordi point
+        return G2Point(
+            [10857046999023057135944570762232829481370756359578518086990519993285655852781,
+             11559732032986387107991004021392285783925812861821192530917403151452391805634],
+            [8495653923123431417604973247489272438418190587263600148770280649306958101930,
+             4082367875863433681332203403145435568316851327593401208105741076214120093531]
+        );
+*/
+    }
+
+    /// @return r the negation of p, i.e. p.addition(p.negate()) should be zero.
+    function negate(G1Point memory p) internal pure returns (G1Point memory r) {
+        // The prime q in the base field F_q for G1
+            uint256 q
+         = 21888242871839275222246405745257275088696311157297823662689037894645226208583;
+        if (p.X == 0 && p.Y == 0) return G1Point(0, 0);
+        return G1Point(p.X, q - (p.Y % q));
+    }
+
+    /// @return r the sum of two points of G1
+    function addition(G1Point memory p1, G1Point memory p2)
+        internal
+        view
+        returns (G1Point memory r)
+    {
+        uint256[4] memory input;
+        input[0] = p1.X;
+        input[1] = p1.Y;
+        input[2] = p2.X;
+        input[3] = p2.Y;
+        bool success;
+        // solium-disable-next-line security/no-inline-assembly
+        assembly {
+            success := staticcall(sub(gas(), 2000), 6, input, 0xc0, r, 0x60)
+            // Use "invalid" to make gas estimation work
+            switch success
+                case 0 {
+                    invalid()
+                }
+        }
+        require(success, "pairing-add-failed");
+    }
+
+    /// @return r the product of a point on G1 and a scalar, i.e.
+    /// p == p.scalar_mul(1) and p.addition(p) == p.scalar_mul(2) for all points p.
+    function scalar_mul(G1Point memory p, uint256 s)
+        internal
+        view
+        returns (G1Point memory r)
+    {
+        uint256[3] memory input;
+        input[0] = p.X;
+        input[1] = p.Y;
+        input[2] = s;
+        bool success;
+        // solium-disable-next-line security/no-inline-assembly
+        assembly {
+            success := staticcall(sub(gas(), 2000), 7, input, 0x80, r, 0x60)
+            // Use "invalid" to make gas estimation work
+            switch success
+                case 0 {
+                    invalid()
+                }
+        }
+        require(success, "pairing-mul-failed");
+    }
+
+    /// @return the result of computing the pairing check
+    /// e(p1[0], p2[0]) *  .... * e(p1[n], p2[n]) == 1
+    /// For example pairing([P1(), P1().negate()], [P2(), P2()]) should
+    /// return true.
+    function pairing(G1Point[] memory p1, G2Point[] memory p2)
+        internal
+        view
+        returns (bool)
+    {
+        require(p1.length == p2.length, "pairing-lengths-failed");
+        uint256 elements = p1.length;
+        uint256 inputSize = elements * 6;
+        uint256[] memory input = new uint256[](inputSize);
+        for (uint256 i = 0; i < elements; i++) {
+            input[i * 6 + 0] = p1[i].X;
+            input[i * 6 + 1] = p1[i].Y;
+            input[i * 6 + 2] = p2[i].X[0];
+            input[i * 6 + 3] = p2[i].X[1];
+            input[i * 6 + 4] = p2[i].Y[0];
+            input[i * 6 + 5] = p2[i].Y[1];
+        }
+        uint256[1] memory out;
+        bool success;
+        // solium-disable-next-line security/no-inline-assembly
+        assembly {
+            success := staticcall(
+                sub(gas(), 2000),
+                8,
+                add(input, 0x20),
+                mul(inputSize, 0x20),
+                out,
+                0x20
+            )
+            // Use "invalid" to make gas estimation work
+            switch success
+                case 0 {
+                    invalid()
+                }
+        }
+        require(success, "pairing-opcode-failed");
+        return out[0] != 0;
+    }
+
+    /// Convenience method for a pairing check for two pairs.
+    function pairingProd2(
+        G1Point memory a1,
+        G2Point memory a2,
+        G1Point memory b1,
+        G2Point memory b2
+    ) internal view returns (bool) {
+        G1Point[] memory p1 = new G1Point[](2);
+        G2Point[] memory p2 = new G2Point[](2);
+        p1[0] = a1;
+        p1[1] = b1;
+        p2[0] = a2;
+        p2[1] = b2;
+        return pairing(p1, p2);
+    }
+
+    /// Convenience method for a pairing check for three pairs.
+    function pairingProd3(
+        G1Point memory a1,
+        G2Point memory a2,
+        G1Point memory b1,
+        G2Point memory b2,
+        G1Point memory c1,
+        G2Point memory c2
+    ) internal view returns (bool) {
+        G1Point[] memory p1 = new G1Point[](3);
+        G2Point[] memory p2 = new G2Point[](3);
+        p1[0] = a1;
+        p1[1] = b1;
+        p1[2] = c1;
+        p2[0] = a2;
+        p2[1] = b2;
+        p2[2] = c2;
+        return pairing(p1, p2);
+    }
+
+    /// Convenience method for a pairing check for four pairs.
+    function pairingProd4(
+        G1Point memory a1,
+        G2Point memory a2,
+        G1Point memory b1,
+        G2Point memory b2,
+        G1Point memory c1,
+        G2Point memory c2,
+        G1Point memory d1,
+        G2Point memory d2
+    ) internal view returns (bool) {
+        G1Point[] memory p1 = new G1Point[](4);
+        G2Point[] memory p2 = new G2Point[](4);
+        p1[0] = a1;
+        p1[1] = b1;
+        p1[2] = c1;
+        p1[3] = d1;
+        p2[0] = a2;
+        p2[1] = b2;
+        p2[2] = c2;
+        p2[3] = d2;
+        return pairing(p1, p2);
+    }
+
+    struct VerifyingKey {
+        G1Point alfa1;
+        G2Point beta2;
+        G2Point gamma2;
+        G2Point delta2;
+        G1Point[] IC;
+    }
+
+    struct VerifyingKey8 {
+        G1Point alfa1;
+        G2Point beta2;
+        G2Point gamma2;
+        G2Point delta2;
+        G1Point[8] IC;
+    }
+
+    struct VerifyingKey5 {
+        G1Point alfa1;
+        G2Point beta2;
+        G2Point gamma2;
+        G2Point delta2;
+        G1Point[5] IC;
+    }
+
+    struct VerifyingKey4 {
+        G1Point alfa1;
+        G2Point beta2;
+        G2Point gamma2;
+        G2Point delta2;
+        G1Point[4] IC;
+    }
+
+    struct Proof {
+        G1Point A;
+        G2Point B;
+        G1Point C;
+    }
+
+    function verify(
+        uint256[] memory input,
+        Proof memory proof,
+        VerifyingKey5 memory vk
+    ) internal view returns (uint256) {
+        uint256 snark_scalar_field = 21888242871839275222246405745257275088548364400416034343698204186575808495617;
+        require(input.length + 1 == 5, "verifier-bad-input");
+        // Compute the linear combination vk_x
+        G1Point memory vk_x =  vk.IC[0];
+        for (uint256 i = 0; i < 4; i++) {
+            require(input[i] < snark_scalar_field, "verifier-gte-snark-scalar-field");
+            vk_x = addition(vk_x, scalar_mul(vk.IC[i + 1], input[i]));
+        }
+        // vk_x = addition(vk_x,);
+        if (
+            !pairingProd4(
+                negate(proof.A),
+                proof.B,
+                vk.alfa1,
+                vk.beta2,
+                vk_x,
+                vk.gamma2,
+                proof.C,
+                vk.delta2
+            )
+        ) return 1;
+        return 0;
+    }
+
+    function verifyProof(
+        uint256[2] memory a,
+        uint256[2][2] memory b,
+        uint256[2] memory c,
+        uint256[] memory input,
+        VerifyingKey5 memory vk
+    ) internal view returns (bool) {
+        Proof memory proof;
+        proof.A = G1Point(a[0], a[1]);
+        proof.B = G2Point([b[0][0], b[0][1]], [b[1][0], b[1][1]]);
+        proof.C = G1Point(c[0], c[1]);
+        if (verify(input, proof, vk) == 0) {
+            return true;
+        } else {
+            return false;
+        }
+    }
+
+    struct BabyPoint {
+        uint256 x;
+        uint256 y;
+    }
+
+    struct IdentityMeta {
+        uint256 A;
+        uint256 C2;
+        // ei(64) | Ys(1) | Ais(1) | C3s(1) | C2s(1) | C1s(1)
+        uint256 ei;
+    }
+
+    struct IdentityFullMeta {
+        uint256 A;
+        uint256 C1;
+        uint256 C2;
+        uint256 C3;
+        // ei(64) | Ys(1) | Ais(1) | C3s(1) | C2s(1) | C1s(1)
+        uint256 ei;
+    }
+
+    VerifyingKey8 public deriveVK;
+    VerifyingKey5 public appkeyVK;
+    VerifyingKey4 public attributeSetVK;
+
+    mapping(address => IdentityMeta) public identityInfo;
+    mapping(uint256 => address) public identityAddress;
+
+    // pendingRevokeRootNum[version][root1][root2] = number
+    mapping(uint256 => mapping(uint256 => mapping(uint256 => uint256))) public pendingRootsNum;
+    // revokeRootApprovers[version][root1][root2][address] = 1|0
+    mapping(uint256 => mapping(uint256 => mapping(uint256 => mapping(address => uint256)))) public rootsApprovers;
+
+    uint256 public rootsHash1;
+    uint256 public rootsHash2;
+    uint256 public rootsVersion;
+
+    mapping(uint256 => address) public committee;
+    mapping(address => uint256) public committeeId;
+    mapping(address => mapping(uint256 => uint256)) public appkeys;
+    // expiry of the pending challenges (ctx) of each app, whose id is its address
+    mapping(uint256 => mapping(uint256 => uint256)) public challengeExpiry;
+    // keccak256 of the commitment and the lrcm or scm of the verified presentations,
+    // which are bound to challenges, so that each user presents once per challenge
+    mapping(bytes32 => bool) public presented;
+    // hashes of the generators and schemas of the trusted CAs, see `ca_hash`
+    mapping(uint256 => bool) public trustedCAs;
+
+    IPoseidon3 public poseidon3;
+    IPoseidon5 public poseidon5;
+
+    // number of identity attributes of the identity proof
+    uint256 public constant attributeNum = {{ATTRIBUTE_NUM}};
+
+    uint256 public numOfAddress;
+    uint256 public numOfCommittee;
+    uint256 public baseNumber;
+
+    BabyPoint public tpkePub;
+    
+
+
+    constructor() {
+        // the verifying keys are written by `Committee::gen_contract` after the trusted
+        // setup, the contract is not deployed with the placeholder keys
+        require(
+            {{DERIVE_ALPHA_X}} != 0 && {{APP_ALPHA_X}} != 0 && {{IDENTITY_ALPHA_X}} != 0,
+            "Missing verifying keys"
+        );
+        _grantRole(DEFAULT_ADMIN_ROLE, msg.sender);
+    }
+    
+    // Configure the public key used in threshold public key encryption.
+    function setTpkePub(BabyPoint memory key) external onlyRole(DEFAULT_ADMIN_ROLE) {
+        tpkePub = key;
+    }
+    
+    // update root
+    function updateRootsHash(uint256 rh1, uint256 rh2, uint256 version) external {
+        require(committeeId[msg.sender] != 0, "not in committee");
+        require(rootsApprovers[version][rh1][rh2][msg.sender] == 0, "already approve");
+        rootsApprovers[version][rh1][rh2][msg.sender] = 1;
+        pendingRootsNum[version][rh1][rh2] += 1;
+        if (pendingRootsNum[version][rh1][rh2] >= baseNumber && version >= rootsVersion) {
+            rootsHash1 = rh1;
+            rootsHash2 = rh2;
+            rootsVersion = version;
+        }
+        emit rootsUpdate(version, rh1, rh2);
+    }
+    
+    // Configure the Poseidon hashes used to recompute lrcm and scm.
+    function setPoseidon(address poseidon3_, address poseidon5_) external onlyRole(DEFAULT_ADMIN_ROLE) {
+        poseidon3 = IPoseidon3(poseidon3_);
+        poseidon5 = IPoseidon5(poseidon5_);
+    }
+
+    // Trust (or distrust) the CA with the given hash of generators and schema.
+    function setTrustedCA(uint256 caHash, bool trusted) external onlyRole(DEFAULT_ADMIN_ROLE) {
+        trustedCAs[caHash] = trusted;
+    }
+
+    // set the threshold
+    function setBaseNumber(uint256 num) external onlyRole(DEFAULT_ADMIN_ROLE) {
+        baseNumber = num;
+    }
+    
+    // add committee member
+    function addCommittee(uint256 _cm) external onlyRole(DEFAULT_ADMIN_ROLE) {
+        address cm = address(uint160(_cm));
+        require(committeeId[cm] == 0, "already add");
+        numOfCommittee += 1;
+        committee[numOfCommittee] = cm;
+        
+        committeeId[cm] = numOfCommittee;
+    }
+    
+    // remove committee member
+    function removeCommittee(address cm) external onlyRole(DEFAULT_ADMIN_ROLE) {
+        require(committeeId[cm] != 0, "not in committee");
+        uint256 id = committeeId[cm];
+        committee[id] = committee[numOfCommittee];
+        committeeId[committee[numOfCommittee]] = id;
+        committee[numOfCommittee] = address(0);
+        numOfCommittee -= 1;
+        committeeId[cm] = 0;
+    }
+    
+    // set verification key used in pseudonym verification
+    function setDeriveVK(VerifyingKey memory vk) external onlyRole(DEFAULT_ADMIN_ROLE) {
+        deriveVK.alfa1 = vk.alfa1;
+        deriveVK.beta2 = vk.beta2;
+        deriveVK.gamma2 = vk.gamma2;
+        deriveVK.delta2 = vk.delta2;
+
+        for (uint i = 0; i < vk.IC.length; ++i) {
+            deriveVK.IC[i] = vk.IC[i];
+        }
+    }
+
+    // set verification key used in sybil-resistance
+    function setAppkeyVK(VerifyingKey memory vk) external onlyRole(DEFAULT_ADMIN_ROLE) {
+        appkeyVK.alfa1 = vk.alfa1;
+        appkeyVK.beta2 = vk.beta2;
+        appkeyVK.gamma2 = vk.gamma2;
+        appkeyVK.delta2 = vk.delta2;
+        for (uint i = 0; i < vk.IC.length; ++i) {
+            appkeyVK.IC[i] = vk.IC[i];
+        }
+    }
+    
+    // set verification key used in set-membership and non-membership proofs
+    function setAttributeSetVK(VerifyingKey memory vk) external onlyRole(DEFAULT_ADMIN_ROLE) {
+        attributeSetVK.alfa1 = vk.alfa1;
+        attributeSetVK.beta2 = vk.beta2;
+        attributeSetVK.gamma2 = vk.gamma2;
+        attributeSetVK.delta2 = vk.delta2;
+        for (uint i = 0; i < vk.IC.length; ++i) {
+            attributeSetVK.IC[i] = vk.IC[i];
+        }
+    }
+
+    // register a pseudonym
+    function register(uint256[6] memory input, Proof memory proof) external {
+        unchecked {
+            require(input.length == 6, "invalid input size");
+            require((input[4] >> 69) <= numOfAddress, "invalid number");
+            require(input[5] == rootsHash1 || input[5] == rootsHash2, "invalid root");
+
+            bool success;
+            uint256[23] memory ic = [{{DERIVE_IC_0_X}}, 
+                                    {{DERIVE_IC_0_Y}},
+                                    {{DERIVE_IC_1_X}},
+                                    {{DERIVE_IC_1_Y}},
+                                    input[0],
+                                    {{DERIVE_IC_2_X}},
+                                    {{DERIVE_IC_2_Y}},
+                                    input[1],
+                                    {{DERIVE_IC_3_X}},
+                                    {{DERIVE_IC_3_Y}},
+                                    input[2],
+                                    {{DERIVE_IC_4_X}},
+                                    {{DERIVE_IC_4_Y}},
+                                    input[3],
+                                    {{DERIVE_IC_5_X}},
+                                    {{DERIVE_IC_5_Y}},
+                                    input[4],
+                                    {{DERIVE_IC_6_X}},
+                                    {{DERIVE_IC_6_Y}},
+                                    input[5],
+                                    {{DERIVE_IC_7_X}},
+                                    {{DERIVE_IC_7_Y}},
+                                    uint256(uint160(msg.sender))];
+
+            uint256[4] memory addInput = [ic[0], ic[1], 0, 0];
+            
+            for (uint256 i = 2; i < 23; i += 3) {
+                // solium-disable-next-line security/no-inline-assembly
+                assembly {
+                    // mul
+                    success := staticcall(sub(gas(), 2000), 7, add(ic, shl(5, i)), 0x80, add(addInput, 64), 0x60)
+                    // Use "invalid" to make gas estimation work
+                    switch success
+                        case 0 {
+                            invalid()
+                        }
+                    // add
+                    success := staticcall(sub(gas(), 2000), 6, addInput, 0xc0, addInput, 0x60)
+                    // Use "invalid" to make gas estimation work
+                    switch success
+                        case 0 {
+                            invalid()
+                        }
+                }
+            }
+
+            G1Point memory nA = negate(proof.A);
+
+            uint256[1] memory out;
+            // verify proof
+            // solium-disable-next-line security/no-inline-assembly
+            assembly {
+                let input_ptr := mload(0x40)
+                let p := mload(proof)
+                mstore(input_ptr, mload(nA))
+                mstore(add(input_ptr, 32), mload(add(nA, 32)))
+                mstore(add(input_ptr, 64), mload(add(p, 128)))
+                mstore(add(input_ptr, 96), mload(add(p, 160)))
+                mstore(add(input_ptr, 128), mload(add(p, 192)))
+                mstore(add(input_ptr, 160), mload(add(p, 224)))
+
+                mstore(add(input_ptr, 192), {{DERIVE_ALPHA_X}})
+                mstore(add(input_ptr, 224), {{DERIVE_ALPHA_Y}})
+                mstore(add(input_ptr, 256), {{DERIVE_BETA_X1}})
+                mstore(add(input_ptr, 288), {{DERIVE_BETA_X0}})
+                mstore(add(input_ptr, 320), {{DERIVE_BETA_Y1}})
+                mstore(add(input_ptr, 352), {{DERIVE_BETA_Y0}})
+
+                mstore(add(input_ptr, 384), mload(addInput))
+                mstore(add(input_ptr, 416), mload(add(addInput, 32)))
+                mstore(add(input_ptr, 448), {{DERIVE_GAMMA_X1}})
+                mstore(add(input_ptr, 480), {{DERIVE_GAMMA_X0}})
+                mstore(add(input_ptr, 512), {{DERIVE_GAMMA_Y1}})
+                mstore(add(input_ptr, 544), {{DERIVE_GAMMA_Y0}})
+
+                mstore(add(input_ptr, 576), mload(add(p, 256)))
+                mstore(add(input_ptr, 608), mload(add(p, 288)))
+                mstore(add(input_ptr, 640), {{DERIVE_DELTA_X1}})
+                mstore(add(input_ptr, 672), {{DERIVE_DELTA_X0}})
+                mstore(add(input_ptr, 704), {{DERIVE_DELTA_Y1}})
+                mstore(add(input_ptr, 736), {{DERIVE_DELTA_Y0}})
+
+                success := staticcall(
+                    sub(gas(), 2000),
+                    8,
+                    input_ptr,
+                    768,
+                    out,
+                    0x20
+                )
+                // Use "invalid" to make gas estimation work
+                switch success
+                    case 0 {
+                        invalid()
+                    }
+            }
+
+            require(out[0] != 0, "invalid proof");
+            numOfAddress += 1;
+            identityInfo[msg.sender] = IdentityMeta(input[3], input[1], (input[4] & 0x1fffffffffffffffff));
+            emit UserRegister(msg.sender, IdentityFullMeta(input[3], input[0], input[1], input[2], (input[4] & 0x1fffffffffffffffff)));
+            emit UserMarked(input[0], msg.sender);
+        }
+    }
+    
+    // verif non-sybil proof
+    function _verifyAppkey(address user, uint256 appkey, uint256 appid, uint256 ctx, Proof memory proof) private view returns (bool) {
+        unchecked {
+            IdentityMeta memory meta = identityInfo[user]; 
+            
+            uint256 ss = (appid & 0x00ffffffffffffffffffffffffffffffffffffffff) + ((tpkePub.x & 1) << 160) + (((meta.ei >> 1) & 1) << 161) + ((ctx & 0xffffffffffffffffffffff) << 162);
+
+            bool success;
+            uint256[14] memory ic = [{{APP_IC_0_X}}, 
+                                    {{APP_IC_0_Y}},
+                                    {{APP_IC_1_X}},
+                                    {{APP_IC_1_Y}},
+                                    appkey,
+                                    {{APP_IC_2_X}},
+                                    {{APP_IC_2_Y}},
+                                    ss,
+                                    {{APP_IC_3_X}},
+                                    {{APP_IC_3_Y}},
+                                    tpkePub.y,
+                                    {{APP_IC_4_X}},
+                                    {{APP_IC_4_Y}},
+                                    meta.C2];
+ 
+            uint256[4] memory addInput = [ic[0], ic[1], 0, 0];
+
+            for (uint256 i = 2; i < 14; i += 3) {
+                // solium-disable-next-line security/no-inline-assembly
+                assembly {
+                    // mul
+                    success := staticcall(sub(gas(), 2000), 7, add(ic, shl(5, i)), 0x80, add(addInput, 64), 0x60)
+                    // Use "invalid" to make gas estimation work
+                    switch success
+                        case 0 {
+                            invalid()
+                        }
+                    // add
+                    success := staticcall(sub(gas(), 2000), 6, addInput, 0xc0, addInput, 0x60)
+                    // Use "invalid" to make gas estimation work
+                    switch success
+                        case 0 {
+                            invalid()
+                        }
+                }
+            }
+
+            G1Point memory nA = negate(proof.A);
+
+            uint256[1] memory out;
+            // solium-disable-next-line security/no-inline-assembly
+            assembly {
+                let input_ptr := mload(0x40)
+                let p := mload(proof)
+                mstore(input_ptr, mload(nA))
+                mstore(add(input_ptr, 32), mload(add(nA, 32)))
+                mstore(add(input_ptr, 64), mload(add(p, 128)))
+                mstore(add(input_ptr, 96), mload(add(p, 160)))
+                mstore(add(input_ptr, 128), mload(add(p, 192)))
+                mstore(add(input_ptr, 160), mload(add(p, 224)))
+
+                mstore(add(input_ptr, 192), {{APP_ALPHA_X}})
+                mstore(add(input_ptr, 224), {{APP_ALPHA_Y}})
+                mstore(add(input_ptr, 256), {{APP_BETA_X1}})
+                mstore(add(input_ptr, 288), {{APP_BETA_X0}})
+                mstore(add(input_ptr, 320), {{APP_BETA_Y1}})
+                mstore(add(input_ptr, 352), {{APP_BETA_Y0}})
+
+                mstore(add(input_ptr, 384), mload(addInput))
+                mstore(add(input_ptr, 416), mload(add(addInput, 32)))
+                mstore(add(input_ptr, 448), {{APP_GAMMA_X1}})
+                mstore(add(input_ptr, 480), {{APP_GAMMA_X0}})
+                mstore(add(input_ptr, 512), {{APP_GAMMA_Y1}})
+                mstore(add(input_ptr, 544), {{APP_GAMMA_Y0}})
+
+                mstore(add(input_ptr, 576), mload(add(p, 256)))
+                mstore(add(input_ptr, 608), mload(add(p, 288)))
+                mstore(add(input_ptr, 640), {{APP_DELTA_X1}})
+                mstore(add(input_ptr, 672), {{APP_DELTA_X0}})
+                mstore(add(input_ptr, 704), {{APP_DELTA_Y1}})
+                mstore(add(input_ptr, 736), {{APP_DELTA_Y0}})
+
+                success := staticcall(
+                    sub(gas(), 2000),
+                    8,
+                    input_ptr,
+                    768,
+                    out,
+                    0x20
+                )
+                // Use "invalid" to make gas estimation work
+                switch success
+                    case 0 {
+                        invalid()
+                    }
+            }
+
+            return out[0] != 0;
+        }
+    }
+    
+    // verify identity proof
+    function _verifyIdentity(uint256 Ax, uint256 Ay, uint256 lrcm, Proof memory proof) private view returns (bool) {
+        unchecked {
+            bool success;
+            uint256[11] memory ic = [{{IDENTITY_IC_0_X}}, 
+                                    {{IDENTITY_IC_0_Y}},
+                                    {{IDENTITY_IC_1_X}},
+                                    {{IDENTITY_IC_1_Y}},
+                                    Ax,
+                                    {{IDENTITY_IC_2_X}},
+                                    {{IDENTITY_IC_2_Y}},
+                                    Ay,
+                                    {{IDENTITY_IC_3_X}},
+                                    {{IDENTITY_IC_3_Y}},
+                                    lrcm];
+ 
+            uint256[4] memory addInput = [ic[0], ic[1], 0, 0];
+
+            for (uint256 i = 2; i < 11; i += 3) {
+                // solium-disable-next-line security/no-inline-assembly
+                assembly {
+                    // mul
+                    success := staticcall(sub(gas(), 2000), 7, add(ic, shl(5, i)), 0x80, add(addInput, 64), 0x60)
+                    // Use "invalid" to make gas estimation work
+                    switch success
+                        case 0 {
+                            invalid()
+                        }
+                    // add
+                    success := staticcall(sub(gas(), 2000), 6, addInput, 0xc0, addInput, 0x60)
+                    // Use "invalid" to make gas estimation work
+                    switch success
+                        case 0 {
+                            invalid()
+                        }
+                }
+            }
+
+            G1Point memory nA = negate(proof.A);
+
+            uint256[1] memory out;
+            // solium-disable-next-line security/no-inline-assembly
+            assembly {
+                let input_ptr := mload(0x40)
+                let p := mload(proof)
+                mstore(input_ptr, mload(nA))
+                mstore(add(input_ptr, 32), mload(add(nA, 32)))
+                mstore(add(input_ptr, 64), mload(add(p, 128)))
+                mstore(add(input_ptr, 96), mload(add(p, 160)))
+                mstore(add(input_ptr, 128), mload(add(p, 192)))
+                mstore(add(input_ptr, 160), mload(add(p, 224)))
+
+                mstore(add(input_ptr, 192), {{IDENTITY_ALPHA_X}})
+                mstore(add(input_ptr, 224), {{IDENTITY_ALPHA_Y}})
+                mstore(add(input_ptr, 256), {{IDENTITY_BETA_X1}})
+                mstore(add(input_ptr, 288), {{IDENTITY_BETA_X0}})
+                mstore(add(input_ptr, 320), {{IDENTITY_BETA_Y1}})
+                mstore(add(input_ptr, 352), {{IDENTITY_BETA_Y0}})
+
+                mstore(add(input_ptr, 384), mload(addInput))
+                mstore(add(input_ptr, 416), mload(add(addInput, 32)))
+                mstore(add(input_ptr, 448), {{IDENTITY_GAMMA_X1}})
+                mstore(add(input_ptr, 480), {{IDENTITY_GAMMA_X0}})
+                mstore(add(input_ptr, 512), {{IDENTITY_GAMMA_Y1}})
+                mstore(add(input_ptr, 544), {{IDENTITY_GAMMA_Y0}})
+
+                mstore(add(input_ptr, 576), mload(add(p, 256)))
+                mstore(add(input_ptr, 608), mload(add(p, 288)))
+                mstore(add(input_ptr, 640), {{IDENTITY_DELTA_X1}})
+                mstore(add(input_ptr, 672), {{IDENTITY_DELTA_X0}})
+                mstore(add(input_ptr, 704), {{IDENTITY_DELTA_Y1}})
+                mstore(add(input_ptr, 736), {{IDENTITY_DELTA_Y0}})
+
+                success := staticcall(
+                    sub(gas(), 2000),
+                    8,
+                    input_ptr,
+                    768,
+                    out,
+                    0x20
+                )
+                // Use "invalid" to make gas estimation work
+                switch success
+                    case 0 {
+                        invalid()
+                    }
+            }
+
+            return out[0] != 0;
+        }
+    }
+    
+    // Verify the identity assertion. lrcm is recomputed from the ranges, the CA and
+    // the challenge, so that only credentials of a trusted CA are accepted.
+    function verifyIdentity(uint256 Ax, uint256 Ay, uint256 rangeHash, uint256 caHash, uint256 ctx, Proof memory proof) external {
+        require(trustedCAs[caHash], "Untrusted CA!");
+        uint256 lrcm = poseidon3.poseidon([rangeHash, caHash, ctx]);
+        bytes32 key = keccak256(abi.encode(Ax, Ay, lrcm));
+        require(!presented[key], "Replayed Proof!");
+        require(_verifyIdentity(Ax, Ay, lrcm, proof), "Invalid Proof!");
+        presented[key] = true;
+        emit IdentityVerified(Ax, Ay, lrcm);
+    }
+    
+    // verify set-membership or non-membership proof
+    function _verifyAttributeSet(uint256 Ax, uint256 Ay, uint256 scm, Proof memory proof) private view returns (bool) {
+        uint256 snark_scalar_field = 21888242871839275222246405745257275088548364400416034343698204186575808495617;
+        uint256[3] memory input = [Ax, Ay, scm];
+        G1Point memory vk_x = attributeSetVK.IC[0];
+        for (uint256 i = 0; i < 3; i++) {
+            require(input[i] < snark_scalar_field, "verifier-gte-snark-scalar-field");
+            vk_x = addition(vk_x, scalar_mul(attributeSetVK.IC[i + 1], input[i]));
+        }
+        return pairingProd4(
+            negate(proof.A),
+            proof.B,
+            attributeSetVK.alfa1,
+            attributeSetVK.beta2,
+            vk_x,
+            attributeSetVK.gamma2,
+            proof.C,
+            attributeSetVK.delta2
+        );
+    }
+
+    // Verify that an attribute is (or is not) in a set. scm is recomputed from the root
+    // of the set, the attribute, the CA and the challenge, so that only credentials of a
+    // trusted CA are accepted.
+    function verifyAttributeSet(uint256 Ax, uint256 Ay, uint256 root, uint256 index, uint256 member, uint256 caHash, uint256 ctx, Proof memory proof) external {
+        require(trustedCAs[caHash], "Untrusted CA!");
+        uint256 scm = poseidon5.poseidon([root, index, member, caHash, ctx]);
+        bytes32 key = keccak256(abi.encode(Ax, Ay, scm));
+        require(!presented[key], "Replayed Proof!");
+        require(_verifyAttributeSet(Ax, Ay, scm, proof), "Invalid Proof!");
+        presented[key] = true;
+        emit AttributeSetVerified(Ax, Ay, scm);
+    }
+
+    // verify non-sybil proof
+    function verifyAppkey(address user, uint256 appkey, uint256 appid, uint256 ctx, Proof memory proof) external view returns (bool) {
+        return _verifyAppkey(user, appkey, appid, ctx, proof);
+    }
+    
+    // Issue a challenge of the calling app for sybil resistance, accepted once by
+    // setAppkey until it expires.
+    function issueChallenge(uint256 ctx, uint256 expiry) external {
+        uint256 appid = uint256(uint160(msg.sender));
+        require(ctx != 0 && expiry >= block.timestamp, "Invalid Challenge!");
+        require(challengeExpiry[appid][ctx] == 0, "Issued Challenge!");
+        challengeExpiry[appid][ctx] = expiry;
+        emit ChallengeIssued(appid, ctx, expiry);
+    }
+
+    // do sybil-resistance, the proof must be bound to a pending challenge of the app,
+    // which is consumed
+    function setAppkey(uint256 user, uint256 appkey, uint256 appid, uint256 ctx, Proof memory proof) external {
+        unchecked {
+            require(challengeExpiry[appid][ctx] >= block.timestamp, "Invalid Challenge!");
+            require(_verifyAppkey(address(uint160(user)), appkey, appid, ctx, proof), "Invalid Proof!");
+            delete challengeExpiry[appid][ctx];
+            // appkeys[address(uint160(user))][appid] = appkey;
+            emit AppkeySet(address(uint160(user)), appid, appkey);
+        }
+    }
+    
+    // revoke pseudonyms
+    function revoke(address[] memory addrs) external {
+         require(committeeId[msg.sender] != 0, "not in committee");
+         for (uint i = 0; i < addrs.length; ++i) {
+             delete identityInfo[addrs[i]];
+             emit AddressRevoke(addrs[i]);
+         }
+    }
+
+    event AppkeySet(address indexed user, uint256 appid, uint256 appkey);
+    event ChallengeIssued(uint256 indexed appid, uint256 ctx, uint256 expiry);
+    event UserRegister(address indexed user, IdentityFullMeta meta);
+    event UserMarked(uint256 indexed c1y, address user);
+    event rootsUpdate(uint256 version, uint256 root1, uint256 root2);
+    event AddressRevoke(address indexed user);
+    event IdentityVerified(uint256 indexed Ax, uint256 indexed Ay, uint256 lrcm);
+    event AttributeSetVerified(uint256 indexed Ax, uint256 indexed Ay, uint256 scm);
+}
@@ -0,0 +1,257 @@
+// This file implements the attestation of identity attributes by the CA. Before
+// signing a credential, the CA passes the attributes of the request and the evidence
+// attached to it (e.g. scans of documents) to an `AttributeVerifier`. Deployments
+// plug in their own checks, two are built in: `AcceptAll`, and `DocumentHashVerifier`
+// which accepts attributes attested by known documents issued to the master key.
+
+use crate::schema::Schema;
+use baby_jub::Point;
+use ethers::utils::keccak256;
+use num_bigint::BigInt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+// A document attesting the attribute with the name.
+pub struct Evidence {
+    pub attribute: String,
+    pub document: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+// Reasons to reject the attributes of a request.
+pub enum AttestationError {
+    // no evidence for the attribute
+    MissingEvidence(String),
+    // the evidence of the attribute is not known
+    UnknownEvidence(String),
+    // the evidence does not attest the value of the attribute
+    Mismatch(String),
+    // rejected by the verifier for another reason
+    Rejected(String),
+}
+
+impl fmt::Display for AttestationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttestationError::MissingEvidence(name) => write!(f, "No evidence of {}", name),
+            AttestationError::UnknownEvidence(name) => write!(f, "Unknown evidence of {}", name),
+            AttestationError::Mismatch(name) => write!(f, "The evidence does not attest {}", name),
+            AttestationError::Rejected(reason) => write!(f, "Rejected: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for AttestationError {}
+
+// Check the identity attributes of a credential request before the CA signs them for
+// the master key.
+pub trait AttributeVerifier: fmt::Debug + Send + Sync {
+    fn verify(
+        &self,
+        schema: &Schema,
+        master_key_g: &Point,
+        attributes: &[BigInt],
+        evidence: &[Evidence],
+    ) -> Result<(), AttestationError>;
+}
+
+#[derive(Debug, Clone, Default)]
+// Accept any attributes, the default of a CA.
+pub struct AcceptAll;
+
+impl AttributeVerifier for AcceptAll {
+    fn verify(
+        &self,
+        _schema: &Schema,
+        _master_key_g: &Point,
+        _attributes: &[BigInt],
+        _evidence: &[Evidence],
+    ) -> Result<(), AttestationError> {
+        Ok(())
+    }
+}
+
+// Hash of a document issued to the master key, see `DocumentHashVerifier`.
+pub fn document_hash(document: &[u8], master_key_g: &Point) -> [u8; 32] {
+    let mut bytes = document.to_vec();
+    for v in [master_key_g.scalar_x(), master_key_g.scalar_y()] {
+        let (_, be) = v.to_bytes_be();
+        bytes.extend(vec![0u8; 32 - be.len()]);
+        bytes.extend(be);
+    }
+    keccak256(bytes)
+}
+
+#[derive(Debug, Clone, Default)]
+// Accept the attributes attested by known documents, identified by their hash with the
+// master key they were issued to (see `document_hash`), so that the document of a
+// user does not attest the attributes of another master key. Attributes without a
+// registered document are not checked.
+pub struct DocumentHashVerifier {
+    // hash of the document => the encoded values it attests, by attribute name
+    documents: HashMap<[u8; 32], HashMap<String, BigInt>>,
+    // attributes which require evidence
+    attested: BTreeSet<String>,
+}
+
+impl DocumentHashVerifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Register the hash of a document attesting the encoded value of the attribute, as
+    // computed by `document_hash`.
+    pub fn register(&mut self, document_hash: [u8; 32], attribute: &str, value: BigInt) {
+        self.attested.insert(attribute.to_string());
+        self.documents
+            .entry(document_hash)
+            .or_default()
+            .insert(attribute.to_string(), value);
+    }
+
+    // check the evidence of one attribute
+    fn verify_attribute(
+        &self,
+        name: &str,
+        master_key_g: &Point,
+        value: &BigInt,
+        evidence: &[Evidence],
+    ) -> Result<(), AttestationError> {
+        let mut known = false;
+        let mut found = false;
+        for doc in evidence.iter().filter(|e| e.attribute == name) {
+            found = true;
+            match self
+                .documents
+                .get(&document_hash(&doc.document, master_key_g))
+                .and_then(|attested| attested.get(name))
+            {
+                Some(attested) if attested == value => return Ok(()),
+                Some(_) => known = true,
+                None => {}
+            }
+        }
+        Err(if !found {
+            AttestationError::MissingEvidence(name.to_string())
+        } else if known {
+            AttestationError::Mismatch(name.to_string())
+        } else {
+            AttestationError::UnknownEvidence(name.to_string())
+        })
+    }
+}
+
+impl AttributeVerifier for DocumentHashVerifier {
+    fn verify(
+        &self,
+        schema: &Schema,
+        master_key_g: &Point,
+        attributes: &[BigInt],
+        evidence: &[Evidence],
+    ) -> Result<(), AttestationError> {
+        if let Some(name) = self.attested.iter().find(|n| schema.index(n).is_none()) {
+            return Err(AttestationError::Rejected(format!(
+                "Unknown attribute {}",
+                name
+            )));
+        }
+        // in the order of the schema, so that the error is the same for a request
+        for (i, def) in schema.attributes.iter().enumerate() {
+            if !self.attested.contains(&def.name) {
+                continue;
+            }
+            let value = attributes.get(i).ok_or_else(|| {
+                AttestationError::Rejected(format!("Missing attribute {}", def.name))
+            })?;
+            self.verify_attribute(&def.name, master_key_g, value, evidence)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        document_hash, AcceptAll, AttestationError, AttributeVerifier, DocumentHashVerifier,
+        Evidence,
+    };
+    use crate::schema::Schema;
+    use baby_jub::new_key;
+    use num_bigint::BigInt;
+
+    #[test]
+    // attributes are accepted with the evidence of known documents
+    fn test_document_hash() {
+        let schema = Schema::untyped(3);
+        let key = new_key().public();
+        let attributes = vec![BigInt::from(10), BigInt::from(20), BigInt::from(30)];
+        let passport = b"passport of alice".to_vec();
+        let evidence = |attribute: &str, document: &[u8]| Evidence {
+            attribute: attribute.to_string(),
+            document: document.to_vec(),
+        };
+        assert!(AcceptAll.verify(&schema, &key, &attributes, &[]).is_ok());
+
+        let mut verifier = DocumentHashVerifier::new();
+        verifier.register(document_hash(&passport, &key), "attr1", BigInt::from(20));
+        assert!(verifier
+            .verify(&schema, &key, &attributes, &[evidence("attr1", &passport)])
+            .is_ok());
+        // the document does not attest the attributes of another master key
+        assert_eq!(
+            verifier.verify(
+                &schema,
+                &new_key().public(),
+                &attributes,
+                &[evidence("attr1", &passport)]
+            ),
+            Err(AttestationError::UnknownEvidence("attr1".to_string()))
+        );
+        // every attested attribute needs evidence, checked in the order of the schema
+        let diploma = b"diploma of alice".to_vec();
+        verifier.register(document_hash(&diploma, &key), "attr2", BigInt::from(30));
+        verifier.register(document_hash(&diploma, &key), "attr0", BigInt::from(10));
+        assert_eq!(
+            verifier.verify(&schema, &key, &attributes, &[]),
+            Err(AttestationError::MissingEvidence("attr0".to_string()))
+        );
+        assert_eq!(
+            verifier.verify(
+                &schema,
+                &key,
+                &attributes,
+                &[evidence("attr0", &diploma), evidence("attr1", &passport)]
+            ),
+            Err(AttestationError::MissingEvidence("attr2".to_string()))
+        );
+        let all = [
+            evidence("attr0", &diploma),
+            evidence("attr1", &passport),
+            evidence("attr2", &diploma),
+        ];
+        assert!(verifier.verify(&schema, &key, &attributes, &all).is_ok());
+        let forged = [
+            evidence("attr0", &diploma),
+            evidence("attr1", b"forged"),
+            evidence("attr2", &diploma),
+        ];
+        assert_eq!(
+            verifier.verify(&schema, &key, &attributes, &forged),
+            Err(AttestationError::UnknownEvidence("attr1".to_string()))
+        );
+        let mut changed = attributes.clone();
+        changed[1] = BigInt::from(21);
+        assert_eq!(
+            verifier.verify(&schema, &key, &changed, &all),
+            Err(AttestationError::Mismatch("attr1".to_string()))
+        );
+        // attested attributes must be in the schema
+        verifier.register(document_hash(&diploma, &key), "grade", BigInt::from(1));
+        assert!(matches!(
+            verifier.verify(&schema, &key, &attributes, &all),
+            Err(AttestationError::Rejected(_))
+        ));
+    }
+}
@@ -0,0 +1,160 @@
+// This file implements the sets used in set-membership and non-membership proofs of
+// attributes (e.g. "nationality is one of the EU countries", "not on a sanctions
+// list"). A set is committed by a sorted Merkle tree, or by a dual tree when the
+// attribute is proven not to be in it. The proof (circuits/attribute_set.circom)
+// reveals the commitment scm = hash(root, index, member, hash of the CA generators and
+// the schema, hash of the challenge of the verifier).
+
+use crate::merkle_tree::{DualTree, MerkleTree};
+use baby_jub::poseidon_hash;
+use num_bigint::{BigInt, ToBigInt};
+use num_traits::One;
+
+// layers of the set trees, the circuit uses paths of SET_TIERS - 1 nodes
+pub const SET_TIERS: usize = 17;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeSet {
+    // the attribute is in the set
+    Member(MerkleTree),
+    // the attribute is not in the set
+    NonMember(DualTree),
+}
+
+// Witness of a set proof, the private inputs of the circuit.
+pub struct SetWitness {
+    pub path: Vec<BigInt>,
+    pub key: BigInt,
+    pub leaf: BigInt,
+    pub root: BigInt,
+}
+
+// leaf of an encoded value, shifted by one as 0 is the left boundary of a dual tree
+fn leaf(value: &BigInt) -> BigInt {
+    value + BigInt::one()
+}
+
+impl AttributeSet {
+    // A set the attribute must belong to.
+    pub fn member(values: &[BigInt]) -> Result<Self, String> {
+        let mut tree = MerkleTree::new(SET_TIERS);
+        tree.insert_nodes(values.iter().map(leaf).collect())?;
+        Ok(AttributeSet::Member(tree))
+    }
+
+    // A set the attribute must not belong to.
+    pub fn non_member(values: &[BigInt]) -> Result<Self, String> {
+        let mut tree = DualTree::new(SET_TIERS);
+        tree.insert_nodes(values.iter().map(leaf).collect())?;
+        Ok(AttributeSet::NonMember(tree))
+    }
+
+    pub fn is_member(&self) -> bool {
+        matches!(self, AttributeSet::Member(_))
+    }
+
+    // Roots a proof can be made against, a non-membership proof uses either tree.
+    pub fn roots(&self) -> Result<Vec<BigInt>, String> {
+        match self {
+            AttributeSet::Member(tree) => Ok(vec![tree.root()?]),
+            AttributeSet::NonMember(tree) => {
+                let (root0, root1) = tree.roots()?;
+                Ok(vec![root0, root1])
+            }
+        }
+    }
+
+    // Commitments accepted by the verifier for the attribute at `index` of credentials
+    // of the CA whose generators and schema hash to `ca_hash` (see `ca_client::ca_hash`),
+    // under the challenge `ctx`.
+    pub fn commitments(
+        &self,
+        index: usize,
+        ca_hash: &BigInt,
+        ctx: &BigInt,
+    ) -> Result<Vec<BigInt>, String> {
+        let member = (self.is_member() as usize).to_bigint().unwrap();
+        let index = index.to_bigint().unwrap();
+        self.roots()?
+            .iter()
+            .map(|root| set_commit(root, &index, &member, ca_hash, ctx))
+            .collect()
+    }
+
+    // Generate the witness for the value of the attribute.
+    pub fn witness(&self, value: &BigInt) -> Result<SetWitness, String> {
+        let value = leaf(value);
+        match self {
+            AttributeSet::Member(tree) => {
+                let proof = tree
+                    .gen_inproof(value)
+                    .map_err(|_| "The attribute is not in the set")?;
+                Ok(SetWitness {
+                    key: proof.key(),
+                    leaf: proof.value,
+                    path: proof.path,
+                    root: tree.root()?,
+                })
+            }
+            AttributeSet::NonMember(tree) => {
+                let proof = tree
+                    .gen_notinproof(value)
+                    .map_err(|_| "The attribute is in the set")?;
+                let [low, high] = proof.siblings.clone();
+                Ok(SetWitness {
+                    key: proof.right_key(),
+                    leaf: high,
+                    path: vec![low].into_iter().chain(proof.path).collect(),
+                    root: proof.root,
+                })
+            }
+        }
+    }
+}
+
+// commitment of the set, the public input of a set proof
+pub fn set_commit(
+    root: &BigInt,
+    index: &BigInt,
+    member: &BigInt,
+    ca_hash: &BigInt,
+    ctx: &BigInt,
+) -> Result<BigInt, String> {
+    poseidon_hash(vec![root, index, member, ca_hash, ctx])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AttributeSet;
+    use num_bigint::BigInt;
+
+    #[test]
+    // witnesses are generated against the roots of the sets
+    fn test_set_witness() {
+        let values: Vec<BigInt> = [3, 7, 9, 0].iter().map(|v| BigInt::from(*v)).collect();
+
+        let member = AttributeSet::member(&values).unwrap();
+        let w = member.witness(&BigInt::from(7)).unwrap();
+        assert_eq!(w.leaf, BigInt::from(8));
+        assert_eq!(member.roots().unwrap(), vec![w.root]);
+        assert!(member.witness(&BigInt::from(5)).is_err());
+
+        let non_member = AttributeSet::non_member(&values).unwrap();
+        assert!(non_member.witness(&BigInt::from(9)).is_err());
+        for v in [1, 5, 8, 100] {
+            let w = non_member.witness(&BigInt::from(v)).unwrap();
+            let value = BigInt::from(v + 1);
+            assert!(w.path[0] < value && value < w.leaf);
+            assert!(non_member.roots().unwrap().contains(&w.root));
+        }
+
+        let ca_hash = BigInt::from(1);
+        let ctx = BigInt::from(2);
+        let c1 = member.commitments(2, &ca_hash, &ctx).unwrap();
+        let c2 = non_member.commitments(2, &ca_hash, &ctx).unwrap();
+        assert_eq!(c1.len(), 1);
+        assert_eq!(c2.len(), 2);
+        assert_ne!(c1, member.commitments(3, &ca_hash, &ctx).unwrap());
+        assert_ne!(c1, member.commitments(2, &ca_hash, &ca_hash).unwrap());
+    }
+}
@@ -4,10 +4,12 @@
 use color_eyre::Result;
 
 use crate::ca_client::CA;
+use crate::challenge::{Challenge, Challenger};
 use crate::committee_client::Committee;
 use crate::get_timestamp;
+use crate::tpke::gen_key_shares;
 use crate::user_client::Client;
-use num_bigint::{BigInt, ToBigInt};
+use num_bigint::{BigInt, Sign, ToBigInt};
 
 use core::str::FromStr;
 
@@ -16,7 +18,38 @@ use ethers::{
     providers::{Http, Provider},
     signers::Signer,
 };
-use std::{convert::TryFrom, sync::Arc, time::Duration};
+use std::{convert::TryFrom, path::Path, sync::Arc, time::Duration};
+
+// generate the committee and CA data loaded by the bench: two members of a 2-of-3
+// committee sharing the zero-knowledge parameters, and a CA.
+pub fn gen_fixtures() -> Result<()> {
+    let mut cm1 = Committee::new(8);
+    let mut cm2 = cm1.clone();
+    let (tpke_key, shares) = gen_key_shares(2, 3).map_err(|e| eyre::eyre!(e))?;
+    let tpke_shards: Vec<_> = shares.iter().map(|s| s.public()).collect();
+    for (cm, share) in [&mut cm1, &mut cm2].into_iter().zip(shares) {
+        cm.update_tpke_share(share, 2);
+        cm.update_tpke_shards(tpke_shards.clone());
+        cm.update_tpke_key(tpke_key.clone());
+    }
+    cm1.save("./data/test_cm1")?;
+    cm2.save("./data/test_cm2")?;
+
+    let ca = CA::init(8, tpke_key);
+    ca.save("./data/test_ca.bak")?;
+    Ok(())
+}
+
+// load the committee members and the CA of the bench, generating them first if missing
+pub fn load_fixtures() -> Result<(Committee, Committee, CA)> {
+    if !Path::new("./data/test_cm1.dat").exists() {
+        gen_fixtures()?;
+    }
+    let cm1 = Committee::load("./data/test_cm1")?;
+    let cm2 = Committee::load("./data/test_cm2")?;
+    let ca = CA::load("./data/test_ca.bak")?;
+    Ok((cm1, cm2, ca))
+}
 
 // bench function
 pub async fn bench_all() -> Result<()> {
@@ -34,24 +67,27 @@ pub async fn bench_all() -> Result<()> {
     let client = Arc::new(client);
 
     println!("1. Start setting up the committee: ");
-    // load the committee data form file
-    let mut cm1 = Committee::load("./data/test_cm1")?;
-    let mut cm2 = Committee::load("./data/test_cm2")?;
+    // load the committee and ca data form file
+    let (mut cm1, mut cm2, mut ca) = load_fixtures()?;
     println!("1. The committee has been set up.");
 
     println!("2. Start setting up CA: ");
-    // load the ca data from file
-    let mut ca = CA::load("./data/test_ca.bak")?;
     ca.tpke_key = cm1.tpke_key.as_ref().unwrap().clone();
     println!("2.1 Start adding CA to trusted list: ");
     // add ca to the trusted list
-    cm1.ca_tree.insert_nodes(vec![ca.pubkey().scalar_y()]);
-    cm2.ca_tree.insert_nodes(vec![ca.pubkey().scalar_y()]);
+    cm1.add_ca(&ca.pubkey(), &ca.generators, &ca.schema.hash())
+        .map_err(|e| eyre::eyre!(e))?;
+    cm2.add_ca(&ca.pubkey(), &ca.generators, &ca.schema.hash())
+        .map_err(|e| eyre::eyre!(e))?;
     println!("2. CA has been set up.");
 
     println!("3. Start setting up the identity contract:");
     // Update the tpke public key to the identity contract.
     let _res = cm1.set_tpke_pub(contract_address, client.clone()).await?;
+    // Trust the CA in the identity contract.
+    let _res = cm1
+        .set_trusted_ca(&ca.pubkey(), true, contract_address, client.clone())
+        .await?;
     println!("3. The identity contract has been set up.");
 
     println!("4. Start updating the identity contract:");
@@ -115,8 +151,12 @@ pub async fn bench_all() -> Result<()> {
     println!("6. pseudonym 2 generated.");
 
     println!("[Sybil-resistance] 7. Start to response to Sybil-resistance: ");
-    // generate an application id
-    let appid = BigInt::from_str("994862232198212916674956859767646391285724603386").unwrap();
+    // the application id is the address of the app
+    let appid = BigInt::from_bytes_be(Sign::Plus, client.address().as_bytes());
+    // the app issues a challenge in the identity contract
+    let challenge = Challenger::new(&appid, 600)
+        .issue_on_chain(contract_address, client.clone())
+        .await?;
     // generate a no-sybil proof, and send it to the identity contract
     let _res = user
         .send_appkey(
@@ -124,13 +164,16 @@ pub async fn bench_all() -> Result<()> {
             &req.master_key_g,
             &sn,
             &appid,
+            &challenge,
             contract_address,
             client.clone(),
         )
         .await?;
     println!("7. proof accepted.");
 
-    println!("[selective disclosure] 8. Start to prove identity attributes (Selective disclosure):");
+    println!(
+        "[selective disclosure] 8. Start to prove identity attributes (Selective disclosure):"
+    );
     // generate an identity proof, and send it to the identity contract
     let _res = user
         .verify_identity(
@@ -139,6 +182,7 @@ pub async fn bench_all() -> Result<()> {
             &sn,
             (0..8).map(|x| (x + 1).to_bigint().unwrap()).collect(),
             (0..8).map(|x| (x + 20).to_bigint().unwrap()).collect(),
+            &Challenge::new(&appid, 600),
             contract_address,
             client.clone(),
         )
@@ -161,7 +205,9 @@ pub async fn bench_all() -> Result<()> {
     // get decryption shard
     let k2 = cm2.decrypt_shard(&cipher1.c1);
     // decrpt the ciphper
-    let (m1, m2) = cipher1.decrypt(vec![&k1, &k2], &user_address);
+    let (m1, m2) = cipher1
+        .decrypt(vec![&k1, &k2], &user_address, cm1.tpke_threshold)
+        .unwrap();
 
     assert_eq!(m2, ca.pubkey());
 
@@ -176,7 +222,11 @@ pub async fn bench_all() -> Result<()> {
     let k2_2 = cm2.decrypt_shard(&user_info.cipher.c1);
 
     // decrtption and reveal the trap-door
-    let beta = user_info.cipher.decrypt(vec![&k2_1, &k2_2]).scalar_y();
+    let beta = user_info
+        .cipher
+        .decrypt(vec![&k2_1, &k2_2], cm1.tpke_threshold)
+        .unwrap()
+        .scalar_y();
 
     // trace
     let derived_address = cm1
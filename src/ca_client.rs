@@ -3,30 +3,65 @@
 // user information storage, user information query, etc. As for how to verify the
 // user's identity, we leave it to the developers.
 
+use crate::attestation::{AcceptAll, AttestationError, AttributeVerifier, Evidence};
+use crate::committee_client::Committee;
 use crate::get_timestamp;
-use crate::tpke::{Cipher, PublicKey};
+use crate::schema::Schema;
+use crate::snarkjs::setup;
+use crate::tpke::{modulo, random_scalar, Cipher, PublicKey};
 use ark_bn254::Bn254;
 use ark_circom::{CircomBuilder, CircomConfig};
 use ark_ff::bytes::ToBytes;
-use ark_groth16::{
-    generate_random_parameters, prepare_verifying_key, verify_proof, KeySize, Proof, ProvingKey,
-};
+use ark_groth16::{prepare_verifying_key, verify_proof, KeySize, Proof, ProvingKey};
 use ark_serialize::*;
-use baby_jub::{new_key, poseidon_hash, Point, PrivateKey, Signature, B8, H8};
+use baby_jub::{new_key, poseidon_hash, Point, PrivateKey, Signature, B8, G, H8};
 use num_bigint::{BigInt, ToBigInt};
 use postcard::{from_bytes, to_stdvec};
 use rand::thread_rng;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
+use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct UserInfo {
     pub attributes: Vec<BigInt>,
     pub cipher: Cipher,
     pub beta_g: Point,
+    // version of the credential of the master key, starting at 0
+    pub version: u32,
+    // unix timestamps in seconds
+    pub issued_at: u64,
+    pub expiration: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+// What the CA does when a master key requests a credential again.
+pub enum Reissue {
+    // refuse the request
+    Refuse,
+    // issue a new version of the credential, keeping the earlier records
+    Version,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+// Issuance policy of a CA.
+pub struct IssuancePolicy {
+    pub reissue: Reissue,
+    // longest validity of a credential in seconds
+    pub max_expiration: Option<u64>,
+}
+
+impl Default for IssuancePolicy {
+    fn default() -> Self {
+        IssuancePolicy {
+            reissue: Reissue::Version,
+            max_expiration: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -34,12 +69,24 @@ pub struct CA {
     pub attribute_num: usize,
     pub private_key: PrivateKey,
     pub generators: Vec<Point>,
-    pub user_infos: HashMap<Point, UserInfo>,
+    // names and types of the attributes
+    pub schema: Schema,
+    // issuance history of each master key, the last record is the current one
+    pub user_infos: HashMap<Point, Vec<UserInfo>>,
+    // master keys which are refused credentials
     pub blacklist: Vec<Point>,
+    pub policy: IssuancePolicy,
     pub zkp_cfg: CircomConfig<Bn254>,
     pub zkp_params: ProvingKey<Bn254>,
     // The public key used in threshold public key encryption.
     pub tpke_key: PublicKey,
+    // number of attributes of the identity circuit of the committee, set by `register`
+    pub committee_attribute_num: Option<usize>,
+    // nonces of the fresh credential and renewal requests received with their
+    // timestamps, each is accepted once
+    pub used_nonces: HashMap<BigInt, u64>,
+    // checks the attributes before signing, not serialized
+    pub attribute_verifier: Arc<dyn AttributeVerifier>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -49,10 +96,14 @@ pub struct CAPart1 {
     pub private_key: PrivateKey,
     // generators used in pedersen commitment
     pub generators: Vec<Point>,
-    pub user_infos: HashMap<Point, UserInfo>,
+    pub schema: Schema,
+    pub user_infos: HashMap<Point, Vec<UserInfo>>,
     pub blacklist: Vec<Point>,
+    pub policy: IssuancePolicy,
     // The public key used in threshold public key encryption.
     pub tpke_key: PublicKey,
+    pub committee_attribute_num: Option<usize>,
+    pub used_nonces: HashMap<BigInt, u64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -65,6 +116,8 @@ pub struct Credential {
     // pedersen commitment of identity attributies
     pub attr_commit: Point,
     pub expiration: u64,
+    // hash of the schema of the attributes
+    pub schema_hash: BigInt,
 }
 
 #[derive(Debug, Clone)]
@@ -74,17 +127,392 @@ pub struct CredentialRequest {
     // identity attributies
     pub attributes: Vec<BigInt>,
     pub expiration: u64,
+    // hash of the schema the attributes are encoded with
+    pub schema_hash: BigInt,
     // cipher of trapdoor
     pub cipher: Cipher,
     // public key of CA
     pub ca_key: Point,
     // zero knowledge proof for the cipher
     pub cipher_proof: Proof<Bn254>,
+    // random nonce of the request, accepted once by the CA
+    pub nonce: BigInt,
+    // unix timestamp of the request in seconds, see `REQUEST_TTL`
+    pub timestamp: u64,
+    // proof of knowledge of the master key and the trapdoor, bound to the request
+    pub possession: PossessionProof,
+    // documents attesting the attributes, checked by the attribute verifier of the CA
+    pub evidence: Vec<Evidence>,
+}
+
+#[derive(Debug, Clone)]
+// Request to renew the credential of the master key with a new expiration.
+pub struct RenewalRequest {
+    pub master_key_g: Point,
+    pub beta_g: Point,
+    pub expiration: u64,
+    // hash of the schema of the credential
+    pub schema_hash: BigInt,
+    // public key of CA
+    pub ca_key: Point,
+    // random nonce of the request, accepted once by the CA
+    pub nonce: BigInt,
+    // unix timestamp of the request in seconds, see `REQUEST_TTL`
+    pub timestamp: u64,
+    // proof of knowledge of the master key and the trapdoor, bound to the request
+    pub possession: PossessionProof,
+}
+
+// Seconds a credential or renewal request is accepted around its timestamp. The CA
+// only keeps the nonces of the requests in this window.
+pub const REQUEST_TTL: u64 = 600;
+
+impl CredentialRequest {
+    // Hash of the request bound into the proof of possession: the nonce, the
+    // timestamp, the expiration, the schema, the cipher of the trapdoor and the
+    // attributes.
+    pub fn hash(&self) -> Result<BigInt, String> {
+        let mut hash = poseidon_hash(vec![
+            &self.nonce,
+            &self.timestamp.to_bigint().unwrap(),
+            &self.expiration.to_bigint().unwrap(),
+            &self.schema_hash,
+        ])?;
+        for p in [&self.cipher.c1, &self.cipher.c2] {
+            hash = poseidon_hash(vec![&hash, &p.scalar_x(), &p.scalar_y()])?;
+        }
+        for a in &self.attributes {
+            hash = poseidon_hash(vec![&hash, a])?;
+        }
+        Ok(hash)
+    }
+}
+
+impl RenewalRequest {
+    // Hash of the request bound into the proof of possession: the nonce, the
+    // timestamp, the expiration and the schema.
+    pub fn hash(&self) -> Result<BigInt, String> {
+        poseidon_hash(vec![
+            &self.nonce,
+            &self.timestamp.to_bigint().unwrap(),
+            &self.expiration.to_bigint().unwrap(),
+            &self.schema_hash,
+        ])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+// Why a CA asks the committee to revoke a credential.
+pub enum RevocationReason {
+    // the master key or the trapdoor leaked
+    KeyCompromise,
+    // the credential was issued on forged evidence
+    Fraud,
+    // the attributes are no longer valid
+    AttributesChanged,
+    Other,
+}
+
+impl RevocationReason {
+    // code of the reason in the signed message
+    pub fn code(&self) -> u64 {
+        match self {
+            RevocationReason::KeyCompromise => 1,
+            RevocationReason::Fraud => 2,
+            RevocationReason::AttributesChanged => 3,
+            RevocationReason::Other => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// Request of a CA to the committee to revoke a credential it issued, signed by the CA.
+pub struct RevocationRequest {
+    // the revoked credential, signed by the CA when it was issued
+    pub credential: Credential,
+    pub reason: RevocationReason,
+    // unix timestamp in seconds
+    pub timestamp: u64,
+    // public key of CA
+    pub ca_key: Point,
+    pub signature: Signature,
+}
+
+// Verify an EdDSA signature, s * B8 = R8 + 8 * H(R8, A, M) * A as in the
+// EdDSAPoseidonVerifier of circomlib.
+fn verify_signature(key: &Point, msg_hash: &BigInt, signature: &Signature) -> bool {
+    let h = match poseidon_hash(vec![
+        &signature.r_b8.scalar_x(),
+        &signature.r_b8.scalar_y(),
+        &key.scalar_x(),
+        &key.scalar_y(),
+        msg_hash,
+    ]) {
+        Ok(h) => h,
+        Err(_) => return false,
+    };
+    &signature.s * B8.clone() == signature.r_b8.clone() + &(h * 8) * key
+}
+
+// message signed in a credential
+fn credential_hash(
+    master_key_g: &Point,
+    beta_g: &Point,
+    attr_commit: &Point,
+    expiration: u64,
+    schema_hash: &BigInt,
+) -> Result<BigInt, String> {
+    let inputs: Vec<BigInt> = vec![master_key_g.scalar_x(), master_key_g.scalar_y()]
+        .into_iter()
+        .chain(vec![beta_g.scalar_x(), beta_g.scalar_y()].into_iter())
+        .chain(vec![attr_commit.scalar_x(), attr_commit.scalar_y()].into_iter())
+        .chain(vec![expiration.to_bigint().unwrap()].into_iter())
+        .chain(vec![schema_hash.clone()].into_iter())
+        .collect();
+
+    let msg_hash1 = poseidon_hash(inputs.iter().take(4).collect())?;
+    poseidon_hash(
+        vec![msg_hash1]
+            .iter()
+            .chain(inputs.iter().skip(4))
+            .collect(),
+    )
+}
+
+impl Credential {
+    // the signed message
+    pub fn hash(&self) -> Result<BigInt, String> {
+        credential_hash(
+            &self.master_key_g,
+            &self.beta_g,
+            &self.attr_commit,
+            self.expiration,
+            &self.schema_hash,
+        )
+    }
+
+    // the credential was issued by the CA
+    pub fn verify(&self, ca_key: &Point) -> bool {
+        match self.hash() {
+            Ok(h) => verify_signature(ca_key, &h, &self.signature),
+            Err(_) => false,
+        }
+    }
+}
+
+// message signed in a revocation request
+fn revocation_hash(
+    ca_key: &Point,
+    master_key_g: &Point,
+    reason: RevocationReason,
+    timestamp: u64,
+) -> Result<BigInt, String> {
+    poseidon_hash(vec![
+        &ca_key.scalar_x(),
+        &ca_key.scalar_y(),
+        &master_key_g.scalar_x(),
+        &master_key_g.scalar_y(),
+        &reason.code().to_bigint().unwrap(),
+        &timestamp.to_bigint().unwrap(),
+    ])
+}
+
+impl RevocationRequest {
+    // the signed message
+    pub fn hash(&self) -> Result<BigInt, String> {
+        revocation_hash(
+            &self.ca_key,
+            &self.credential.master_key_g,
+            self.reason,
+            self.timestamp,
+        )
+    }
+
+    // Verify the signature of the request and of the credential, both by the CA.
+    pub fn verify(&self) -> bool {
+        match self.hash() {
+            Ok(h) => {
+                verify_signature(&self.ca_key, &h, &self.signature)
+                    && self.credential.verify(&self.ca_key)
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+// Reasons the CA refuses to issue a credential.
+pub enum CredentialError {
+    // the request is malformed or its proofs are invalid
+    InvalidRequest(String),
+    // the attributes are not attested
+    Attestation(AttestationError),
+    // the master key is blacklisted
+    Blacklisted,
+    // the master key already has a credential and the policy refuses re-issuance
+    AlreadyIssued,
+    // the expiration exceeds the longest validity of the policy
+    ExpirationTooLong(u64),
+    // no credential was issued to the master key
+    NotIssued,
+    // the number of attributes does not match the CA or the identity circuit
+    AttributeNum { expected: usize, found: usize },
+}
+
+impl fmt::Display for CredentialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredentialError::InvalidRequest(reason) => write!(f, "{}", reason),
+            CredentialError::Attestation(e) => write!(f, "{}", e),
+            CredentialError::Blacklisted => write!(f, "The master key is blacklisted"),
+            CredentialError::AlreadyIssued => write!(f, "A credential was already issued"),
+            CredentialError::ExpirationTooLong(max) => {
+                write!(f, "The expiration exceeds {} seconds", max)
+            }
+            CredentialError::NotIssued => write!(f, "No credential was issued"),
+            CredentialError::AttributeNum { expected, found } => {
+                write!(f, "Expected {} attributes, found {}", expected, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CredentialError {}
+
+impl From<String> for CredentialError {
+    fn from(reason: String) -> Self {
+        CredentialError::InvalidRequest(reason)
+    }
+}
+
+impl From<AttestationError> for CredentialError {
+    fn from(e: AttestationError) -> Self {
+        CredentialError::Attestation(e)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+// Schnorr proof of knowledge of the discrete logarithms of the master public key and
+// the trapdoor public key, bound to the CA and the hash of the request.
+pub struct PossessionProof {
+    pub c: BigInt,
+    pub z_master: BigInt,
+    pub z_beta: BigInt,
+}
+
+impl PossessionProof {
+    // Fiat-Shamir challenge
+    fn challenge(
+        ca_key: &Point,
+        request: &BigInt,
+        master_key_g: &Point,
+        beta_g: &Point,
+        a_master: &Point,
+        a_beta: &Point,
+    ) -> BigInt {
+        let h1 = poseidon_hash(vec![
+            &ca_key.scalar_x(),
+            &ca_key.scalar_y(),
+            request,
+            &master_key_g.scalar_x(),
+            &master_key_g.scalar_y(),
+            &beta_g.scalar_x(),
+        ])
+        .unwrap();
+        let h2 = poseidon_hash(vec![
+            &h1,
+            &beta_g.scalar_y(),
+            &a_master.scalar_x(),
+            &a_master.scalar_y(),
+            &a_beta.scalar_x(),
+            &a_beta.scalar_y(),
+        ])
+        .unwrap();
+        modulo(&h2)
+    }
+
+    // Prove the knowledge of the master key and the trapdoor.
+    pub fn new(ca_key: &Point, request: &BigInt, master_key: &BigInt, beta: &BigInt) -> Self {
+        let w_master = random_scalar();
+        let w_beta = random_scalar();
+        let c = Self::challenge(
+            ca_key,
+            request,
+            &(master_key * G.clone()),
+            &(beta * G.clone()),
+            &(&w_master * G.clone()),
+            &(&w_beta * G.clone()),
+        );
+        PossessionProof {
+            z_master: modulo(&(w_master + &c * master_key)),
+            z_beta: modulo(&(w_beta + &c * beta)),
+            c,
+        }
+    }
+
+    // verify the proof
+    pub fn verify(
+        &self,
+        ca_key: &Point,
+        request: &BigInt,
+        master_key_g: &Point,
+        beta_g: &Point,
+    ) -> bool {
+        let a_master = &self.z_master * G.clone() + (&self.c * master_key_g).negative();
+        let a_beta = &self.z_beta * G.clone() + (&self.c * beta_g).negative();
+        self.c == Self::challenge(ca_key, request, master_key_g, beta_g, &a_master, &a_beta)
+    }
+}
+
+// circuit of the credential request
+fn load_config() -> CircomConfig<Bn254> {
+    CircomConfig::<Bn254>::load("./circuits/tpke_single.so", "./circuits/tpke_single.r1cs")
+        .unwrap_or_else(|error| {
+            panic!("{:?}", error);
+        })
+}
+
+// Hash of the generators of a CA, chained over the points in the same way as
+// `GeneratorsHash` in circuits/generators_hash.circom.
+pub fn generators_hash(generators: &[Point]) -> Result<BigInt, String> {
+    let (first, rest) = generators.split_first().ok_or("No generators")?;
+    let mut hash = poseidon_hash(vec![&first.scalar_x(), &first.scalar_y()])?;
+    for g in rest {
+        hash = poseidon_hash(vec![&hash, &g.scalar_x(), &g.scalar_y()])?;
+    }
+    Ok(hash)
+}
+
+// Hash of a CA bound into identity and set proofs: the hash of its generators and the
+// hash of the schema of its credentials, in the same way as `CaHash` in
+// circuits/generators_hash.circom.
+pub fn ca_hash(generators_hash: &BigInt, schema_hash: &BigInt) -> Result<BigInt, String> {
+    poseidon_hash(vec![generators_hash, schema_hash])
 }
 
 impl CA {
-    // Initialize a CA.
+    // Initialize a CA. The zero-knowledge proof parameters are generated locally, which
+    // is only suitable for testing, a real CA uses `init_with_params` with the
+    // parameters of a trusted setup ceremony.
     pub fn init(attribute_num: usize, tpke_key: PublicKey) -> Self {
+        let cfg = load_config();
+
+        // Generate zero-knowledge proof parameters.
+        let builder = CircomBuilder::new(cfg);
+        let circom = builder.setup();
+
+        let mut rng = thread_rng();
+        let params = setup(circom, &mut rng).unwrap();
+
+        Self::init_with_params(attribute_num, tpke_key, params)
+    }
+
+    // Initialize a CA with shared parameters, e.g. loaded by `ceremony::load_params`.
+    pub fn init_with_params(
+        attribute_num: usize,
+        tpke_key: PublicKey,
+        params: ProvingKey<Bn254>,
+    ) -> Self {
         let private_key = new_key();
         let k = private_key.scalar_key();
         // gen generators
@@ -98,28 +526,20 @@ impl CA {
             })
             .collect();
 
-        let cfg =
-            CircomConfig::<Bn254>::load("./circuits/tpke_single.so", "./circuits/tpke_single.r1cs")
-                .unwrap_or_else(|error| {
-                    panic!("{:?}", error);
-                });
-
-        // Generate zero-knowledge proof parameters.
-        let builder = CircomBuilder::new(cfg.clone());
-        let circom = builder.setup();
-
-        let mut rng = thread_rng();
-        let params = generate_random_parameters::<Bn254, _, _>(circom, &mut rng).unwrap();
-
         CA {
             attribute_num,
             private_key,
             generators,
+            schema: Schema::untyped(attribute_num),
             user_infos: HashMap::new(),
             blacklist: Vec::new(),
-            zkp_cfg: cfg,
+            policy: IssuancePolicy::default(),
+            zkp_cfg: load_config(),
             zkp_params: params,
             tpke_key,
+            committee_attribute_num: None,
+            used_nonces: HashMap::new(),
+            attribute_verifier: Arc::new(AcceptAll),
         }
     }
 
@@ -128,15 +548,106 @@ impl CA {
         self.private_key.public()
     }
 
+    // Register the CA with the committee. Its credentials must have the number of
+    // attributes of the identity circuit, or they could never be used.
+    pub fn register(&mut self, committee: &mut Committee) -> Result<(), CredentialError> {
+        if committee.attribute_num != self.attribute_num {
+            return Err(CredentialError::AttributeNum {
+                expected: committee.attribute_num,
+                found: self.attribute_num,
+            });
+        }
+        committee.add_ca(&self.pubkey(), &self.generators, &self.schema.hash())?;
+        self.committee_attribute_num = Some(committee.attribute_num);
+        Ok(())
+    }
+
+    // Declare the schema of the attributes, which must match the number of attributes.
+    // The committee binds the schema into identity proofs, so the CA must be registered
+    // again with its new schema.
+    pub fn update_schema(&mut self, schema: Schema) -> Result<(), String> {
+        if schema.len() != self.attribute_num {
+            return Err("The schema does not match the number of attributes".to_string());
+        }
+        self.schema = schema;
+        Ok(())
+    }
+
+    // Set the checks of the attributes of credential requests.
+    pub fn set_attribute_verifier(&mut self, verifier: Arc<dyn AttributeVerifier>) {
+        self.attribute_verifier = verifier;
+    }
+
+    // Set the issuance policy.
+    pub fn set_policy(&mut self, policy: IssuancePolicy) {
+        self.policy = policy;
+    }
+
+    // Refuse credentials to the master key.
+    pub fn add_blacklist(&mut self, master_key_g: &Point) {
+        if !self.is_blacklisted(master_key_g) {
+            self.blacklist.push(master_key_g.clone());
+        }
+    }
+
+    pub fn remove_blacklist(&mut self, master_key_g: &Point) {
+        self.blacklist.retain(|k| k != master_key_g);
+    }
+
+    pub fn is_blacklisted(&self, master_key_g: &Point) -> bool {
+        self.blacklist.contains(master_key_g)
+    }
+
+    // check the request against the issuance policy
+    fn check_policy(&self, req: &CredentialRequest) -> Result<(), CredentialError> {
+        if self.policy.reissue == Reissue::Refuse && self.user_infos.contains_key(&req.master_key_g)
+        {
+            return Err(CredentialError::AlreadyIssued);
+        }
+        self.check_expiration(&req.master_key_g, req.expiration)
+    }
+
+    // the master key is not blacklisted and the expiration is within the policy
+    fn check_expiration(
+        &self,
+        master_key_g: &Point,
+        expiration: u64,
+    ) -> Result<(), CredentialError> {
+        if self.is_blacklisted(master_key_g) {
+            return Err(CredentialError::Blacklisted);
+        }
+        match self.policy.max_expiration {
+            Some(max) if expiration > max => Err(CredentialError::ExpirationTooLong(max)),
+            _ => Ok(()),
+        }
+    }
+
+    // sign the fields of a credential
+    fn sign_credential(
+        &self,
+        master_key_g: &Point,
+        beta_g: &Point,
+        attr_commit: &Point,
+        expiration: u64,
+        schema_hash: &BigInt,
+    ) -> Result<Signature, String> {
+        let msg_hash = credential_hash(master_key_g, beta_g, attr_commit, expiration, schema_hash)?;
+        self.private_key.sign(msg_hash)
+    }
+
     // A part of the CA used for serialization.
     pub fn part1(&self) -> CAPart1 {
         CAPart1 {
             attribute_num: self.attribute_num,
             private_key: self.private_key.clone(),
             generators: self.generators.clone(),
+            schema: self.schema.clone(),
             user_infos: self.user_infos.clone(),
             blacklist: self.blacklist.clone(),
+            policy: self.policy.clone(),
             tpke_key: self.tpke_key.clone(),
+            committee_attribute_num: self.committee_attribute_num,
+            used_nonces: self.used_nonces.clone(),
         }
     }
 
@@ -158,7 +669,7 @@ impl CA {
         Ok(())
     }
 
-    // Load a CA from a file.
+    // Load a CA from a file. The attribute verifier must be set again.
     pub fn load(path: &str) -> std::io::Result<Self> {
         // load part 1
         let p1_data = fs::read(path.to_owned() + ".1")?;
@@ -172,30 +683,61 @@ impl CA {
         let reader3 = BufReader::new(file3);
         let zkp_params = ProvingKey::<Bn254>::read(reader3, &zkp_size);
 
-        let cfg =
-            CircomConfig::<Bn254>::load("./circuits/tpke_single.so", "./circuits/tpke_single.r1cs")
-                .unwrap_or_else(|error| {
-                    panic!("{:?}", error);
-                });
+        let cfg = load_config();
 
         Ok(CA {
             attribute_num: ca1.attribute_num,
             private_key: ca1.private_key,
             generators: ca1.generators,
+            schema: ca1.schema,
             user_infos: ca1.user_infos,
             blacklist: ca1.blacklist,
+            policy: ca1.policy,
             zkp_cfg: cfg,
             zkp_params,
             tpke_key: ca1.tpke_key,
+            committee_attribute_num: ca1.committee_attribute_num,
+            used_nonces: ca1.used_nonces,
+            attribute_verifier: Arc::new(AcceptAll),
         })
     }
 
     // Process the user's credential request, verify the request data, and issue a credential.
-    // Note: This function does not handle the validation of user identity attributes.
-    pub fn gen_credential(&mut self, req: CredentialRequest) -> Result<Credential, String> {
+    // The identity attributes are checked by the attribute verifier of the CA, with the
+    // evidence attached to the request, and the request must satisfy the issuance policy.
+    pub fn gen_credential(
+        &mut self,
+        req: CredentialRequest,
+    ) -> Result<Credential, CredentialError> {
+        self.check_policy(&req)?;
+        if let Some(num) = self.committee_attribute_num {
+            if num != self.attribute_num {
+                return Err(CredentialError::AttributeNum {
+                    expected: num,
+                    found: self.attribute_num,
+                });
+            }
+        }
         if req.attributes.len() != self.attribute_num {
-            return Err("Invalid number of attributes".to_string());
+            return Err(CredentialError::AttributeNum {
+                expected: self.attribute_num,
+                found: req.attributes.len(),
+            });
+        }
+        let schema_hash = self.schema.hash();
+        if req.schema_hash != schema_hash {
+            return Err("Invalid schema".to_string().into());
+        }
+        self.schema.check(&req.attributes)?;
+        // the requester must know the keys bound in the credential
+        if !req
+            .possession
+            .verify(&self.pubkey(), &req.hash()?, &req.master_key_g, &req.beta_g)
+        {
+            return Err("Invalid Proof of Possession".to_string().into());
         }
+        // the request cannot be replayed, even if it is rejected below
+        self.use_nonce(&req.nonce, req.timestamp)?;
         // verif proof
         let mut builder = CircomBuilder::new(self.zkp_cfg.clone());
         // put public input
@@ -213,52 +755,162 @@ impl CA {
         let pvk = prepare_verifying_key(&self.zkp_params.vk);
         let verified = verify_proof(&pvk, &req.cipher_proof, &inputs).unwrap();
         if !verified {
-            return Err("Invalid Cipher Proof".to_string());
+            return Err("Invalid Cipher Proof".to_string().into());
         }
 
+        // check the attributes
+        self.attribute_verifier.verify(
+            &self.schema,
+            &req.master_key_g,
+            &req.attributes,
+            &req.evidence,
+        )?;
+
+        let issued_at = get_timestamp();
+        let expiration = issued_at + req.expiration;
         // compute pedersen commitment
         let attr_commit: Point = req
             .attributes
             .iter()
             .zip(self.generators.iter())
             .fold(Point::identity(), |sum, val| sum + val.0 * val.1);
-        let inputs: Vec<BigInt> = vec![req.master_key_g.scalar_x(), req.master_key_g.scalar_y()]
-            .into_iter()
-            .chain(vec![req.beta_g.scalar_x(), req.beta_g.scalar_y()].into_iter())
-            .chain(vec![attr_commit.scalar_x(), attr_commit.scalar_y()].into_iter())
-            .chain(vec![(get_timestamp() + req.expiration).to_bigint().unwrap()].into_iter())
-            .collect();
-
-        let msg_hash1 = poseidon_hash(inputs.iter().take(4).collect())?;
-        let msg_hash = poseidon_hash(
-            vec![msg_hash1]
-                .iter()
-                .chain(inputs.iter().skip(4))
-                .collect(),
+        // sign data
+        let signature = self.sign_credential(
+            &req.master_key_g,
+            &req.beta_g,
+            &attr_commit,
+            expiration,
+            &schema_hash,
         )?;
 
-        // sign data
-        let signature = self.private_key.sign(msg_hash)?;
+        // save user info, after the earlier records of the master key
+        let history = self.user_infos.entry(req.master_key_g.clone()).or_default();
+        history.push(UserInfo {
+            attributes: req.attributes,
+            cipher: req.cipher,
+            beta_g: req.beta_g.clone(),
+            version: history.len() as u32,
+            issued_at,
+            expiration,
+        });
+        Ok(Credential {
+            signature,
+            master_key_g: req.master_key_g,
+            beta_g: req.beta_g,
+            attr_commit,
+            expiration,
+            schema_hash,
+        })
+    }
 
-        // save user info
-        self.user_infos.insert(
-            req.master_key_g.clone(),
-            UserInfo {
-                attributes: req.attributes,
-                cipher: req.cipher,
-                beta_g: req.beta_g.clone(),
-            },
-        );
+    // Accept the nonce of a request once, while the request is fresh. The nonces of
+    // requests out of the window of `REQUEST_TTL` are forgotten, as such requests are
+    // refused anyway.
+    fn use_nonce(&mut self, nonce: &BigInt, timestamp: u64) -> Result<(), CredentialError> {
+        let now = get_timestamp();
+        if timestamp.abs_diff(now) > REQUEST_TTL {
+            return Err("Expired request".to_string().into());
+        }
+        self.used_nonces
+            .retain(|_, t| t.abs_diff(now) <= REQUEST_TTL);
+        if self.used_nonces.insert(nonce.clone(), timestamp).is_some() {
+            return Err("Replayed request".to_string().into());
+        }
+        Ok(())
+    }
+
+    // Renew the credential of the master key with a new expiration. The keys and the
+    // attributes are those of the current record, so that the pseudonyms derived from
+    // the credential are kept. Renewal is a new version of the record, allowed even if
+    // the policy refuses re-issuance.
+    pub fn renew_credential(&mut self, req: RenewalRequest) -> Result<Credential, CredentialError> {
+        self.check_expiration(&req.master_key_g, req.expiration)?;
+        let info = self
+            .get_user_info(&req.master_key_g)
+            .ok_or(CredentialError::NotIssued)?
+            .clone();
+        if info.beta_g != req.beta_g {
+            return Err("Invalid trapdoor".to_string().into());
+        }
+        let schema_hash = self.schema.hash();
+        if req.schema_hash != schema_hash {
+            return Err("Invalid schema".to_string().into());
+        }
+        if !req
+            .possession
+            .verify(&self.pubkey(), &req.hash()?, &req.master_key_g, &req.beta_g)
+        {
+            return Err("Invalid Proof of Possession".to_string().into());
+        }
+        self.use_nonce(&req.nonce, req.timestamp)?;
+
+        let issued_at = get_timestamp();
+        let expiration = issued_at + req.expiration;
+        let attr_commit: Point = info
+            .attributes
+            .iter()
+            .zip(self.generators.iter())
+            .fold(Point::identity(), |sum, val| sum + val.0 * val.1);
+        let signature = self.sign_credential(
+            &req.master_key_g,
+            &req.beta_g,
+            &attr_commit,
+            expiration,
+            &schema_hash,
+        )?;
+
+        let history = self.user_infos.get_mut(&req.master_key_g).unwrap();
+        history.push(UserInfo {
+            version: history.len() as u32,
+            issued_at,
+            expiration,
+            ..info
+        });
         Ok(Credential {
             signature,
             master_key_g: req.master_key_g,
             beta_g: req.beta_g,
             attr_commit,
-            expiration: get_timestamp() + req.expiration,
+            expiration,
+            schema_hash,
         })
     }
 
+    // Ask the committee to revoke the credential, e.g. when the master key is
+    // compromised. The credential must have been issued by this CA, and is attached to
+    // the request. The master key is also blacklisted, so that it is refused new
+    // credentials and renewals.
+    pub fn revocation_request(
+        &mut self,
+        credential: &Credential,
+        reason: RevocationReason,
+    ) -> Result<RevocationRequest, CredentialError> {
+        let master_key_g = &credential.master_key_g;
+        if !self.user_infos.contains_key(master_key_g) || !credential.verify(&self.pubkey()) {
+            return Err(CredentialError::NotIssued);
+        }
+        let timestamp = get_timestamp();
+        let msg_hash = revocation_hash(&self.pubkey(), master_key_g, reason, timestamp)?;
+        let signature = self.private_key.sign(msg_hash)?;
+        self.add_blacklist(master_key_g);
+        Ok(RevocationRequest {
+            credential: credential.clone(),
+            reason,
+            timestamp,
+            ca_key: self.pubkey(),
+            signature,
+        })
+    }
+
+    // the current record of the master key
     pub fn get_user_info(&self, key: &Point) -> Option<&UserInfo> {
-        self.user_infos.get(key)
+        self.user_infos.get(key).and_then(|history| history.last())
+    }
+
+    // all the records of the master key, oldest first
+    pub fn get_user_history(&self, key: &Point) -> &[UserInfo] {
+        self.user_infos
+            .get(key)
+            .map_or(&[], |history| history.as_slice())
     }
 }
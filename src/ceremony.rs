@@ -0,0 +1,241 @@
+// This file implements the phase-2 trusted setup ceremony of the Groth16 parameters.
+// Starting from initial parameters of the circuit, every participant multiplies delta
+// by a secret and proves knowledge of it. The coordinator verifies each contribution,
+// and anyone can verify the whole transcript before the final parameters are saved and
+// loaded by every client.
+//
+// The contributions only rerandomize delta, alpha, beta and the queries are taken as
+// they are from the initial parameters, which must therefore be trusted: e.g. the
+// initial zkey of `snarkjs groth16 setup`, which anyone can recompute from the circuit
+// and a public powers-of-tau phase 1. Only then are the final parameters secure if one
+// participant of each phase erased its secret. Initial parameters generated locally
+// (`snarkjs::setup`) are known to whoever generated them, whatever the
+// contributions, and are only suitable for testing.
+
+use ark_bn254::{Bn254, Fr, G1Affine, G2Affine};
+use ark_ec::msm::VariableBaseMSM;
+use ark_ec::{AffineCurve, PairingEngine, ProjectiveCurve};
+use ark_ff::bytes::ToBytes;
+use ark_ff::{Field, PrimeField, UniformRand};
+use ark_groth16::{KeySize, ProvingKey};
+use ark_serialize::*;
+use ethers::utils::keccak256;
+use rand::Rng;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind};
+
+#[derive(Debug, Clone, PartialEq, CanonicalSerialize, CanonicalDeserialize)]
+// A contribution to the parameters, with a Schnorr proof of knowledge of the secret.
+pub struct Contribution {
+    // hash of the parameters before this contribution
+    pub params_hash: Vec<u8>,
+    pub delta_before: G1Affine,
+    pub delta_after: G1Affine,
+    // commitment and response of the Schnorr proof
+    pub commitment: G1Affine,
+    pub response: Fr,
+}
+
+impl Contribution {
+    // challenge of the Schnorr proof
+    fn challenge(&self) -> Fr {
+        let mut bytes = self.params_hash.clone();
+        self.delta_before.write(&mut bytes).unwrap();
+        self.delta_after.write(&mut bytes).unwrap();
+        self.commitment.write(&mut bytes).unwrap();
+        Fr::from_le_bytes_mod_order(&keccak256(bytes))
+    }
+
+    // Verify the proof of knowledge of x where delta_after = x * delta_before.
+    pub fn verify_proof(&self) -> bool {
+        let c = self.challenge();
+        self.delta_before.mul(self.response.into_repr())
+            == self.commitment.into_projective() + self.delta_after.mul(c.into_repr())
+    }
+}
+
+// hash of the parameters, binding a contribution to its input
+pub fn params_hash(params: &ProvingKey<Bn254>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    params.write(&mut bytes).unwrap();
+    keccak256(bytes).to_vec()
+}
+
+// multiply every element by the scalar
+fn scale(points: &[G1Affine], s: &Fr) -> Vec<G1Affine> {
+    let points: Vec<_> = points.iter().map(|p| p.mul(s.into_repr())).collect();
+    ProjectiveCurve::batch_normalization_into_affine(&points)
+}
+
+// random linear combination of the elements
+fn combine(points: &[G1Affine], rho: &[Fr]) -> G1Affine {
+    let scalars: Vec<_> = rho.iter().map(|r| r.into_repr()).collect();
+    VariableBaseMSM::multi_scalar_mul(points, &scalars).into_affine()
+}
+
+// Contribute a fresh secret to the parameters. The secret is dropped on return.
+pub fn contribute<R: Rng>(
+    params: &ProvingKey<Bn254>,
+    rng: &mut R,
+) -> (ProvingKey<Bn254>, Contribution) {
+    let x = Fr::rand(rng);
+    let x_inv = x.inverse().unwrap();
+
+    let mut next = params.clone();
+    next.delta_g1 = params.delta_g1.mul(x.into_repr()).into_affine();
+    next.vk.delta_g2 = params.vk.delta_g2.mul(x.into_repr()).into_affine();
+    next.h_query = scale(&params.h_query, &x_inv);
+    next.l_query = scale(&params.l_query, &x_inv);
+
+    // Schnorr proof of knowledge of x
+    let k = Fr::rand(rng);
+    let mut contribution = Contribution {
+        params_hash: params_hash(params),
+        delta_before: params.delta_g1,
+        delta_after: next.delta_g1,
+        commitment: params.delta_g1.mul(k.into_repr()).into_affine(),
+        response: Fr::from(0u64),
+    };
+    contribution.response = k + contribution.challenge() * x;
+
+    (next, contribution)
+}
+
+// Check that `after` only differs from `before` by a consistent change of delta.
+fn check_update(before: &ProvingKey<Bn254>, after: &ProvingKey<Bn254>) -> Result<(), String> {
+    if before.vk.alpha_g1 != after.vk.alpha_g1
+        || before.vk.beta_g2 != after.vk.beta_g2
+        || before.vk.gamma_g2 != after.vk.gamma_g2
+        || before.vk.gamma_abc_g1 != after.vk.gamma_abc_g1
+        || before.beta_g1 != after.beta_g1
+        || before.a_query != after.a_query
+        || before.b_g1_query != after.b_g1_query
+        || before.b_g2_query != after.b_g2_query
+    {
+        return Err("Parameters other than delta changed".to_string());
+    }
+    if before.h_query.len() != after.h_query.len() || before.l_query.len() != after.l_query.len() {
+        return Err("Invalid query length".to_string());
+    }
+
+    let g1 = G1Affine::prime_subgroup_generator();
+    let g2 = G2Affine::prime_subgroup_generator();
+    if Bn254::pairing(after.delta_g1, g2) != Bn254::pairing(g1, after.vk.delta_g2) {
+        return Err("Inconsistent delta".to_string());
+    }
+
+    // h and l are divided by the same secret as delta is multiplied by
+    let mut rng = rand::thread_rng();
+    for (old, new) in [
+        (&before.h_query, &after.h_query),
+        (&before.l_query, &after.l_query),
+    ] {
+        let rho: Vec<Fr> = (0..old.len()).map(|_| Fr::rand(&mut rng)).collect();
+        if Bn254::pairing(combine(new, &rho), after.vk.delta_g2)
+            != Bn254::pairing(combine(old, &rho), before.vk.delta_g2)
+        {
+            return Err("Inconsistent h or l query".to_string());
+        }
+    }
+    Ok(())
+}
+
+// Verify a single contribution, used by the coordinator before accepting it.
+pub fn verify_contribution(
+    before: &ProvingKey<Bn254>,
+    after: &ProvingKey<Bn254>,
+    contribution: &Contribution,
+) -> Result<(), String> {
+    if contribution.params_hash != params_hash(before)
+        || contribution.delta_before != before.delta_g1
+        || contribution.delta_after != after.delta_g1
+    {
+        return Err("The contribution does not match the parameters".to_string());
+    }
+    if !contribution.verify_proof() {
+        return Err("Invalid proof of knowledge".to_string());
+    }
+    check_update(before, after)
+}
+
+// Verify the whole ceremony, from the initial parameters to the final ones.
+pub fn verify_transcript(
+    initial: &ProvingKey<Bn254>,
+    last: &ProvingKey<Bn254>,
+    contributions: &[Contribution],
+) -> Result<(), String> {
+    if contributions.is_empty() {
+        return Err("No contribution".to_string());
+    }
+    if contributions[0].params_hash != params_hash(initial) {
+        return Err("The transcript does not start from the initial parameters".to_string());
+    }
+    let mut delta = initial.delta_g1;
+    for c in contributions {
+        if c.delta_before != delta || !c.verify_proof() {
+            return Err("Invalid contribution in the transcript".to_string());
+        }
+        delta = c.delta_after;
+    }
+    if delta != last.delta_g1 {
+        return Err("The transcript does not end at the final parameters".to_string());
+    }
+    check_update(initial, last)
+}
+
+// Verify the transcript and save the final parameters for the clients.
+pub fn finalize(
+    initial: &ProvingKey<Bn254>,
+    last: &ProvingKey<Bn254>,
+    contributions: &[Contribution],
+    path: &str,
+) -> Result<(), String> {
+    verify_transcript(initial, last, contributions)?;
+    save_params(last, path).map_err(|e| e.to_string())
+}
+
+// serialization errors are reported as invalid data
+fn invalid_data(e: SerializationError) -> Error {
+    Error::new(ErrorKind::InvalidData, e.to_string())
+}
+
+// Save the parameters to a file.
+pub fn save_params(params: &ProvingKey<Bn254>, path: &str) -> std::io::Result<()> {
+    let file1 = File::create(path.to_owned() + ".s")?;
+    let file2 = File::create(path.to_owned() + ".p")?;
+
+    let w1 = BufWriter::new(file1);
+    params
+        .size()
+        .serialize_unchecked(w1)
+        .map_err(invalid_data)?;
+    let w2 = BufWriter::new(file2);
+    params.write(w2)?;
+
+    Ok(())
+}
+
+// Load the parameters from a file.
+pub fn load_params(path: &str) -> std::io::Result<ProvingKey<Bn254>> {
+    let file1 = File::open(path.to_owned() + ".s")?;
+    let reader1 = BufReader::new(file1);
+    let size = KeySize::deserialize_unchecked(reader1).map_err(invalid_data)?;
+
+    let file2 = File::open(path.to_owned() + ".p")?;
+    let reader2 = BufReader::new(file2);
+    Ok(ProvingKey::<Bn254>::read(reader2, &size))
+}
+
+// Save the contributions of the ceremony, so that anyone can verify it.
+pub fn save_transcript(contributions: &[Contribution], path: &str) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    let w = BufWriter::new(file);
+    contributions.to_vec().serialize(w).map_err(invalid_data)
+}
+
+// Load the contributions of the ceremony.
+pub fn load_transcript(path: &str) -> std::io::Result<Vec<Contribution>> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    Vec::<Contribution>::deserialize(reader).map_err(invalid_data)
+}
@@ -0,0 +1,160 @@
+// This file implements the challenges of verifiers, which bind presentations (identity,
+// set and sybil-resistance proofs) to a verifier so that an observer cannot replay
+// them. A challenge is a random nonce, the domain of the verifier (e.g. the application
+// id) and an expiry time. Its hash (ctx) is folded into the public inputs of the proofs.
+
+use crate::get_timestamp;
+use crate::IdentityManager;
+use baby_jub::poseidon_hash;
+use color_eyre::Result;
+use num_bigint::{BigInt, RandBigInt, Sign, ToBigInt};
+use num_traits::One;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use ethers::{prelude::SignerMiddleware, providers::Middleware, signers::Signer, types::Address};
+use std::sync::Arc;
+
+// bits of ctx carried by the ss input of sybil-resistance proofs
+pub const CTX_BITS: usize = 88;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Challenge {
+    pub nonce: BigInt,
+    // domain of the verifier, e.g. the application id
+    pub domain: BigInt,
+    // unix timestamp in seconds
+    pub expiry: u64,
+}
+
+impl Challenge {
+    // A fresh challenge of the domain, valid for `ttl` seconds.
+    pub fn new(domain: &BigInt, ttl: u64) -> Self {
+        let mut rng = rand::thread_rng();
+        Self {
+            nonce: rng.gen_biguint(128).to_bigint().unwrap(),
+            domain: domain.clone(),
+            expiry: get_timestamp() + ttl,
+        }
+    }
+
+    // Hash of the challenge, the ctx input of the proofs.
+    pub fn ctx(&self) -> Result<BigInt, String> {
+        poseidon_hash(vec![
+            &self.nonce,
+            &self.domain,
+            &self.expiry.to_bigint().unwrap(),
+        ])
+    }
+
+    // the challenge has not expired
+    pub fn is_fresh(&self) -> bool {
+        get_timestamp() <= self.expiry
+    }
+}
+
+// ctx carried by sybil-resistance proofs, truncated to CTX_BITS
+pub fn short_ctx(ctx: &BigInt) -> BigInt {
+    ctx & ((BigInt::one() << CTX_BITS) - 1)
+}
+
+#[derive(Debug, Clone)]
+// Issue challenges of a verifier, and accept each of them once before it expires.
+pub struct Challenger {
+    pub domain: BigInt,
+    // lifetime of the challenges in seconds
+    pub ttl: u64,
+    pending: HashMap<BigInt, Challenge>,
+}
+
+impl Challenger {
+    pub fn new(domain: &BigInt, ttl: u64) -> Self {
+        Self {
+            domain: domain.clone(),
+            ttl,
+            pending: HashMap::new(),
+        }
+    }
+
+    // issue a new challenge
+    pub fn issue(&mut self) -> Challenge {
+        let challenge = Challenge::new(&self.domain, self.ttl);
+        self.pending
+            .insert(challenge.nonce.clone(), challenge.clone());
+        challenge
+    }
+
+    // Issue a new challenge in the identity contract, which accepts it once in a
+    // sybil-resistance proof before it expires. The domain must be the id of the app,
+    // the address of the client.
+    pub async fn issue_on_chain<M: Middleware + 'static, S: Signer + 'static>(
+        &mut self,
+        contract_address: &str,
+        client: Arc<SignerMiddleware<M, S>>,
+    ) -> Result<Challenge> {
+        let appid = BigInt::from_bytes_be(Sign::Plus, client.address().as_bytes());
+        if appid != self.domain {
+            return Err(eyre::eyre!("The domain is not the address of the app"));
+        }
+        let address = contract_address.parse::<Address>()?;
+        let contract = IdentityManager::new(address, client.clone());
+        let challenge = self.issue();
+        let ctx = challenge.ctx().map_err(|e| eyre::eyre!(e))?;
+        // send transaction
+        let _res = contract.do_issue_challenge(&ctx, challenge.expiry).await?;
+        Ok(challenge)
+    }
+
+    // Check that the challenge was issued here and has not expired. It is removed,
+    // so that a presentation cannot be accepted twice.
+    pub fn consume(&mut self, challenge: &Challenge) -> Result<(), String> {
+        if self.pending.get(&challenge.nonce) != Some(challenge) {
+            return Err("Unknown challenge".to_string());
+        }
+        let issued = self.pending.remove(&challenge.nonce).unwrap();
+        if !issued.is_fresh() {
+            return Err("Expired challenge".to_string());
+        }
+        Ok(())
+    }
+
+    // remove the expired challenges
+    pub fn prune(&mut self) {
+        self.pending.retain(|_, c| c.is_fresh());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{short_ctx, Challenge, Challenger, CTX_BITS};
+    use num_bigint::BigInt;
+
+    #[test]
+    // challenges are accepted once, before they expire
+    fn test_challenge() {
+        let domain = BigInt::from(42);
+        let mut challenger = Challenger::new(&domain, 60);
+        let c1 = challenger.issue();
+        let c2 = challenger.issue();
+        assert!(c1.is_fresh());
+        assert_ne!(c1.ctx().unwrap(), c2.ctx().unwrap());
+        assert!(short_ctx(&c1.ctx().unwrap()).bits() <= CTX_BITS as u64);
+
+        assert!(challenger.consume(&c1).is_ok());
+        assert!(challenger.consume(&c1).is_err());
+
+        // a challenge cannot be altered
+        let mut forged = c2.clone();
+        forged.expiry += 60;
+        assert!(challenger.consume(&forged).is_err());
+        assert!(challenger.consume(&c2).is_ok());
+
+        // expired or foreign challenges are rejected
+        let expired = Challenge {
+            expiry: 0,
+            ..Challenge::new(&domain, 0)
+        };
+        assert!(!expired.is_fresh());
+        assert!(challenger.consume(&Challenge::new(&domain, 60)).is_err());
+    }
+}
@@ -3,17 +3,26 @@
 // node management, identity contract management, and identity management (audit,
 // trace, revoke, witch resistance, identity verification), etc.
 
-use crate::merkle_tree::{DualTree, MerkleTree};
-use crate::tpke::PublicKey;
-use crate::user_client::{ApplicationKey, IdentityRequest};
+use crate::attribute_set::AttributeSet;
+use crate::ca_client::{ca_hash, generators_hash, RevocationRequest};
+use crate::challenge::Challenge;
+use crate::contract_gen::gen_manager;
+use crate::dkg::DkgOutput;
+use crate::get_timestamp;
+use crate::merkle_tree::{DualTree, MerkleTree, BNMAX, ZERO};
+use crate::predicate::Assertion;
+use crate::presentation::{CircuitId, Verifier};
+use crate::reshare::Resharer;
+use crate::snarkjs::setup;
+use crate::tpke::{DecryptShard, DleqProof, PublicKey, SecretShare};
+use crate::tree_store::{DiskStore, MemoryStore, TreeStore};
+use crate::user_client::{appkey_ss, lrcm_hash, ApplicationKey, IdentityRequest};
 use crate::{BabyPoint, IdentityFullMeta, IdentityManager};
 use ark_bn254::Bn254;
 use ark_bn254::Fr;
 use ark_circom::{CircomBuilder, CircomConfig};
 use ark_ff::bytes::ToBytes;
-use ark_groth16::{
-    generate_random_parameters, prepare_verifying_key, verify_proof, KeySize, Proof, ProvingKey,
-};
+use ark_groth16::{prepare_verifying_key, verify_proof, KeySize, Proof, ProvingKey};
 use ark_serialize::*;
 use baby_jub::{new_key, poseidon_hash, Point, G};
 use color_eyre::Result;
@@ -21,8 +30,10 @@ use num_bigint::{BigInt, ToBigInt};
 use postcard::{from_bytes, to_stdvec};
 use rand::thread_rng;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::fs::File;
+use std::str::FromStr;
 
 use std::io::{BufReader, BufWriter};
 
@@ -35,10 +46,22 @@ use std::sync::Arc;
 pub struct Committee {
     // Private key shard used in threshold public key encryption.
     pub tpke_sec: BigInt,
+    // index of the private key shard (Shamir share), 0 for an additive share
+    pub tpke_index: usize,
+    // number of shards required to decrypt
+    pub tpke_threshold: usize,
+    // public key shards of all members, used to verify decryption shards
+    pub tpke_shards: Vec<Point>,
     // A Merkle tree used to store the list of CAs.
-    pub ca_tree: MerkleTree,
+    pub ca_tree: MerkleTree<TreeStore>,
+    // hashes of the pedersen generators of the CAs, checked in identity proofs
+    pub ca_generators: HashMap<Point, BigInt>,
+    // hashes of the schemas of the credentials of the CAs, checked in identity proofs
+    pub ca_schemas: HashMap<Point, BigInt>,
     // A Merkle tree used to store the list of revoked credentials.
-    pub block_tree: DualTree,
+    pub block_tree: DualTree<TreeStore>,
+    // the last revocation request of a CA accepted for each master key
+    pub ca_revocations: HashMap<Point, RevocationRequest>,
     // zero kownledge proofs
     pub zkp_cfg: CircomConfig<Bn254>,
     pub zkp_params: ProvingKey<Bn254>,
@@ -46,77 +69,265 @@ pub struct Committee {
     pub app_params: ProvingKey<Bn254>,
     pub pedersen_cfg: CircomConfig<Bn254>,
     pub pedersen_params: ProvingKey<Bn254>,
+    pub set_cfg: CircomConfig<Bn254>,
+    pub set_params: ProvingKey<Bn254>,
+    // number of identity attributes of the identity circuit
+    pub attribute_num: usize,
     // public key of tpke encryption
     pub tpke_key: Option<PublicKey>,
+    // snapshots of the roots, keyed by the version of the roots hash
+    pub root_history: BTreeMap<u64, RootSnapshot>,
+    // leaves of the CA tree and block tree0 in the latest snapshot, the trees of
+    // older snapshots are rebuilt from them with the diffs of the snapshots
+    pub snapshot_leaves: (Vec<BigInt>, Vec<BigInt>),
+    // maximum number of retained snapshots
+    pub history_size: usize,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CommitteePart1 {
     pub tpke_sec: BigInt,
-    pub ca_tree: MerkleTree,
-    pub block_tree: DualTree,
+    pub tpke_index: usize,
+    pub tpke_threshold: usize,
+    pub tpke_shards: Vec<Point>,
+    pub ca_tree: MerkleTree<TreeStore>,
+    pub ca_generators: HashMap<Point, BigInt>,
+    pub ca_schemas: HashMap<Point, BigInt>,
+    pub block_tree: DualTree<TreeStore>,
+    pub ca_revocations: HashMap<Point, RevocationRequest>,
+    pub attribute_num: usize,
     pub tpke_key: Option<PublicKey>,
+    pub root_history: BTreeMap<u64, RootSnapshot>,
+    pub snapshot_leaves: (Vec<BigInt>, Vec<BigInt>),
+    pub history_size: usize,
 }
 
-impl Committee {
-    // Initialize a committee node.
-    pub fn new() -> Self {
-        // generate shard of tpke private key
-        let tpke_sec = new_key().scalar_key();
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// The roots submitted to the chain under a version.
+pub struct RootSnapshot {
+    pub version: u64,
+    // roots of the CA tree and of the two block trees
+    pub roots: (BigInt, BigInt, BigInt),
+    // roots hashes sent to the contract
+    pub roots_hash: (BigInt, BigInt),
+    // changes of the leaves of the CA tree and block tree0 in the next snapshot
+    pub ca_diff: LeafDiff,
+    pub block_diff: LeafDiff,
+}
 
-        // TODO: make trust setup
-        //
-        let cfg = CircomConfig::<Bn254>::load(
-            "./circuits/pseudonym_check.so",
-            "./circuits/pseudonym_check.r1cs",
-        )
-        .unwrap_or_else(|error| {
-            panic!("{:?}", error);
-        });
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+// Leaves added and removed between two snapshots.
+pub struct LeafDiff {
+    pub added: Vec<BigInt>,
+    pub removed: Vec<BigInt>,
+}
+
+impl LeafDiff {
+    // difference between two sorted lists of leaves
+    pub fn new(old: &[BigInt], new: &[BigInt]) -> Self {
+        let mut diff = LeafDiff::default();
+        let (mut i, mut j) = (0, 0);
+        while i < old.len() || j < new.len() {
+            if j == new.len() || (i < old.len() && old[i] < new[j]) {
+                diff.removed.push(old[i].clone());
+                i += 1;
+            } else if i == old.len() || new[j] < old[i] {
+                diff.added.push(new[j].clone());
+                j += 1;
+            } else {
+                i += 1;
+                j += 1;
+            }
+        }
+        diff
+    }
+
+    // turn the sorted new leaves back into the old ones
+    pub fn revert(&self, leaves: &mut Vec<BigInt>) {
+        for x in &self.added {
+            if let Ok(i) = leaves.binary_search(x) {
+                leaves.remove(i);
+            }
+        }
+        for x in &self.removed {
+            let i = leaves.binary_search(x).unwrap_or_else(|i| i);
+            leaves.insert(i, x.clone());
+        }
+    }
+}
+
+// Open the named tree in the database, moving the nodes of `tree` into it if it is
+// empty. `fresh` is the root of the tree before any insertion.
+fn open_store(
+    tree: &MerkleTree<TreeStore>,
+    fresh: &BigInt,
+    path: &str,
+    name: &str,
+) -> Result<TreeStore, String> {
+    let mut store = TreeStore::Disk(DiskStore::open(path, name)?);
+    let disk = MerkleTree::with_store(tree.tiers, store.clone());
+    if disk.is_empty()? {
+        tree.copy_into(&mut store)?;
+    } else if tree.root()? != *fresh && tree.root()? != disk.root()? {
+        return Err(format!("The {} in the database does not match", name));
+    }
+    Ok(store)
+}
+
+// circuits of the pseudonym, sybil resistance, identity and set proofs
+fn load_configs() -> (
+    CircomConfig<Bn254>,
+    CircomConfig<Bn254>,
+    CircomConfig<Bn254>,
+    CircomConfig<Bn254>,
+) {
+    let cfg = CircomConfig::<Bn254>::load(
+        "./circuits/pseudonym_check.so",
+        "./circuits/pseudonym_check.r1cs",
+    )
+    .unwrap_or_else(|error| {
+        panic!("{:?}", error);
+    });
+
+    let app_cfg =
+        CircomConfig::<Bn254>::load("./circuits/sybil_check.so", "./circuits/sybil_check.r1cs")
+            .unwrap_or_else(|error| {
+                panic!("{:?}", error);
+            });
+
+    let pedersen_cfg = CircomConfig::<Bn254>::load(
+        "./circuits/pedersen_commit.so",
+        "./circuits/pedersen_commit.r1cs",
+    )
+    .unwrap_or_else(|error| {
+        panic!("{:?}", error);
+    });
+
+    let set_cfg = CircomConfig::<Bn254>::load(
+        "./circuits/attribute_set.so",
+        "./circuits/attribute_set.r1cs",
+    )
+    .unwrap_or_else(|error| {
+        panic!("{:?}", error);
+    });
+
+    (cfg, app_cfg, pedersen_cfg, set_cfg)
+}
+
+impl Committee {
+    // Initialize a committee node. The zero-knowledge proof parameters are generated
+    // locally, which is only suitable for testing. Nodes of a real committee use
+    // `with_params` with the parameters of a trusted setup ceremony. The identity
+    // circuit (circuits/pedersen_commit.circom) must be compiled for `attribute_num`.
+    pub fn new(attribute_num: usize) -> Self {
+        let (cfg, app_cfg, pedersen_cfg, set_cfg) = load_configs();
 
         let builder = CircomBuilder::new(cfg.clone());
         let circom = builder.setup();
 
         let mut rng = thread_rng();
-        let params = generate_random_parameters::<Bn254, _, _>(circom, &mut rng).unwrap();
-
-        let app_cfg =
-            CircomConfig::<Bn254>::load("./circuits/sybil_check.so", "./circuits/sybil_check.r1cs")
-                .unwrap_or_else(|error| {
-                    panic!("{:?}", error);
-                });
+        let params = setup(circom, &mut rng).unwrap();
 
         let builder = CircomBuilder::new(app_cfg.clone());
         let circom = builder.setup();
 
         let mut rng = thread_rng();
-        let app_params = generate_random_parameters::<Bn254, _, _>(circom, &mut rng).unwrap();
-
-        let pedersen_cfg = CircomConfig::<Bn254>::load(
-            "./circuits/pedersen_commit.so",
-            "./circuits/pedersen_commit.r1cs",
-        )
-        .unwrap_or_else(|error| {
-            panic!("{:?}", error);
-        });
+        let app_params = setup(circom, &mut rng).unwrap();
 
         let builder = CircomBuilder::new(pedersen_cfg.clone());
         let circom = builder.setup();
 
         let mut rng = thread_rng();
-        let pedersen_params = generate_random_parameters::<Bn254, _, _>(circom, &mut rng).unwrap();
+        let pedersen_params = setup(circom, &mut rng).unwrap();
+
+        let builder = CircomBuilder::new(set_cfg.clone());
+        let circom = builder.setup();
+
+        let mut rng = thread_rng();
+        let set_params = setup(circom, &mut rng).unwrap();
+
+        Self::assemble(
+            cfg,
+            params,
+            app_cfg,
+            app_params,
+            pedersen_cfg,
+            pedersen_params,
+            set_cfg,
+            set_params,
+            attribute_num,
+        )
+    }
+
+    // Initialize a committee node with shared parameters, e.g. loaded by
+    // `ceremony::load_params`.
+    pub fn with_params(
+        attribute_num: usize,
+        zkp_params: ProvingKey<Bn254>,
+        app_params: ProvingKey<Bn254>,
+        pedersen_params: ProvingKey<Bn254>,
+        set_params: ProvingKey<Bn254>,
+    ) -> Self {
+        let (cfg, app_cfg, pedersen_cfg, set_cfg) = load_configs();
+        Self::assemble(
+            cfg,
+            zkp_params,
+            app_cfg,
+            app_params,
+            pedersen_cfg,
+            pedersen_params,
+            set_cfg,
+            set_params,
+            attribute_num,
+        )
+    }
+
+    // create a committee node with a new shard of tpke private key
+    #[allow(clippy::too_many_arguments)]
+    fn assemble(
+        zkp_cfg: CircomConfig<Bn254>,
+        zkp_params: ProvingKey<Bn254>,
+        app_cfg: CircomConfig<Bn254>,
+        app_params: ProvingKey<Bn254>,
+        pedersen_cfg: CircomConfig<Bn254>,
+        pedersen_params: ProvingKey<Bn254>,
+        set_cfg: CircomConfig<Bn254>,
+        set_params: ProvingKey<Bn254>,
+        attribute_num: usize,
+    ) -> Self {
+        // generate shard of tpke private key
+        let tpke_sec = new_key().scalar_key();
 
         Self {
             tpke_sec,
-            ca_tree: MerkleTree::new(31),  // 20
-            block_tree: DualTree::new(41), // 32
-            zkp_cfg: cfg,
-            zkp_params: params,
+            // an additive share of a key built with `PublicKey::new`, until a Shamir
+            // share is set with `update_tpke_share`
+            tpke_index: 0,
+            tpke_threshold: 0,
+            tpke_shards: Vec::new(),
+            ca_tree: MerkleTree::with_store(31, TreeStore::Memory(MemoryStore::new(31))), // 20
+            ca_generators: HashMap::new(),
+            ca_schemas: HashMap::new(),
+            block_tree: DualTree::with_stores(
+                41,
+                TreeStore::Memory(MemoryStore::new(41)),
+                TreeStore::Memory(MemoryStore::new(41)),
+            )
+            .unwrap(), // 32
+            ca_revocations: HashMap::new(),
+            zkp_cfg,
+            zkp_params,
             app_cfg,
             app_params,
             pedersen_cfg,
             pedersen_params,
+            set_cfg,
+            set_params,
+            attribute_num,
             tpke_key: None,
+            root_history: BTreeMap::new(),
+            snapshot_leaves: (Vec::new(), Vec::new()),
+            history_size: 16,
         }
     }
 
@@ -124,9 +335,19 @@ impl Committee {
     pub fn part1(&self) -> CommitteePart1 {
         CommitteePart1 {
             tpke_sec: self.tpke_sec.clone(),
+            tpke_index: self.tpke_index,
+            tpke_threshold: self.tpke_threshold,
+            tpke_shards: self.tpke_shards.clone(),
             ca_tree: self.ca_tree.clone(),
+            ca_generators: self.ca_generators.clone(),
+            ca_schemas: self.ca_schemas.clone(),
             block_tree: self.block_tree.clone(),
+            ca_revocations: self.ca_revocations.clone(),
+            attribute_num: self.attribute_num,
             tpke_key: self.tpke_key.clone(),
+            root_history: self.root_history.clone(),
+            snapshot_leaves: self.snapshot_leaves.clone(),
+            history_size: self.history_size,
         }
     }
 
@@ -156,15 +377,26 @@ impl Committee {
         self.app_params.write(w6).unwrap();
         let w7 = BufWriter::new(file7);
         self.pedersen_params.write(w7).unwrap();
+        let file8 = File::create(path.to_owned() + ".s4")?;
+        let file9 = File::create(path.to_owned() + ".p4")?;
+        let w8 = BufWriter::new(file8);
+        self.set_params.size().serialize_unchecked(w8).unwrap();
+        let w9 = BufWriter::new(file9);
+        self.set_params.write(w9).unwrap();
 
         Ok(())
     }
 
-    // load committee data from a file
+    // Load committee data from a file. Data saved in an older format is rejected.
     pub fn load(path: &str) -> std::io::Result<Self> {
         // load part 1
         let p1_data = fs::read(path.to_owned() + ".dat")?;
-        let p1: CommitteePart1 = from_bytes(&p1_data).unwrap();
+        let p1: CommitteePart1 = from_bytes(&p1_data).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Unsupported committee data",
+            )
+        })?;
 
         let file2 = File::open(path.to_owned() + ".s1")?;
         let reader2 = BufReader::new(file2);
@@ -188,47 +420,203 @@ impl Committee {
         let reader7 = BufReader::new(file7);
         let pedersen_params = ProvingKey::<Bn254>::read(reader7, &pedersen_size);
 
-        let zkp_cfg = CircomConfig::<Bn254>::load(
-            "./circuits/pseudonym_check.so",
-            "./circuits/pseudonym_check.r1cs",
-        )
-        .unwrap_or_else(|error| {
-            panic!("{:?}", error);
-        });
-
-        let app_cfg =
-            CircomConfig::<Bn254>::load("./circuits/sybil_check.so", "./circuits/sybil_check.r1cs")
-                .unwrap_or_else(|error| {
-                    panic!("{:?}", error);
-                });
-
-        let pedersen_cfg = CircomConfig::<Bn254>::load(
-            "./circuits/pedersen_commit.so",
-            "./circuits/pedersen_commit.r1cs",
-        )
-        .unwrap_or_else(|error| {
-            panic!("{:?}", error);
-        });
+        let file8 = File::open(path.to_owned() + ".s4")?;
+        let reader8 = BufReader::new(file8);
+        let set_size = KeySize::deserialize_unchecked(reader8).unwrap();
+        let file9 = File::open(path.to_owned() + ".p4")?;
+        let reader9 = BufReader::new(file9);
+        let set_params = ProvingKey::<Bn254>::read(reader9, &set_size);
+
+        let (zkp_cfg, app_cfg, pedersen_cfg, set_cfg) = load_configs();
 
         Ok(Committee {
             tpke_sec: p1.tpke_sec,
+            tpke_index: p1.tpke_index,
+            tpke_threshold: p1.tpke_threshold,
+            tpke_shards: p1.tpke_shards,
             ca_tree: p1.ca_tree,
+            ca_generators: p1.ca_generators,
+            ca_schemas: p1.ca_schemas,
             block_tree: p1.block_tree,
+            ca_revocations: p1.ca_revocations,
             zkp_cfg,
             zkp_params,
             app_cfg,
             app_params,
             pedersen_cfg,
             pedersen_params,
+            set_cfg,
+            set_params,
+            attribute_num: p1.attribute_num,
             tpke_key: p1.tpke_key,
+            root_history: p1.root_history,
+            snapshot_leaves: p1.snapshot_leaves,
+            history_size: p1.history_size,
         })
     }
 
+    // Keep the Merkle trees in the database at `path` instead of memory, and only save
+    // their location in the committee data afterwards. The current trees are moved
+    // into a fresh database, trees already in the database are reopened. It fails if
+    // the database and the committee hold different non-empty trees.
+    pub fn open_trees(&mut self, path: &str) -> Result<(), String> {
+        let ca_fresh = MerkleTree::new(self.ca_tree.tiers).root()?;
+        let (block_fresh0, block_fresh1) = DualTree::new(self.block_tree.tree0.tiers).roots()?;
+        let ca_store = open_store(&self.ca_tree, &ca_fresh, path, "ca_tree")?;
+        let block_store0 = open_store(&self.block_tree.tree0, &block_fresh0, path, "block_tree0")?;
+        let block_store1 = open_store(&self.block_tree.tree1, &block_fresh1, path, "block_tree1")?;
+        self.ca_tree = MerkleTree::with_store(self.ca_tree.tiers, ca_store);
+        self.block_tree =
+            DualTree::with_stores(self.block_tree.tree0.tiers, block_store0, block_store1)?;
+        Ok(())
+    }
+
+    // Generate the identity contract with the verifying keys of this committee.
+    pub fn gen_contract(&self, template_path: &str, out_path: &str) -> Result<(), String> {
+        let template = fs::read_to_string(template_path).map_err(|e| e.to_string())?;
+        let source = gen_manager(
+            &template,
+            &self.zkp_params.vk,
+            &self.app_params.vk,
+            &self.pedersen_params.vk,
+            self.attribute_num,
+        )?;
+        fs::write(out_path, source).map_err(|e| e.to_string())
+    }
+
+    // Add a CA to the CA tree with its pedersen generators and the hash of its schema.
+    // The CA must issue credentials with the number of attributes of the identity
+    // circuit. Adding a CA again updates its schema.
+    pub fn add_ca(
+        &mut self,
+        ca_key: &Point,
+        generators: &[Point],
+        schema_hash: &BigInt,
+    ) -> Result<(), String> {
+        if generators.len() != self.attribute_num {
+            return Err("The CA does not match the number of attributes".to_string());
+        }
+        let hash = generators_hash(generators)?;
+        if !self.ca_tree.contains(&ca_key.scalar_y())? {
+            self.ca_tree.insert_nodes(vec![ca_key.scalar_y()])?;
+        }
+        self.ca_generators.insert(ca_key.clone(), hash);
+        self.ca_schemas.insert(ca_key.clone(), schema_hash.clone());
+        Ok(())
+    }
+
+    // Hash of the generators and the schema of a CA, bound into identity and set proofs.
+    pub fn ca_hash(&self, ca_key: &Point) -> Result<BigInt, String> {
+        let generators = self.ca_generators.get(ca_key).ok_or("Unknown CA")?;
+        let schema = self.ca_schemas.get(ca_key).ok_or("Unknown CA")?;
+        ca_hash(generators, schema)
+    }
+
+    // Verify a revocation request of a CA: the CA is in the CA tree, the request and
+    // the revoked credential are signed by it, and the request is newer than the last
+    // one accepted for the master key, so that it cannot be replayed after the
+    // credential is reinstated.
+    pub fn verify_revocation(&self, req: &RevocationRequest) -> Result<(), String> {
+        if !self.ca_tree.contains(&req.ca_key.scalar_y())? {
+            return Err("Untrusted CA".to_string());
+        }
+        if !req.verify() {
+            return Err("Invalid signature".to_string());
+        }
+        if req.timestamp > get_timestamp() {
+            return Err("Invalid timestamp".to_string());
+        }
+        match self.ca_revocations.get(&req.credential.master_key_g) {
+            Some(last) if last.timestamp >= req.timestamp => {
+                Err("Stale revocation request".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Verify a batch of revocation requests, with at most one request for each master
+    // key, and return the keys not yet in the block tree.
+    fn verify_revocations(&self, reqs: &[RevocationRequest]) -> Result<Vec<BigInt>, String> {
+        let mut revoked = Vec::new();
+        for (i, req) in reqs.iter().enumerate() {
+            self.verify_revocation(req)?;
+            let master_key_g = &req.credential.master_key_g;
+            if reqs[..i]
+                .iter()
+                .any(|r| r.credential.master_key_g == *master_key_g)
+            {
+                return Err("Duplicate revocation request".to_string());
+            }
+            let key = master_key_g.scalar_y();
+            if !self.block_tree.tree0.contains(&key)? {
+                revoked.push(key);
+            }
+        }
+        Ok(revoked)
+    }
+
+    // Add the credentials of the revocation requests to the block tree. Nothing is
+    // revoked if any of the requests is invalid.
+    pub fn apply_revocations(&mut self, reqs: &[RevocationRequest]) -> Result<(), String> {
+        let revoked = self.verify_revocations(reqs)?;
+        self.block_tree.insert_nodes(revoked)?;
+        self.record_revocations(reqs);
+        Ok(())
+    }
+
+    // remember the last accepted request of each master key
+    fn record_revocations(&mut self, reqs: &[RevocationRequest]) {
+        for req in reqs {
+            self.ca_revocations
+                .insert(req.credential.master_key_g.clone(), req.clone());
+        }
+    }
+
     // shard of tpke public key
     pub fn tpke_shard(&self) -> Point {
         &self.tpke_sec * G.clone()
     }
 
+    // Update the private key shard, e.g. a share dealt by `tpke::gen_key_shares`.
+    pub fn update_tpke_share(&mut self, share: SecretShare, threshold: usize) {
+        self.tpke_sec = share.value;
+        self.tpke_index = share.index;
+        self.tpke_threshold = threshold;
+    }
+
+    // Use the share and the joint public key produced by the DKG protocol.
+    pub fn apply_dkg(&mut self, output: DkgOutput) {
+        self.update_tpke_share(output.share, output.threshold);
+        self.update_tpke_shards(output.public_shards);
+        self.update_tpke_key(output.public_key);
+    }
+
+    // Deal the private key shard to a new committee (see `reshare`), used after
+    // members are added or removed.
+    pub fn resharer(
+        &self,
+        new_threshold: usize,
+        new_peers: Vec<Point>,
+    ) -> Result<Resharer, String> {
+        Resharer::new(
+            SecretShare {
+                index: self.tpke_index,
+                value: self.tpke_sec.clone(),
+            },
+            new_threshold,
+            new_peers,
+        )
+    }
+
+    // Update public key shards of all members. With additive shares, all the members
+    // are needed to decrypt.
+    pub fn update_tpke_shards(&mut self, tpke_shards: Vec<Point>) {
+        if self.tpke_index == 0 {
+            self.tpke_threshold = tpke_shards.len();
+        }
+        self.tpke_shards = tpke_shards;
+    }
+
     // update tpke public key
     pub fn update_tpke_key(&mut self, tpke_key: PublicKey) {
         self.tpke_key = Some(tpke_key);
@@ -249,6 +637,11 @@ impl Committee {
         self.pedersen_params = pedersen_params;
     }
 
+    // Update the parameters of zero-knowledge proof.
+    pub fn update_set_param(&mut self, set_params: ProvingKey<Bn254>) {
+        self.set_params = set_params;
+    }
+
     // Verify the zero-knowledge proof for pseudonym registration.
     pub fn verify_key_request(&self, req: &IdentityRequest) -> bool {
         let pvk = prepare_verifying_key(&self.zkp_params.vk);
@@ -263,6 +656,28 @@ impl Committee {
         verify_proof(&pvk, &appkey.proof, &appkey.pub_inputs).unwrap()
     }
 
+    // Verify a sybil-resistance proof presented to the verifier of the challenge, the
+    // domain of the challenge is the application id.
+    pub fn verify_app_key_challenge(
+        &self,
+        appkey: &ApplicationKey,
+        challenge: &Challenge,
+    ) -> Result<bool, String> {
+        let tpke_key = self.tpke_key.as_ref().ok_or("No tpke key")?;
+        let ctx = challenge.ctx()?;
+        if !challenge.is_fresh() || challenge.domain != appkey.appid || appkey.ctx != ctx {
+            return Ok(false);
+        }
+        let ss = appkey_ss(&appkey.appid, tpke_key, &appkey.cipher.c2, &ctx);
+        if appkey.pub_inputs.len() != 4
+            || Fr::from_str(&appkey.appkey.to_string()).ok() != Some(appkey.pub_inputs[0])
+            || Fr::from_str(&ss.to_string()).ok() != Some(appkey.pub_inputs[1])
+        {
+            return Ok(false);
+        }
+        Ok(self.verify_app_key(appkey))
+    }
+
     // Verify the zero-knowledge proof for identity check
     pub fn verify_identity_proof(&self, public_inputs: Vec<Fr>, proof: &Proof<Bn254>) -> bool {
         let pvk = prepare_verifying_key(&self.pedersen_params.vk);
@@ -270,9 +685,118 @@ impl Committee {
         verify_proof(&pvk, proof, &public_inputs).unwrap()
     }
 
-    // get decryption shard of tpke decryption
-    pub fn decrypt_shard(&self, c1: &Point) -> Point {
-        &self.tpke_sec * c1
+    // Verify that an identity proof asserts the ranges for a credential of the CA, and
+    // is bound to the challenge, which must not have expired.
+    pub fn verify_identity_assertion(
+        &self,
+        ca_key: &Point,
+        l_range: &[BigInt],
+        r_range: &[BigInt],
+        challenge: &Challenge,
+        public_inputs: Vec<Fr>,
+        proof: &Proof<Bn254>,
+    ) -> Result<bool, String> {
+        let ca_hash = self.ca_hash(ca_key)?;
+        let lrcm = lrcm_hash(l_range, r_range, &ca_hash, &challenge.ctx()?)?;
+        Ok(self.verify_lrcm(&lrcm, challenge, public_inputs, proof))
+    }
+
+    // Verify that an identity proof proves the assertion for a credential of the CA.
+    // The schema of the assertion must be the one registered for the CA, and is bound
+    // into the lrcm of the proof.
+    pub fn verify_assertion(
+        &self,
+        ca_key: &Point,
+        assertion: &Assertion,
+        challenge: &Challenge,
+        public_inputs: Vec<Fr>,
+        proof: &Proof<Bn254>,
+    ) -> Result<bool, String> {
+        let generators = self.ca_generators.get(ca_key).ok_or("Unknown CA")?;
+        let schema = self.ca_schemas.get(ca_key).ok_or("Unknown CA")?;
+        if *schema != assertion.schema.hash() {
+            return Ok(false);
+        }
+        let lrcm = assertion.lrcm(generators, &challenge.ctx()?)?;
+        Ok(self.verify_lrcm(&lrcm, challenge, public_inputs, proof))
+    }
+
+    // check that a fresh identity proof outputs the expected lrcm
+    fn verify_lrcm(
+        &self,
+        lrcm: &BigInt,
+        challenge: &Challenge,
+        public_inputs: Vec<Fr>,
+        proof: &Proof<Bn254>,
+    ) -> bool {
+        if !challenge.is_fresh()
+            || public_inputs.len() != 3
+            || Fr::from_str(&lrcm.to_string()).ok() != Some(public_inputs[2])
+        {
+            return false;
+        }
+        self.verify_identity_proof(public_inputs, proof)
+    }
+
+    // Verify that a set proof proves the attribute at `index` of a credential of the
+    // CA is (or is not) in the set, and is bound to the challenge.
+    pub fn verify_set_proof(
+        &self,
+        ca_key: &Point,
+        index: usize,
+        set: &AttributeSet,
+        challenge: &Challenge,
+        public_inputs: Vec<Fr>,
+        proof: &Proof<Bn254>,
+    ) -> Result<bool, String> {
+        let ca_hash = self.ca_hash(ca_key)?;
+        if !challenge.is_fresh() {
+            return Ok(false);
+        }
+        let commitments = set
+            .commitments(index, &ca_hash, &challenge.ctx()?)?
+            .iter()
+            .map(|scm| Fr::from_str(&scm.to_string()).ok())
+            .collect::<Vec<_>>();
+        if public_inputs.len() != 3 || !commitments.contains(&Some(public_inputs[2])) {
+            return Ok(false);
+        }
+        let pvk = prepare_verifying_key(&self.set_params.vk);
+
+        Ok(verify_proof(&pvk, proof, &public_inputs).unwrap())
+    }
+
+    // Export the verifying keys and the trusted CAs, used by dapps to verify
+    // presentations off-chain.
+    pub fn verifier(&self) -> Verifier {
+        let mut vks = HashMap::new();
+        vks.insert(CircuitId::Identity, self.pedersen_params.vk.clone());
+        vks.insert(CircuitId::AttributeSet, self.set_params.vk.clone());
+        vks.insert(CircuitId::AppKey, self.app_params.vk.clone());
+        Verifier {
+            vks,
+            ca_hashes: self.ca_generators.clone(),
+            ca_schemas: self.ca_schemas.clone(),
+            tpke_key: self.tpke_key.as_deref().cloned(),
+        }
+    }
+
+    // get decryption shard of tpke decryption, with a proof of correctness
+    pub fn decrypt_shard(&self, c1: &Point) -> DecryptShard {
+        let shard = &self.tpke_sec * c1;
+        let proof = DleqProof::new(&self.tpke_sec, &self.tpke_shard(), c1, &shard);
+        DecryptShard {
+            index: self.tpke_index,
+            shard,
+            proof,
+        }
+    }
+
+    // Verify a decryption shard submitted by another member.
+    pub fn verify_decrypt_shard(&self, c1: &Point, shard: &DecryptShard) -> bool {
+        shard.index >= 1
+            && shard.index <= self.tpke_shards.len()
+            && shard.verify(&self.tpke_shards[shard.index - 1], c1)
     }
 
     // Add a member to the committee.
@@ -297,22 +821,139 @@ impl Committee {
         Ok(())
     }
 
-    // Update the latest Merkle tree root on the blockchain.
-    pub async fn update_roots_hash<M: Middleware + 'static, S: Signer + 'static>(
+    // Remove a member from the committee, the tpke key should be reshared afterwards.
+    pub async fn remove_committee<M: Middleware + 'static, S: Signer + 'static>(
         &self,
-        version: u64,
+        cm: Address,
         contract_address: &str,
         client: Arc<SignerMiddleware<M, S>>,
     ) -> Result<()> {
         let address = contract_address.parse::<Address>()?;
         let contract = IdentityManager::new(address, client.clone());
-        let (root1, root2) = self.block_tree.roots();
-        let root_ca = self.ca_tree.root();
-        let y = self.tpke_key.as_deref().unwrap().scalar_y();
+
+        // send transaction
+        let _res = contract.remove_committee(cm).send().await?.await?;
+        let _res = _res.unwrap();
+        println!(
+            "tx_hash: {:?}, Gas_used: {:?}",
+            _res.transaction_hash, _res.gas_used
+        );
+        Ok(())
+    }
+
+    // hashes of the current tree roots, as sent to the contract
+    pub fn roots_hash(&self) -> Result<(BigInt, BigInt), String> {
+        let y = self
+            .tpke_key
+            .as_deref()
+            .ok_or("The tpke public key is not set")?
+            .scalar_y();
+        let (root1, root2) = self.block_tree.roots()?;
+        let root_ca = self.ca_tree.root()?;
 
         // Calculate the hash of these tree roots.
-        let rh1 = poseidon_hash(vec![&root1, &root_ca, &y]).unwrap();
-        let rh2 = poseidon_hash(vec![&root2, &root_ca, &y]).unwrap();
+        let rh1 = poseidon_hash(vec![&root1, &root_ca, &y])?;
+        let rh2 = poseidon_hash(vec![&root2, &root_ca, &y])?;
+        Ok((rh1, rh2))
+    }
+
+    // the version must be newer than the latest snapshot
+    fn check_roots_version(&self, version: u64) -> Result<(), String> {
+        match self.root_history.keys().next_back() {
+            Some(latest) if version <= *latest => Err("Stale roots version".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    // Save a snapshot of the current roots under the version, so that proofs can
+    // still be generated against these roots after the trees change. Only the leaves
+    // of the latest snapshot are kept, with the leaf changes between snapshots, and
+    // only the latest `history_size` snapshots are retained.
+    pub fn snapshot_roots(&mut self, version: u64) -> Result<&RootSnapshot, String> {
+        self.check_roots_version(version)?;
+        let roots_hash = self.roots_hash()?;
+        let (root1, root2) = self.block_tree.roots()?;
+        let ca_leaves = self.ca_tree.leaves()?;
+        let block_leaves = self.block_tree.tree0.leaves()?;
+
+        if let Some(latest) = self.root_history.values_mut().next_back() {
+            latest.ca_diff = LeafDiff::new(&self.snapshot_leaves.0, &ca_leaves);
+            latest.block_diff = LeafDiff::new(&self.snapshot_leaves.1, &block_leaves);
+        }
+        self.root_history.insert(
+            version,
+            RootSnapshot {
+                version,
+                roots: (self.ca_tree.root()?, root1, root2),
+                roots_hash,
+                ca_diff: LeafDiff::default(),
+                block_diff: LeafDiff::default(),
+            },
+        );
+        self.snapshot_leaves = (ca_leaves, block_leaves);
+        while self.root_history.len() > self.history_size.max(1) {
+            let oldest = *self.root_history.keys().next().unwrap();
+            self.root_history.remove(&oldest);
+        }
+        Ok(&self.root_history[&version])
+    }
+
+    // the retained snapshot of the version
+    pub fn roots_snapshot(&self, version: u64) -> Option<&RootSnapshot> {
+        self.root_history.get(&version)
+    }
+
+    // Rebuild the trees of a retained snapshot in memory.
+    pub fn snapshot_trees(&self, version: u64) -> Result<(MerkleTree, DualTree), String> {
+        let snapshot = self
+            .roots_snapshot(version)
+            .ok_or("Unknown roots version")?;
+        let (mut ca_leaves, mut block_leaves) = self.snapshot_leaves.clone();
+        for s in self
+            .root_history
+            .range(version..)
+            .rev()
+            .skip(1)
+            .map(|(_, s)| s)
+        {
+            s.ca_diff.revert(&mut ca_leaves);
+            s.block_diff.revert(&mut block_leaves);
+        }
+
+        let mut ca_tree = MerkleTree::new(self.ca_tree.tiers);
+        ca_tree.insert_nodes(ca_leaves)?;
+        // the boundaries are already in a new block tree
+        let mut block_tree = DualTree::new(self.block_tree.tree0.tiers);
+        block_tree.insert_nodes(
+            block_leaves
+                .into_iter()
+                .filter(|x| *x != *ZERO && *x != *BNMAX)
+                .collect(),
+        )?;
+        let (root1, root2) = block_tree.roots()?;
+        if (ca_tree.root()?, root1, root2) != snapshot.roots {
+            return Err("The snapshot can not be rebuilt".to_string());
+        }
+        Ok((ca_tree, block_tree))
+    }
+
+    // the most recent retained snapshot
+    pub fn latest_snapshot(&self) -> Option<&RootSnapshot> {
+        self.root_history.values().next_back()
+    }
+
+    // Update the latest Merkle tree root on the blockchain.
+    pub async fn update_roots_hash<M: Middleware + 'static, S: Signer + 'static>(
+        &mut self,
+        version: u64,
+        contract_address: &str,
+        client: Arc<SignerMiddleware<M, S>>,
+    ) -> Result<()> {
+        let address = contract_address.parse::<Address>()?;
+        let contract = IdentityManager::new(address, client.clone());
+        self.check_roots_version(version)
+            .map_err(|e| eyre::eyre!(e))?;
+        let (rh1, rh2) = self.roots_hash().map_err(|e| eyre::eyre!(e))?;
         let rh1: U256 = U256::from_little_endian(&rh1.to_bytes_le().1);
         let rh2: U256 = U256::from_little_endian(&rh2.to_bytes_le().1);
 
@@ -322,14 +963,28 @@ impl Committee {
             .send()
             .await?
             .await?;
-        let _res = _res.unwrap();
+        let _res = _res.ok_or(eyre::eyre!("The transaction was dropped"))?;
         println!(
             "tx_hash: {:?}, Gas_used: {:?}",
             _res.transaction_hash, _res.gas_used
         );
+        // the roots are only retained once the contract accepted them
+        self.snapshot_roots(version).map_err(|e| eyre::eyre!(e))?;
         Ok(())
     }
 
+    // Fetch the version of the roots hash currently accepted by the contract.
+    pub async fn get_roots_version<M: Middleware + 'static, S: Signer + 'static>(
+        &self,
+        contract_address: &str,
+        client: Arc<SignerMiddleware<M, S>>,
+    ) -> Result<u64> {
+        let address = contract_address.parse::<Address>()?;
+        let contract = IdentityManager::new(address, client.clone());
+        let version = contract.roots_version().call().await?;
+        Ok(version.as_u64())
+    }
+
     // Update the tpke publickey on the blockchain.
     pub async fn set_tpke_pub<M: Middleware + 'static, S: Signer + 'static>(
         &self,
@@ -386,6 +1041,56 @@ impl Committee {
         Ok(())
     }
 
+    // Trust (or distrust) a CA of the CA tree in the identity contract, by the hash of
+    // its generators and schema which the contract binds into lrcm.
+    pub async fn set_trusted_ca<M: Middleware + 'static, S: Signer + 'static>(
+        &self,
+        ca_key: &Point,
+        trusted: bool,
+        contract_address: &str,
+        client: Arc<SignerMiddleware<M, S>>,
+    ) -> Result<()> {
+        let ca_hash = self.ca_hash(ca_key).map_err(|e| eyre::eyre!(e))?;
+        let address = contract_address.parse::<Address>()?;
+        let contract = IdentityManager::new(address, client.clone());
+        // send transaction
+        let _res = contract.do_set_trusted_ca(&ca_hash, trusted).await?;
+        Ok(())
+    }
+
+    // Set the Poseidon contracts (of 3 and 5 inputs) the identity contract uses to
+    // recompute lrcm and scm.
+    pub async fn set_poseidon<M: Middleware + 'static, S: Signer + 'static>(
+        &self,
+        poseidon3_address: &str,
+        poseidon5_address: &str,
+        contract_address: &str,
+        client: Arc<SignerMiddleware<M, S>>,
+    ) -> Result<()> {
+        let poseidon3 = poseidon3_address.parse::<Address>()?;
+        let poseidon5 = poseidon5_address.parse::<Address>()?;
+        let address = contract_address.parse::<Address>()?;
+        let contract = IdentityManager::new(address, client.clone());
+        // send transaction
+        let _res = contract.do_set_poseidon(poseidon3, poseidon5).await?;
+        Ok(())
+    }
+
+    // Push the zero-knowledge proof's validation key to the chain.
+    pub async fn set_attribute_set_vk<M: Middleware + 'static, S: Signer + 'static>(
+        &self,
+        contract_address: &str,
+        client: Arc<SignerMiddleware<M, S>>,
+    ) -> Result<()> {
+        let address = contract_address.parse::<Address>()?;
+        let contract = IdentityManager::new(address, client.clone());
+        // send transaction
+        let _res = contract
+            .do_set_attribute_set_vk(self.set_params.vk.clone())
+            .await?;
+        Ok(())
+    }
+
     // Revoke the given user pseudonym (address).
     pub async fn revoke_user<M: Middleware + 'static, S: Signer + 'static>(
         &self,
@@ -405,7 +1110,8 @@ impl Committee {
         Ok(())
     }
 
-    // revoke the credential
+    // Revoke the credentials. The block tree is restored if the contract does not
+    // accept the new roots.
     pub async fn revoke_credential<M: Middleware + 'static, S: Signer + 'static>(
         &mut self,
         version: u64,
@@ -413,10 +1119,138 @@ impl Committee {
         contract_address: &str,
         client: Arc<SignerMiddleware<M, S>>,
     ) -> Result<()> {
+        self.check_roots_version(version)
+            .map_err(|e| eyre::eyre!(e))?;
+        let keys: Vec<BigInt> = credentials.iter().map(|x| x.scalar_y()).collect();
         self.block_tree
-            .insert_nodes(credentials.iter().map(|x| x.scalar_y()).collect());
-        self.update_roots_hash(version, contract_address, client)
-            .await?;
+            .insert_nodes(keys.clone())
+            .map_err(|e| eyre::eyre!(e))?;
+        if let Err(e) = self
+            .update_roots_hash(version, contract_address, client)
+            .await
+        {
+            self.block_tree
+                .remove_nodes(keys)
+                .map_err(|e| eyre::eyre!(e))?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Revoke the credentials of the revocation requests of CAs. The requests are only
+    // recorded once the contract accepted the new roots, and the block tree is
+    // restored otherwise.
+    pub async fn revoke_by_ca<M: Middleware + 'static, S: Signer + 'static>(
+        &mut self,
+        version: u64,
+        reqs: Vec<RevocationRequest>,
+        contract_address: &str,
+        client: Arc<SignerMiddleware<M, S>>,
+    ) -> Result<()> {
+        self.check_roots_version(version)
+            .map_err(|e| eyre::eyre!(e))?;
+        let revoked = self.verify_revocations(&reqs).map_err(|e| eyre::eyre!(e))?;
+        self.block_tree
+            .insert_nodes(revoked.clone())
+            .map_err(|e| eyre::eyre!(e))?;
+        if let Err(e) = self
+            .update_roots_hash(version, contract_address, client)
+            .await
+        {
+            self.block_tree
+                .remove_nodes(revoked)
+                .map_err(|e| eyre::eyre!(e))?;
+            return Err(e);
+        }
+        self.record_revocations(&reqs);
+        Ok(())
+    }
+
+    // Reinstate wrongly revoked credentials. The block tree is restored if the
+    // contract does not accept the new roots.
+    pub async fn reinstate_credential<M: Middleware + 'static, S: Signer + 'static>(
+        &mut self,
+        version: u64,
+        credentials: Vec<Point>,
+        contract_address: &str,
+        client: Arc<SignerMiddleware<M, S>>,
+    ) -> Result<()> {
+        self.check_roots_version(version)
+            .map_err(|e| eyre::eyre!(e))?;
+        let keys: Vec<BigInt> = credentials.iter().map(|x| x.scalar_y()).collect();
+        self.block_tree
+            .remove_nodes(keys.clone())
+            .map_err(|e| eyre::eyre!(e))?;
+        if let Err(e) = self
+            .update_roots_hash(version, contract_address, client)
+            .await
+        {
+            self.block_tree
+                .insert_nodes(keys)
+                .map_err(|e| eyre::eyre!(e))?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Remove CAs from the CA tree and forget their generators and schemas, so that
+    // their proofs are rejected. Nothing is removed if any of the CAs is unknown.
+    pub fn remove_cas(&mut self, ca_keys: &[Point]) -> Result<(), String> {
+        for key in ca_keys {
+            self.ca_hash(key)?;
+        }
+        self.ca_tree
+            .remove_nodes(ca_keys.iter().map(|x| x.scalar_y()).collect())?;
+        for key in ca_keys {
+            self.ca_generators.remove(key);
+            self.ca_schemas.remove(key);
+        }
+        Ok(())
+    }
+
+    // Remove CAs from the trust list. The CAs are distrusted in the identity contract
+    // first, and are restored locally if the contract does not accept the new roots.
+    pub async fn remove_ca<M: Middleware + 'static, S: Signer + 'static>(
+        &mut self,
+        version: u64,
+        ca_keys: Vec<Point>,
+        contract_address: &str,
+        client: Arc<SignerMiddleware<M, S>>,
+    ) -> Result<()> {
+        self.check_roots_version(version)
+            .map_err(|e| eyre::eyre!(e))?;
+        let removed = ca_keys
+            .iter()
+            .map(|key| {
+                let generators = self.ca_generators.get(key).ok_or("Unknown CA")?;
+                let schema = self.ca_schemas.get(key).ok_or("Unknown CA")?;
+                Ok((key.clone(), generators.clone(), schema.clone()))
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|e| eyre::eyre!(e))?;
+
+        let address = contract_address.parse::<Address>()?;
+        let contract = IdentityManager::new(address, client.clone());
+        for (_, generators, schema) in &removed {
+            let hash = ca_hash(generators, schema).map_err(|e| eyre::eyre!(e))?;
+            // send transaction
+            let _res = contract.do_set_trusted_ca(&hash, false).await?;
+        }
+
+        self.remove_cas(&ca_keys).map_err(|e| eyre::eyre!(e))?;
+        if let Err(e) = self
+            .update_roots_hash(version, contract_address, client)
+            .await
+        {
+            self.ca_tree
+                .insert_nodes(ca_keys.iter().map(|x| x.scalar_y()).collect())
+                .map_err(|e| eyre::eyre!(e))?;
+            for (key, generators, schema) in removed {
+                self.ca_generators.insert(key.clone(), generators);
+                self.ca_schemas.insert(key, schema);
+            }
+            return Err(e);
+        }
         Ok(())
     }
 
@@ -0,0 +1,137 @@
+// This file generates the identity contract from a template. The verifying keys of the
+// pseudonym, sybil resistance and identity proofs are hardcoded in the contract to
+// save gas, so the contract must be regenerated after every trusted setup. The
+// template (contracts/manager.sol.tpl) marks each constant with a placeholder such as
+// {{DERIVE_IC_0_X}} or {{APP_DELTA_Y1}}, and the number of identity attributes with
+// {{ATTRIBUTE_NUM}}. G2 elements are written in the order of the pairing precompile,
+// with the imaginary part first.
+
+use crate::snarkjs::to_dec;
+use ark_bn254::{Bn254, Fq, Fq2, G1Affine, G2Affine};
+use ark_groth16::VerifyingKey;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// prefixes of the verifying keys in the template
+pub const DERIVE: &str = "DERIVE";
+pub const APP: &str = "APP";
+pub const IDENTITY: &str = "IDENTITY";
+
+// placeholders and values of the verifying key
+pub fn vk_constants(prefix: &str, vk: &VerifyingKey<Bn254>) -> Vec<(String, String)> {
+    let mut constants = vec![
+        (format!("{}_ALPHA_X", prefix), to_dec(&vk.alpha_g1.x)),
+        (format!("{}_ALPHA_Y", prefix), to_dec(&vk.alpha_g1.y)),
+    ];
+    for (name, p) in [
+        ("BETA", &vk.beta_g2),
+        ("GAMMA", &vk.gamma_g2),
+        ("DELTA", &vk.delta_g2),
+    ] {
+        constants.push((format!("{}_{}_X1", prefix, name), to_dec(&p.x.c1)));
+        constants.push((format!("{}_{}_X0", prefix, name), to_dec(&p.x.c0)));
+        constants.push((format!("{}_{}_Y1", prefix, name), to_dec(&p.y.c1)));
+        constants.push((format!("{}_{}_Y0", prefix, name), to_dec(&p.y.c0)));
+    }
+    for (i, p) in vk.gamma_abc_g1.iter().enumerate() {
+        constants.push((format!("{}_IC_{}_X", prefix, i), to_dec(&p.x)));
+        constants.push((format!("{}_IC_{}_Y", prefix, i), to_dec(&p.y)));
+    }
+    constants
+}
+
+// placeholder of the number of identity attributes
+pub const ATTRIBUTE_NUM: &str = "ATTRIBUTE_NUM";
+
+// Fill the template with the verifying keys of the pseudonym, sybil resistance
+// and identity proofs, and the number of attributes of the identity circuit.
+pub fn gen_manager(
+    template: &str,
+    derive_vk: &VerifyingKey<Bn254>,
+    app_vk: &VerifyingKey<Bn254>,
+    identity_vk: &VerifyingKey<Bn254>,
+    attribute_num: usize,
+) -> Result<String, String> {
+    if attribute_num == 0 {
+        return Err("Invalid number of attributes".to_string());
+    }
+    let mut source = template.replace(
+        &format!("{{{{{}}}}}", ATTRIBUTE_NUM),
+        &attribute_num.to_string(),
+    );
+    for (prefix, vk) in [(DERIVE, derive_vk), (APP, app_vk), (IDENTITY, identity_vk)] {
+        // the number of public inputs is fixed by the template
+        let next_ic = format!("{{{{{}_IC_{}_X}}}}", prefix, vk.gamma_abc_g1.len());
+        let last_ic = format!("{{{{{}_IC_{}_X}}}}", prefix, vk.gamma_abc_g1.len() - 1);
+        if source.contains(&next_ic) || !source.contains(&last_ic) {
+            return Err(format!("Invalid number of public inputs for {}", prefix));
+        }
+        for (name, value) in vk_constants(prefix, vk) {
+            source = source.replace(&format!("{{{{{}}}}}", name), &value);
+        }
+    }
+    if let Some(i) = source.find("{{") {
+        let end = source[i..].find("}}").map_or(source.len(), |j| i + j + 2);
+        return Err(format!("Unknown placeholder {}", &source[i..end]));
+    }
+    Ok(source)
+}
+
+// Read the constants of a generated contract back, by matching it with the template.
+pub fn read_constants(template: &str, source: &str) -> Result<HashMap<String, String>, String> {
+    let mut constants = HashMap::new();
+    let mut rest = source;
+    let mut parts = template.split("{{");
+    let head = parts.next().unwrap();
+    rest = rest
+        .strip_prefix(head)
+        .ok_or("The contract does not match the template")?;
+    for part in parts {
+        let (name, text) = part.split_once("}}").ok_or("Invalid template")?;
+        let len = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        constants.insert(name.to_string(), rest[..len].to_string());
+        rest = rest[len..]
+            .strip_prefix(text)
+            .ok_or("The contract does not match the template")?;
+    }
+    Ok(constants)
+}
+
+// Rebuild the verifying key from the constants of a generated contract.
+pub fn vk_from_constants(
+    prefix: &str,
+    constants: &HashMap<String, String>,
+) -> Result<VerifyingKey<Bn254>, String> {
+    let fq = |name: &str| -> Result<Fq, String> {
+        constants
+            .get(&format!("{}_{}", prefix, name))
+            .and_then(|v| Fq::from_str(v).ok())
+            .ok_or(format!("Missing constant {}_{}", prefix, name))
+    };
+    let g2 = |name: &str| -> Result<G2Affine, String> {
+        Ok(G2Affine::new(
+            Fq2::new(fq(&format!("{}_X0", name))?, fq(&format!("{}_X1", name))?),
+            Fq2::new(fq(&format!("{}_Y0", name))?, fq(&format!("{}_Y1", name))?),
+            false,
+        ))
+    };
+
+    let mut gamma_abc_g1 = Vec::new();
+    while constants.contains_key(&format!("{}_IC_{}_X", prefix, gamma_abc_g1.len())) {
+        let i = gamma_abc_g1.len();
+        gamma_abc_g1.push(G1Affine::new(
+            fq(&format!("IC_{}_X", i))?,
+            fq(&format!("IC_{}_Y", i))?,
+            false,
+        ));
+    }
+    Ok(VerifyingKey {
+        alpha_g1: G1Affine::new(fq("ALPHA_X")?, fq("ALPHA_Y")?, false),
+        beta_g2: g2("BETA")?,
+        gamma_g2: g2("GAMMA")?,
+        delta_g2: g2("DELTA")?,
+        gamma_abc_g1,
+    })
+}
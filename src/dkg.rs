@@ -0,0 +1,491 @@
+// This file implements Pedersen's distributed key generation protocol (Joint-Feldman
+// VSS) for the tpke key of the committee. Each member deals a random polynomial,
+// publishes Feldman commitments to its coefficients, and sends encrypted shares to
+// the other members. Members complain about invalid shares, accused dealers must
+// reveal the disputed shares, and dealers that fail to do so are disqualified.
+
+use crate::tpke::{eval_polynomial, modulo, random_scalar, PublicKey, SecretShare};
+use baby_jub::{poseidon_hash, Point, G};
+use num_bigint::{BigInt, ToBigInt};
+use num_traits::{One, Zero};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// A share encrypted to the communication key of the receiver.
+pub struct EncryptedShare {
+    pub receiver: usize,
+    // ephemeral public key
+    pub ephemeral: Point,
+    // share + pad (mod ORDER)
+    pub value: BigInt,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// Broadcast message of a dealer in the first round.
+pub struct Dealing {
+    pub dealer: usize,
+    // Feldman commitments of the polynomial coefficients
+    pub commitments: Vec<Point>,
+    pub shares: Vec<EncryptedShare>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// The accuser claims that the share it received from the accused dealer is invalid.
+// It is signed with the communication key of the accuser (Schnorr), so a member can
+// not complain on behalf of another one.
+pub struct Complaint {
+    pub accuser: usize,
+    pub accused: usize,
+    // challenge and response of the signature
+    pub c: BigInt,
+    pub z: BigInt,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// The accused dealer reveals the disputed share in plaintext.
+pub struct ComplaintResponse {
+    pub dealer: usize,
+    pub accuser: usize,
+    pub share: BigInt,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// Result of the DKG protocol for one member.
+pub struct DkgOutput {
+    pub share: SecretShare,
+    pub threshold: usize,
+    // the joint tpke public key
+    pub public_key: PublicKey,
+    // public key shards of all members, indexed by (member index - 1)
+    pub public_shards: Vec<Point>,
+    // dealers that were not disqualified
+    pub qualified: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+// A member taking part in the DKG protocol.
+pub struct Participant {
+    pub index: usize,
+    pub threshold: usize,
+    pub n: usize,
+    // private key used to decrypt received shares
+    comm_key: BigInt,
+    // communication public keys of all members, indexed by (member index - 1)
+    peers: Vec<Point>,
+    // coefficients of the dealt polynomial
+    coeffs: Vec<BigInt>,
+    // commitments broadcast by each dealer
+    commitments: BTreeMap<usize, Vec<Point>>,
+    // valid shares received from each dealer
+    received: BTreeMap<usize, BigInt>,
+}
+
+// Check a share against the Feldman commitments of the dealer.
+pub fn verify_share(commitments: &[Point], index: usize, share: &BigInt) -> bool {
+    share * G.clone() == eval_commitments(commitments, index)
+}
+
+// Evaluate the committed polynomial in the exponent.
+pub fn eval_commitments(commitments: &[Point], index: usize) -> Point {
+    let x = index.to_bigint().unwrap();
+    let mut d = BigInt::one();
+    let mut sum = Point::identity();
+    for c in commitments {
+        sum = sum + &d * c;
+        d = modulo(&(d * &x));
+    }
+    sum
+}
+
+// pad used to encrypt a share
+fn share_pad(key: &Point) -> BigInt {
+    modulo(&poseidon_hash(vec![&key.scalar_x(), &key.scalar_y()]).unwrap())
+}
+
+// Encrypt a share to the communication key of the receiver.
+pub(crate) fn encrypt_share(share: &BigInt, receiver: usize, pk: &Point) -> EncryptedShare {
+    let r = random_scalar();
+    EncryptedShare {
+        receiver,
+        ephemeral: &r * G.clone(),
+        value: modulo(&(share + share_pad(&(&r * pk)))),
+    }
+}
+
+// Decrypt a share using the communication private key.
+pub(crate) fn decrypt_share(enc: &EncryptedShare, comm_key: &BigInt) -> BigInt {
+    modulo(&(&enc.value - share_pad(&(comm_key * &enc.ephemeral))))
+}
+
+impl Complaint {
+    // challenge of the signature, binding the accuser and the accused dealer
+    fn challenge(pk: &Point, accuser: usize, accused: usize, r: &Point) -> BigInt {
+        modulo(
+            &poseidon_hash(vec![
+                &pk.scalar_x(),
+                &pk.scalar_y(),
+                &accuser.to_bigint().unwrap(),
+                &accused.to_bigint().unwrap(),
+                &r.scalar_x(),
+                &r.scalar_y(),
+            ])
+            .unwrap(),
+        )
+    }
+
+    // Sign a complaint with the communication private key of the accuser.
+    pub fn new(accuser: usize, accused: usize, comm_key: &BigInt) -> Self {
+        let w = random_scalar();
+        let c = Self::challenge(&(comm_key * G.clone()), accuser, accused, &(&w * G.clone()));
+        let z = modulo(&(w + &c * comm_key));
+        Self {
+            accuser,
+            accused,
+            c,
+            z,
+        }
+    }
+
+    // Check that the accuser is one of the peers and signed the complaint.
+    pub fn verify(&self, peers: &[Point]) -> bool {
+        if self.accuser == 0 || self.accuser > peers.len() {
+            return false;
+        }
+        let pk = &peers[self.accuser - 1];
+        let r = &self.z * G.clone() + (&self.c * pk).negative();
+        self.c == Self::challenge(pk, self.accuser, self.accused, &r)
+    }
+}
+
+// Check the revealed shares of the complaints, returning the disqualified dealers.
+// Complaints which are not signed by one of the `peers` are ignored. Valid shares
+// revealed for complaints of member `index` are added to `received`.
+pub(crate) fn resolve_complaints(
+    commitments: &BTreeMap<usize, Vec<Point>>,
+    complaints: &[Complaint],
+    responses: &[ComplaintResponse],
+    peers: &[Point],
+    index: usize,
+    received: &mut BTreeMap<usize, BigInt>,
+) -> BTreeSet<usize> {
+    let mut disqualified = BTreeSet::new();
+    for c in complaints.iter().filter(|c| c.verify(peers)) {
+        let commitments = match commitments.get(&c.accused) {
+            Some(v) => v,
+            None => continue,
+        };
+        let revealed = responses
+            .iter()
+            .find(|r| r.dealer == c.accused && r.accuser == c.accuser)
+            .map(|r| r.share.clone());
+        match revealed {
+            Some(share) if verify_share(commitments, c.accuser, &share) => {
+                if c.accuser == index {
+                    received.insert(c.accused, share);
+                }
+            }
+            _ => {
+                disqualified.insert(c.accused);
+            }
+        }
+    }
+    disqualified
+}
+
+impl Participant {
+    // Initialize a member with index in [1, n].
+    pub fn new(index: usize, threshold: usize, n: usize) -> Result<Self, String> {
+        if index == 0 || index > n {
+            return Err("Invalid member index".to_string());
+        }
+        if threshold == 0 || threshold > n {
+            return Err("Invalid threshold".to_string());
+        }
+        Ok(Self {
+            index,
+            threshold,
+            n,
+            comm_key: random_scalar(),
+            peers: Vec::new(),
+            coeffs: Vec::new(),
+            commitments: BTreeMap::new(),
+            received: BTreeMap::new(),
+        })
+    }
+
+    // public key used by other members to encrypt shares
+    pub fn comm_pubkey(&self) -> Point {
+        &self.comm_key * G.clone()
+    }
+
+    // set the communication public keys of all members
+    pub fn set_peers(&mut self, peers: Vec<Point>) -> Result<(), String> {
+        if peers.len() != self.n {
+            return Err("Invalid number of members".to_string());
+        }
+        self.peers = peers;
+        Ok(())
+    }
+
+    // Round 1: sample a polynomial, commit to it and encrypt a share for every member.
+    pub fn deal(&mut self) -> Result<Dealing, String> {
+        if self.peers.len() != self.n {
+            return Err("Members are not set".to_string());
+        }
+        self.coeffs = (0..self.threshold).map(|_| random_scalar()).collect();
+        let commitments = self.coeffs.iter().map(|c| c * G.clone()).collect();
+        let shares = self
+            .peers
+            .iter()
+            .enumerate()
+            .map(|(i, pk)| encrypt_share(&eval_polynomial(&self.coeffs, i + 1), i + 1, pk))
+            .collect();
+
+        Ok(Dealing {
+            dealer: self.index,
+            commitments,
+            shares,
+        })
+    }
+
+    // Round 2: decrypt and verify the shares sent to this member, and complain
+    // about every dealer whose share is missing or invalid.
+    pub fn receive(&mut self, dealings: &[Dealing]) -> Vec<Complaint> {
+        let mut complaints = Vec::new();
+        for d in dealings {
+            if d.dealer == 0 || d.dealer > self.n || self.commitments.contains_key(&d.dealer) {
+                continue;
+            }
+            // malformed dealings are disqualified in `finalize`
+            if d.commitments.len() != self.threshold {
+                continue;
+            }
+            self.commitments.insert(d.dealer, d.commitments.clone());
+
+            let share = d
+                .shares
+                .iter()
+                .find(|s| s.receiver == self.index)
+                .map(|s| decrypt_share(s, &self.comm_key));
+            match share {
+                Some(v) if verify_share(&d.commitments, self.index, &v) => {
+                    self.received.insert(d.dealer, v);
+                }
+                _ => complaints.push(Complaint::new(self.index, d.dealer, &self.comm_key)),
+            }
+        }
+        complaints
+    }
+
+    // complaints of other members, signed by the accuser
+    fn valid_complaints(&self, complaints: &[Complaint]) -> Vec<Complaint> {
+        complaints
+            .iter()
+            .filter(|c| c.accuser != c.accused && c.verify(&self.peers))
+            .cloned()
+            .collect()
+    }
+
+    // Round 3: reveal the disputed shares for the complaints against this member.
+    pub fn respond(&self, complaints: &[Complaint]) -> Vec<ComplaintResponse> {
+        self.valid_complaints(complaints)
+            .iter()
+            .filter(|c| c.accused == self.index)
+            .map(|c| ComplaintResponse {
+                dealer: self.index,
+                accuser: c.accuser,
+                share: eval_polynomial(&self.coeffs, c.accuser),
+            })
+            .collect()
+    }
+
+    // Resolve the complaints, build the qualified set and compute the final share.
+    // All honest members reach the same qualified set and public key.
+    pub fn finalize(
+        &mut self,
+        complaints: &[Complaint],
+        responses: &[ComplaintResponse],
+    ) -> Result<DkgOutput, String> {
+        let complaints = self.valid_complaints(complaints);
+        let disqualified = resolve_complaints(
+            &self.commitments,
+            &complaints,
+            responses,
+            &self.peers,
+            self.index,
+            &mut self.received,
+        );
+
+        let qualified: Vec<usize> = self
+            .commitments
+            .keys()
+            .filter(|i| !disqualified.contains(*i))
+            .cloned()
+            .collect();
+        if qualified.is_empty() {
+            return Err("No qualified dealer".to_string());
+        }
+
+        let mut value = BigInt::zero();
+        for i in &qualified {
+            let share = self
+                .received
+                .get(i)
+                .ok_or(format!("Missing share from dealer {}", i))?;
+            value = modulo(&(value + share));
+        }
+
+        let public_key =
+            PublicKey::new(qualified.iter().map(|i| &self.commitments[i][0]).collect());
+        let public_shards = (1..=self.n)
+            .map(|j| {
+                qualified.iter().fold(Point::identity(), |sum, i| {
+                    sum + eval_commitments(&self.commitments[i], j)
+                })
+            })
+            .collect();
+
+        Ok(DkgOutput {
+            share: SecretShare {
+                index: self.index,
+                value,
+            },
+            threshold: self.threshold,
+            public_key,
+            public_shards,
+            qualified,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Complaint, Dealing, DkgOutput, Participant};
+    use crate::tpke::random_scalar;
+    use baby_jub::{Point, G};
+
+    // set up n in-process members
+    fn setup(threshold: usize, n: usize) -> Vec<Participant> {
+        let mut members: Vec<Participant> = (1..=n)
+            .map(|i| Participant::new(i, threshold, n).unwrap())
+            .collect();
+        let peers: Vec<Point> = members.iter().map(|m| m.comm_pubkey()).collect();
+        for m in members.iter_mut() {
+            m.set_peers(peers.clone()).unwrap();
+        }
+        members
+    }
+
+    // run the remaining rounds of the protocol
+    fn run(members: &mut [Participant], dealings: &[Dealing]) -> Vec<DkgOutput> {
+        let complaints: Vec<Complaint> = members
+            .iter_mut()
+            .flat_map(|m| m.receive(dealings))
+            .collect();
+        let responses: Vec<_> = members
+            .iter()
+            .flat_map(|m| m.respond(&complaints))
+            .collect();
+        members
+            .iter_mut()
+            .map(|m| m.finalize(&complaints, &responses).unwrap())
+            .collect()
+    }
+
+    #[test]
+    // all members are honest
+    fn test_dkg() {
+        let mut members = setup(3, 5);
+        let dealings: Vec<Dealing> = members.iter_mut().map(|m| m.deal().unwrap()).collect();
+        let outputs = run(&mut members, &dealings);
+
+        let key = outputs[0].public_key.clone();
+        for out in &outputs {
+            assert_eq!(out.public_key, key);
+            assert_eq!(out.qualified, vec![1, 2, 3, 4, 5]);
+            assert_eq!(out.share.public(), out.public_shards[out.share.index - 1]);
+        }
+
+        let msg = random_scalar() * G.clone();
+        let (cipher, _) = key.encrypt(&msg);
+        let shards: Vec<_> = outputs
+            .iter()
+            .map(|o| o.share.decrypt_shard(&cipher.c1))
+            .collect();
+        let m = cipher
+            .decrypt(vec![&shards[4], &shards[1], &shards[2]], 3)
+            .unwrap();
+        assert_eq!(m, msg);
+    }
+
+    #[test]
+    // a dealer sends an invalid share and refuses to reveal it
+    fn test_dkg_disqualify() {
+        let mut members = setup(2, 4);
+        let mut dealings: Vec<Dealing> = members.iter_mut().map(|m| m.deal().unwrap()).collect();
+        dealings[1].shares[0].value += 1;
+        // the cheater does not respond
+        members[1].coeffs[0] += 1;
+        let outputs = run(&mut members, &dealings);
+
+        let key = outputs[0].public_key.clone();
+        for out in &outputs {
+            assert_eq!(out.public_key, key);
+            assert_eq!(out.qualified, vec![1, 3, 4]);
+        }
+
+        let msg = random_scalar() * G.clone();
+        let (cipher, _) = key.encrypt(&msg);
+        let shards: Vec<_> = outputs
+            .iter()
+            .map(|o| o.share.decrypt_shard(&cipher.c1))
+            .collect();
+        let m = cipher.decrypt(vec![&shards[0], &shards[3]], 2).unwrap();
+        assert_eq!(m, msg);
+    }
+
+    #[test]
+    // a dealer sends an invalid share but reveals the correct one
+    fn test_dkg_complaint_resolved() {
+        let mut members = setup(2, 3);
+        let mut dealings: Vec<Dealing> = members.iter_mut().map(|m| m.deal().unwrap()).collect();
+        dealings[0].shares[2].value += 1;
+        let outputs = run(&mut members, &dealings);
+
+        for out in &outputs {
+            assert_eq!(out.qualified, vec![1, 2, 3]);
+            assert_eq!(out.share.public(), out.public_shards[out.share.index - 1]);
+        }
+    }
+
+    #[test]
+    // complaints which are not signed by another member are ignored
+    fn test_dkg_forged_complaint() {
+        let mut members = setup(2, 3);
+        let dealings: Vec<Dealing> = members.iter_mut().map(|m| m.deal().unwrap()).collect();
+        let mut complaints: Vec<Complaint> = members
+            .iter_mut()
+            .flat_map(|m| m.receive(&dealings))
+            .collect();
+        assert!(complaints.is_empty());
+
+        // member 2 complains about dealer 1 on behalf of others and of itself
+        let key = members[1].comm_key.clone();
+        complaints.push(Complaint::new(0, 1, &key));
+        complaints.push(Complaint::new(4, 1, &key));
+        complaints.push(Complaint::new(3, 1, &key));
+        complaints.push(Complaint::new(1, 1, &members[0].comm_key));
+        let mut tampered = Complaint::new(2, 3, &key);
+        tampered.accused = 1;
+        complaints.push(tampered);
+
+        for m in &members {
+            assert!(m.respond(&complaints).is_empty());
+        }
+        for m in members.iter_mut() {
+            let out = m.finalize(&complaints, &[]).unwrap();
+            assert_eq!(out.qualified, vec![1, 2, 3]);
+        }
+    }
+}
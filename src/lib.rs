@@ -7,16 +7,28 @@ use baby_jub::Point;
 use color_eyre::Result;
 use ethers::prelude::abigen;
 use ethers::providers::Middleware;
-use ethers::types::U256;
+use ethers::types::{Address, U256};
 use num_bigint::{BigInt, Sign};
 use std::convert::TryInto;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod attestation;
+pub mod attribute_set;
 pub mod bench;
 pub mod ca_client;
+pub mod ceremony;
+pub mod challenge;
 pub mod committee_client;
+pub mod contract_gen;
+pub mod dkg;
 pub mod merkle_tree;
+pub mod predicate;
+pub mod presentation;
+pub mod reshare;
+pub mod schema;
+pub mod snarkjs;
 pub mod tpke;
+pub mod tree_store;
 pub mod user_client;
 
 pub fn get_timestamp() -> u64 {
@@ -123,6 +135,7 @@ impl<M: Middleware + 'static> IdentityManager<M> {
         user: &BigInt,
         appkey: &BigInt,
         appid: &BigInt,
+        ctx: &BigInt,
         proof: P,
     ) -> Result<bool> {
         // convert into the expected format by the contract
@@ -130,10 +143,11 @@ impl<M: Middleware + 'static> IdentityManager<M> {
         let user_e = U256::from_little_endian(&user.to_bytes_le().1);
         let appkey_e = U256::from_little_endian(&appkey.to_bytes_le().1);
         let appid_e = U256::from_little_endian(&appid.to_bytes_le().1);
+        let ctx_e = U256::from_little_endian(&ctx.to_bytes_le().1);
 
         // send transactions
         let _res = self
-            .set_appkey(user_e, appkey_e, appid_e, proof)
+            .set_appkey(user_e, appkey_e, appid_e, ctx_e, proof)
             .send()
             .await?
             .await?;
@@ -145,23 +159,117 @@ impl<M: Middleware + 'static> IdentityManager<M> {
         Ok(true)
     }
 
-    // verify identitys on identity contract
+    // issue a challenge of the sender app for sybil resistance
+    async fn do_issue_challenge(&self, ctx: &BigInt, expiry: u64) -> Result<bool> {
+        let ctx_e = U256::from_little_endian(&ctx.to_bytes_le().1);
+
+        // send transactions
+        let _res = self
+            .issue_challenge(ctx_e, U256::from(expiry))
+            .send()
+            .await?
+            .await?;
+        let _res = _res.unwrap();
+        println!(
+            "tx_hash: {:?}, Gas_used: {:?}",
+            _res.transaction_hash, _res.gas_used
+        );
+        Ok(true)
+    }
+
+    // verify identitys on identity contract, which recomputes lrcm from the hash of the
+    // ranges, the hash of the generators of the CA and the challenge
     async fn do_veriy_identity<P: Into<ethereum::Proof>>(
         &self,
         ax: &BigInt,
         ay: &BigInt,
-        lrcm: &BigInt,
+        range_hash: &BigInt,
+        ca_hash: &BigInt,
+        ctx: &BigInt,
+        proof: P,
+    ) -> Result<bool> {
+        // convert into the expected format by the contract
+        let proof = proof.into().into();
+        let ax_e = U256::from_little_endian(&ax.to_bytes_le().1);
+        let ay_e = U256::from_little_endian(&ay.to_bytes_le().1);
+        let range_hash_e = U256::from_little_endian(&range_hash.to_bytes_le().1);
+        let ca_hash_e = U256::from_little_endian(&ca_hash.to_bytes_le().1);
+        let ctx_e = U256::from_little_endian(&ctx.to_bytes_le().1);
+
+        // send transactions
+        let _res = self
+            .verify_identity(ax_e, ay_e, range_hash_e, ca_hash_e, ctx_e, proof)
+            .send()
+            .await?
+            .await?;
+        let _res = _res.unwrap();
+        println!(
+            "tx_hash: {:?}, Gas_used: {:?}",
+            _res.transaction_hash, _res.gas_used
+        );
+        Ok(true)
+    }
+
+    // verify set-membership or non-membership proofs on identity contract, which
+    // recomputes scm from the root of the set, the attribute, the CA and the challenge
+    #[allow(clippy::too_many_arguments)]
+    async fn do_verify_attribute_set<P: Into<ethereum::Proof>>(
+        &self,
+        ax: &BigInt,
+        ay: &BigInt,
+        root: &BigInt,
+        index: &BigInt,
+        member: &BigInt,
+        ca_hash: &BigInt,
+        ctx: &BigInt,
         proof: P,
     ) -> Result<bool> {
         // convert into the expected format by the contract
         let proof = proof.into().into();
         let ax_e = U256::from_little_endian(&ax.to_bytes_le().1);
         let ay_e = U256::from_little_endian(&ay.to_bytes_le().1);
-        let lrcm_e = U256::from_little_endian(&lrcm.to_bytes_le().1);
+        let root_e = U256::from_little_endian(&root.to_bytes_le().1);
+        let index_e = U256::from_little_endian(&index.to_bytes_le().1);
+        let member_e = U256::from_little_endian(&member.to_bytes_le().1);
+        let ca_hash_e = U256::from_little_endian(&ca_hash.to_bytes_le().1);
+        let ctx_e = U256::from_little_endian(&ctx.to_bytes_le().1);
 
         // send transactions
         let _res = self
-            .verify_identity(ax_e, ay_e, lrcm_e, proof)
+            .verify_attribute_set(
+                ax_e, ay_e, root_e, index_e, member_e, ca_hash_e, ctx_e, proof,
+            )
+            .send()
+            .await?
+            .await?;
+        let _res = _res.unwrap();
+        println!(
+            "tx_hash: {:?}, Gas_used: {:?}",
+            _res.transaction_hash, _res.gas_used
+        );
+        Ok(true)
+    }
+
+    // trust (or distrust) a CA by the hash of its generators
+    async fn do_set_trusted_ca(&self, ca_hash: &BigInt, trusted: bool) -> Result<bool> {
+        let ca_hash_e = U256::from_little_endian(&ca_hash.to_bytes_le().1);
+        let _res = self
+            .set_trusted_ca(ca_hash_e, trusted)
+            .send()
+            .await?
+            .await?;
+        let _res = _res.unwrap();
+        println!(
+            "tx_hash: {:?}, Gas_used: {:?}",
+            _res.transaction_hash, _res.gas_used
+        );
+        Ok(true)
+    }
+
+    // set the Poseidon contracts used to recompute lrcm and scm
+    async fn do_set_poseidon(&self, poseidon3: Address, poseidon5: Address) -> Result<bool> {
+        let _res = self
+            .set_poseidon(poseidon3, poseidon5)
             .send()
             .await?
             .await?;
@@ -202,4 +310,20 @@ impl<M: Middleware + 'static> IdentityManager<M> {
         );
         Ok(true)
     }
+
+    // push zero-knowledge proof parameters to the identity contract.
+    async fn do_set_attribute_set_vk<VK: Into<ethereum::VerifyingKey>>(
+        &self,
+        vk: VK,
+    ) -> Result<bool> {
+        // convert into the expected format by the contract
+        let vk = vk.into().into();
+        let _res = self.set_attribute_set_vk(vk).send().await?.await?;
+        let _res = _res.unwrap();
+        println!(
+            "tx_hash: {:?}, Gas_used: {:?}",
+            _res.transaction_hash, _res.gas_used
+        );
+        Ok(true)
+    }
 }
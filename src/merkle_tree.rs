@@ -1,10 +1,13 @@
 // This file implements a Merkle tree, which can support both
 // proofs of data existence and non-existence.
 
+use crate::tree_store::{level_len, MemoryStore, NodeStore};
 use baby_jub::{poseidon_hash, Q};
 use lazy_static::lazy_static;
 use num_bigint::{BigInt, ToBigInt};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::vec;
 
 lazy_static! {
@@ -115,20 +118,118 @@ impl NotInProof {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// Proof of several leaves in the Merkle tree, the internal nodes shared by
+// their paths are only included once.
+pub struct MultiProof {
+    // (position, value) of the proven leaves, sorted by position
+    pub leaves: Vec<(usize, BigInt)>,
+    // nodes that can not be computed from the leaves, level by level from the bottom
+    pub nodes: Vec<BigInt>,
+    // length of the paths
+    pub depth: usize,
+}
+
+impl MultiProof {
+    // verify the proof
+    pub fn verify(&self, root: &BigInt) -> bool {
+        if self.leaves.is_empty() || self.leaves.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return false;
+        }
+        let last = self.leaves[self.leaves.len() - 1].0;
+        if last.checked_shr(self.depth as u32).unwrap_or(0) != 0 {
+            return false;
+        }
+
+        let mut level = self.leaves.clone();
+        let mut nodes = self.nodes.iter();
+        for _ in 0..self.depth {
+            let mut next = Vec::new();
+            let mut k = 0;
+            while k < level.len() {
+                let (idx, value) = &level[k];
+                let parent = if idx % 2 == 0 && k + 1 < level.len() && level[k + 1].0 == idx + 1 {
+                    k += 1;
+                    poseidon_hash(vec![value, &level[k].1]).unwrap()
+                } else {
+                    let sibling = match nodes.next() {
+                        Some(node) => node,
+                        None => return false,
+                    };
+                    if idx % 2 == 0 {
+                        poseidon_hash(vec![value, sibling]).unwrap()
+                    } else {
+                        poseidon_hash(vec![sibling, value]).unwrap()
+                    }
+                };
+                next.push((idx / 2, parent));
+                k += 1;
+            }
+            level = next;
+        }
+
+        nodes.next().is_none() && &level[0].1 == root
+    }
+
+    // whether the value is one of the proven leaves
+    pub fn contains(&self, value: &BigInt) -> bool {
+        self.leaves.iter().any(|(_, v)| v == value)
+    }
+
+    // whether the value lies between two adjacent proven leaves of the same pair
+    fn separates(&self, value: &BigInt) -> bool {
+        self.leaves
+            .windows(2)
+            .any(|w| w[0].0 % 2 == 0 && w[1].0 == w[0].0 + 1 && &w[0].1 < value && value < &w[1].1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// Proof that several values are not in the Merkle tree. Each value lies between
+// the two leaves of a pair in tree0 or tree1 of the DualTree.
+pub struct MultiNotInProof {
+    pub values: Vec<BigInt>,
+    pub proof0: Option<MultiProof>,
+    pub proof1: Option<MultiProof>,
+}
+
+impl MultiNotInProof {
+    // verify the proof
+    pub fn verify(&self, root: [&BigInt; 2]) -> bool {
+        for (proof, root) in [&self.proof0, &self.proof1].iter().zip(root.iter()) {
+            if let Some(p) = proof {
+                if !p.verify(root) {
+                    return false;
+                }
+            }
+        }
+        self.values.iter().all(|v| {
+            self.proof0.as_ref().map_or(false, |p| p.separates(v))
+                || self.proof1.as_ref().map_or(false, |p| p.separates(v))
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 // A complete binary tree, where empty positions are filled with 0.
-pub struct MerkleTree {
-    // size of non-empty nodes
-    pub len: usize,
+pub struct MerkleTree<S = MemoryStore> {
     // layers of the tree
     pub tiers: usize,
-    pub nodes: Vec<Vec<BigInt>>,
     pub empty_nodes: Vec<BigInt>,
+    // storage of the non-empty nodes
+    pub store: S,
 }
 
 impl MerkleTree {
-    // Initialize the Merkle tree.
+    // Initialize the Merkle tree in memory.
     pub fn new(tiers: usize) -> Self {
+        Self::with_store(tiers, MemoryStore::new(tiers))
+    }
+}
+
+impl<S: NodeStore> MerkleTree<S> {
+    // Initialize the Merkle tree on the given store, existing nodes are kept.
+    pub fn with_store(tiers: usize, store: S) -> Self {
         // Calculate the value of empty nodes for each layer.
         let empty_nodes: Vec<BigInt> = (0..tiers)
             .scan(ZERO.clone(), |state, i| {
@@ -140,127 +241,744 @@ impl MerkleTree {
                 }
             })
             .collect();
-        // nodes by layers
-        let nodes = vec![Vec::new(); tiers];
-        let tree = Self {
-            len: 0,
+
+        Self {
             tiers,
-            nodes,
             empty_nodes,
-        };
+            store,
+        }
+    }
+
+    // number of leaves
+    pub fn len(&self) -> Result<usize, String> {
+        self.store.leaf_count()
+    }
+
+    // whether the tree has no leaves
+    pub fn is_empty(&self) -> Result<bool, String> {
+        Ok(self.len()? == 0)
+    }
+
+    // the node at (level, idx), None if the position is empty
+    pub fn node(&self, level: usize, idx: usize) -> Result<Option<BigInt>, String> {
+        if idx < level_len(self.len()?, level) {
+            self.stored(level, idx).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    // a node which must be in the store
+    fn stored(&self, level: usize, idx: usize) -> Result<BigInt, String> {
+        self.store
+            .get(level, idx)?
+            .ok_or(format!("Missing node ({}, {})", level, idx))
+    }
 
-        tree
+    // the node at (level, idx), or the empty node of the level
+    fn node_or_empty(&self, level: usize, idx: usize) -> Result<BigInt, String> {
+        Ok(self
+            .node(level, idx)?
+            .unwrap_or_else(|| self.empty_nodes[level].clone()))
     }
 
     // the root of merkle tree
-    pub fn root(&self) -> BigInt {
-        self.nodes[self.tiers - 1][0].clone()
+    pub fn root(&self) -> Result<BigInt, String> {
+        self.node_or_empty(self.tiers - 1, 0)
     }
 
-    // insert nodes
-    pub fn insert_nodes(&mut self, nodes: Vec<BigInt>) {
-        self.nodes[0].extend(nodes.into_iter());
-        self.nodes[0].sort();
-        self.len = self.nodes[0].len();
+    // Binary search the sorted leaves, giving Ok(position) if the node is found and
+    // Err(insertion position) otherwise.
+    pub fn search(&self, node: &BigInt) -> Result<Result<usize, usize>, String> {
+        let (mut lo, mut hi) = (0, self.len()?);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.stored(0, mid)?.cmp(node) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Equal => return Ok(Ok(mid)),
+                Ordering::Greater => hi = mid,
+            }
+        }
+        Ok(Err(lo))
+    }
+
+    // all the leaves, in order
+    pub fn leaves(&self) -> Result<Vec<BigInt>, String> {
+        (0..self.len()?).map(|i| self.stored(0, i)).collect()
+    }
+
+    // whether the node is a leaf of the tree
+    pub fn contains(&self, node: &BigInt) -> Result<bool, String> {
+        Ok(self.search(node)?.is_ok())
+    }
+
+    // Insert nodes, keeping the leaves sorted, and write all changes at once. The
+    // leaves on the right of the first inserted position shift, so their paths are
+    // rehashed: O(n) hashes for a random value (n/2 on average), O(tiers) only when
+    // appending on the right. Use `IndexedTree` when insertions must be cheap.
+    pub fn insert_nodes(&mut self, nodes: Vec<BigInt>) -> Result<(), String> {
+        self.update_nodes(Vec::new(), nodes)
+    }
+
+    // Remove nodes, the tree is unchanged if any of them is missing.
+    pub fn remove_nodes(&mut self, nodes: Vec<BigInt>) -> Result<(), String> {
+        self.update_nodes(nodes, Vec::new())
+    }
+
+    // replace a node with another one
+    pub fn replace_node(&mut self, old: BigInt, new: BigInt) -> Result<(), String> {
+        self.update_nodes(vec![old], vec![new])
+    }
+
+    // Remove and insert nodes in one update, keeping the leaves sorted.
+    pub fn update_nodes(
+        &mut self,
+        mut removed: Vec<BigInt>,
+        mut inserted: Vec<BigInt>,
+    ) -> Result<(), String> {
+        if removed.is_empty() && inserted.is_empty() {
+            return Ok(());
+        }
+        removed.sort();
+        inserted.sort();
+        let len = self.len()?;
+        // the first changed position
+        let mut start = len;
+        if let Some(node) = removed.first() {
+            start = self
+                .search(node)?
+                .map_err(|_| "Node not found".to_string())?;
+        }
+        if let Some(node) = inserted.first() {
+            start = start.min(match self.search(node)? {
+                Ok(i) => i,
+                Err(i) => i,
+            });
+        }
 
+        // drop the removed nodes from the tail of the leaves
+        let mut removed = removed.into_iter().peekable();
+        let mut kept = Vec::new();
+        for i in start..len {
+            let v = self.stored(0, i)?;
+            if removed.peek() == Some(&v) {
+                removed.next();
+            } else {
+                kept.push(v);
+            }
+        }
+        if removed.next().is_some() {
+            return Err("Node not found".to_string());
+        }
+
+        // merge the new nodes into the tail
+        let mut tail = kept.into_iter().peekable();
+        let mut new = inserted.into_iter().peekable();
+        let mut merged = Vec::new();
+        loop {
+            let take_tail = match (tail.peek(), new.peek()) {
+                (Some(a), Some(b)) => a <= b,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let next = if take_tail { tail.next() } else { new.next() };
+            merged.push(next.unwrap());
+        }
+
+        let new_len = start + merged.len();
+        let updates = merged
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| ((0, start + i), v))
+            .collect();
+        self.update(updates, start, len.max(new_len), new_len)
+    }
+
+    // insert a node
+    pub fn insert_node(&mut self, node: BigInt) -> Result<(), String> {
+        self.insert_nodes(vec![node])
+    }
+
+    // Set the leaf at idx (append it if idx == len) and rehash its path.
+    // Unlike `insert_nodes`, the order of the leaves is not maintained.
+    pub fn set_node_raw(&mut self, idx: usize, node: BigInt) -> Result<(), String> {
+        let len = self.len()?;
+        if idx > len {
+            return Err("Invalid leaf position".to_string());
+        }
+        let mut updates = HashMap::new();
+        updates.insert((0, idx), node);
+        self.update(updates, idx, idx + 1, len.max(idx + 1))
+    }
+
+    // Recompute the parents of the positions in [lo, hi) of a tree with `len` leaves,
+    // and commit them together with the updated leaves.
+    fn update(
+        &mut self,
+        mut updates: HashMap<(usize, usize), BigInt>,
+        mut lo: usize,
+        mut hi: usize,
+        len: usize,
+    ) -> Result<(), String> {
         for i in 0..(self.tiers - 1) {
-            if self.nodes[i].len() % 2 != 0 {
-                self.nodes[i].push(self.empty_nodes[i].clone());
+            let (parent_lo, parent_hi) = (lo / 2, (hi + 1) / 2);
+            // positions beyond the last leaf are empty, they are not written
+            for j in parent_lo..parent_hi.min(level_len(len, i + 1)) {
+                let left = self.read(&updates, len, i, 2 * j)?;
+                let right = self.read(&updates, len, i, 2 * j + 1)?;
+                updates.insert((i + 1, j), poseidon_hash(vec![&left, &right]).unwrap());
             }
-            self.nodes[i + 1] = self.nodes[i]
-                .chunks(2)
-                .map(|x| poseidon_hash(x.iter().collect()).unwrap())
-                .collect();
+            lo = parent_lo;
+            hi = parent_hi;
+        }
+        self.store.commit(updates.into_iter().collect(), len)
+    }
+
+    // read a node of a tree with `len` leaves, taking the pending updates into account
+    fn read(
+        &self,
+        updates: &HashMap<(usize, usize), BigInt>,
+        len: usize,
+        level: usize,
+        idx: usize,
+    ) -> Result<BigInt, String> {
+        if idx >= level_len(len, level) {
+            return Ok(self.empty_nodes[level].clone());
+        }
+        match updates.get(&(level, idx)) {
+            Some(v) => Ok(v.clone()),
+            None => self.stored(level, idx),
         }
+    }
+
+    // Copy the non-empty nodes into an in-memory tree.
+    pub fn to_memory(&self) -> Result<MerkleTree, String> {
+        let len = self.len()?;
+        let nodes = (0..self.tiers)
+            .map(|level| {
+                (0..level_len(len, level))
+                    .map(|i| self.stored(level, i))
+                    .collect::<Result<Vec<BigInt>, String>>()
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(MerkleTree {
+            tiers: self.tiers,
+            empty_nodes: self.empty_nodes.clone(),
+            store: MemoryStore {
+                nodes,
+                leaf_count: len,
+            },
+        })
+    }
 
-        self.nodes[0] = self.nodes[0][0..self.len].to_vec();
+    // Write all the non-empty nodes into an empty store, e.g. to move the tree to a
+    // database.
+    pub fn copy_into<T: NodeStore>(&self, store: &mut T) -> Result<(), String> {
+        if store.leaf_count()? != 0 {
+            return Err("The store is not empty".to_string());
+        }
+        let len = self.len()?;
+        let mut nodes = Vec::new();
+        for level in 0..self.tiers {
+            for i in 0..level_len(len, level) {
+                nodes.push(((level, i), self.stored(level, i)?));
+            }
+        }
+        store.commit(nodes, len)
     }
 
-    // Generate existence proof for the key.
-    pub fn gen_inproof_raw(&self, mut idx: usize) -> InProof {
+    // Generate existence proof for the leaf at idx.
+    pub fn gen_inproof_raw(&self, mut idx: usize) -> Result<InProof, String> {
         let mut path = Vec::new();
         let mut flags = Vec::new();
-        let value = self.nodes[0][idx].clone();
+        let value = self
+            .node(0, idx)?
+            .ok_or("Invalid leaf position".to_string())?;
         for i in 0..(self.tiers - 1) {
             if idx % 2 == 0 {
                 flags.push(0);
-                let node = self.nodes[i].get(idx + 1).unwrap_or(&self.empty_nodes[i]);
-                path.push(node.clone());
+                path.push(self.node_or_empty(i, idx + 1)?);
             } else {
                 flags.push(1);
-                path.push(self.nodes[i][idx - 1].clone());
+                path.push(self.node_or_empty(i, idx - 1)?);
             }
             idx >>= 1;
         }
-        InProof { value, path, flags }
+        Ok(InProof { value, path, flags })
     }
 
     // Generate existence proof for the data.
-    pub fn gen_inproof(&self, node: BigInt) -> Result<InProof, usize> {
-        let idx = self.nodes[0].binary_search(&node)?;
-        Ok(self.gen_inproof_raw(idx))
+    pub fn gen_inproof(&self, node: BigInt) -> Result<InProof, String> {
+        let idx = self
+            .search(&node)?
+            .map_err(|_| "Node not found".to_string())?;
+        self.gen_inproof_raw(idx)
+    }
+
+    // Generate one proof for the leaves at the positions.
+    pub fn gen_multiproof_raw(&self, mut indices: Vec<usize>) -> Result<MultiProof, String> {
+        indices.sort();
+        indices.dedup();
+        let leaves = indices
+            .iter()
+            .map(|i| {
+                self.node(0, *i)?
+                    .map(|v| (*i, v))
+                    .ok_or("Invalid leaf position".to_string())
+            })
+            .collect::<Result<_, String>>()?;
+        let mut nodes = Vec::new();
+        for i in 0..(self.tiers - 1) {
+            let mut next = Vec::new();
+            let mut k = 0;
+            while k < indices.len() {
+                let idx = indices[k];
+                if idx % 2 == 0 && k + 1 < indices.len() && indices[k + 1] == idx + 1 {
+                    // the sibling is computed by the verifier
+                    k += 1;
+                } else {
+                    nodes.push(self.node_or_empty(i, idx ^ 1)?);
+                }
+                next.push(idx / 2);
+                k += 1;
+            }
+            indices = next;
+        }
+        Ok(MultiProof {
+            leaves,
+            nodes,
+            depth: self.tiers - 1,
+        })
+    }
+
+    // Generate one existence proof for all the data.
+    pub fn gen_multiproof(&self, nodes: &[BigInt]) -> Result<MultiProof, String> {
+        let mut indices = Vec::new();
+        for node in nodes {
+            match self.search(node)? {
+                Ok(i) => indices.push(i),
+                Err(_) => return Err(format!("Node not found: {}", node)),
+            }
+        }
+        self.gen_multiproof_raw(indices)
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 // Merkle tree used for non-existence proof,
 // use two Merkle trees to reduce verification overhead.
-pub struct DualTree {
-    pub tree0: MerkleTree,
-    pub tree1: MerkleTree,
+pub struct DualTree<S = MemoryStore> {
+    pub tree0: MerkleTree<S>,
+    pub tree1: MerkleTree<S>,
 }
 
 impl DualTree {
-    // Initialize the Merkle tree.
+    // Initialize the Merkle tree in memory.
     pub fn new(tiers: usize) -> Self {
-        let mut tree0 = MerkleTree::new(tiers);
-        let mut tree1 = MerkleTree::new(tiers);
-        // Mark the left boundary and the right boundary.
-        tree0.insert_nodes(vec![ZERO.clone(), BNMAX.clone()]);
-        tree1.insert_nodes(vec![ZERO.clone(), ZERO.clone(), BNMAX.clone()]);
+        Self::with_stores(tiers, MemoryStore::new(tiers), MemoryStore::new(tiers)).unwrap()
+    }
+}
 
-        Self { tree0, tree1 }
+impl<S: NodeStore> DualTree<S> {
+    // Initialize the Merkle tree on the given stores, existing nodes are kept.
+    pub fn with_stores(tiers: usize, store0: S, store1: S) -> Result<Self, String> {
+        let mut tree0 = MerkleTree::with_store(tiers, store0);
+        let mut tree1 = MerkleTree::with_store(tiers, store1);
+        if tree0.is_empty()? {
+            // Mark the left boundary and the right boundary.
+            tree0.insert_nodes(vec![ZERO.clone(), BNMAX.clone()])?;
+        }
+        if tree1.is_empty()? {
+            tree1.insert_nodes(vec![ZERO.clone(), ZERO.clone(), BNMAX.clone()])?;
+        }
+        let mut tree = Self { tree0, tree1 };
+        tree.repair()?;
+
+        Ok(tree)
+    }
+
+    // tree1 is tree0 with an extra zero leaf. tree0 is always written first, so
+    // after a crash tree1 may miss the last update, which is replayed here.
+    fn repair(&mut self) -> Result<(), String> {
+        let leaves0 = self.tree0.leaves()?;
+        let leaves1: Vec<BigInt> = self.tree1.leaves()?.into_iter().skip(1).collect();
+        let (mut missing, mut extra) = (Vec::new(), Vec::new());
+        let (mut i, mut j) = (0, 0);
+        while i < leaves0.len() || j < leaves1.len() {
+            if j == leaves1.len() || (i < leaves0.len() && leaves0[i] < leaves1[j]) {
+                missing.push(leaves0[i].clone());
+                i += 1;
+            } else if i == leaves0.len() || leaves1[j] < leaves0[i] {
+                extra.push(leaves1[j].clone());
+                j += 1;
+            } else {
+                i += 1;
+                j += 1;
+            }
+        }
+        self.tree1.update_nodes(extra, missing)
     }
 
     // root of the merkle tree
-    pub fn roots(&self) -> (BigInt, BigInt) {
-        (self.tree0.root(), self.tree1.root())
+    pub fn roots(&self) -> Result<(BigInt, BigInt), String> {
+        Ok((self.tree0.root()?, self.tree1.root()?))
+    }
+
+    // Copy both trees into memory.
+    pub fn to_memory(&self) -> Result<DualTree, String> {
+        Ok(DualTree {
+            tree0: self.tree0.to_memory()?,
+            tree1: self.tree1.to_memory()?,
+        })
     }
 
     // insert nodes
-    pub fn insert_nodes(&mut self, nodes: Vec<BigInt>) {
-        self.tree0.insert_nodes(nodes.clone());
-        self.tree1.insert_nodes(nodes.clone());
+    pub fn insert_nodes(&mut self, nodes: Vec<BigInt>) -> Result<(), String> {
+        self.update_nodes(Vec::new(), nodes)
+    }
+
+    // Remove nodes, e.g. to reinstate revoked credentials.
+    pub fn remove_nodes(&mut self, nodes: Vec<BigInt>) -> Result<(), String> {
+        self.update_nodes(nodes, Vec::new())
+    }
+
+    // replace a node with another one
+    pub fn replace_node(&mut self, old: BigInt, new: BigInt) -> Result<(), String> {
+        self.update_nodes(vec![old], vec![new])
+    }
+
+    // Remove and insert nodes in both trees, the boundaries can not be removed.
+    pub fn update_nodes(
+        &mut self,
+        removed: Vec<BigInt>,
+        inserted: Vec<BigInt>,
+    ) -> Result<(), String> {
+        if removed.iter().any(|x| *x == *ZERO || *x == *BNMAX) {
+            return Err("Boundary nodes can not be removed".to_string());
+        }
+        self.tree0.update_nodes(removed.clone(), inserted.clone())?;
+        self.tree1.update_nodes(removed, inserted)
+    }
+
+    // The pair of adjacent leaves around the node, given as (tree, position of the
+    // right leaf). The right leaf is always at an odd position, in tree0 if the node
+    // would be inserted at an odd position, otherwise in tree1 which is shifted by one.
+    fn pair_position(&self, node: &BigInt) -> Result<(usize, usize), String> {
+        match self.tree0.search(node)? {
+            Ok(_) => Err(format!("Node already in the tree: {}", node)),
+            Err(i) if i % 2 == 1 => Ok((0, i)),
+            Err(i) => Ok((1, i + 1)),
+        }
     }
 
     // Generate non-existence proof for the node.
-    pub fn gen_notinproof(&self, node: BigInt) -> Result<NotInProof, usize> {
-        let idx = self.tree0.nodes[0].binary_search(&node);
-
-        match idx {
-            Ok(i) => Err(i),
-            Err(i) => {
-                let (proof, root) = if i % 2 == 1 {
-                    (self.tree0.gen_inproof_raw(i), self.tree0.root())
-                } else {
-                    (self.tree1.gen_inproof_raw(i), self.tree1.root())
-                };
-                Ok(NotInProof {
-                    value: node,
-                    siblings: [proof.path[0].clone(), proof.value],
-                    path: proof.path[1..].to_vec(),
-                    flags: proof.flags[1..].to_vec(),
-                    root,
-                })
-            }
+    pub fn gen_notinproof(&self, node: BigInt) -> Result<NotInProof, String> {
+        let (proof, root) = match self.pair_position(&node)? {
+            (0, i) => (self.tree0.gen_inproof_raw(i)?, self.tree0.root()?),
+            (_, i) => (self.tree1.gen_inproof_raw(i)?, self.tree1.root()?),
+        };
+        Ok(NotInProof {
+            value: node,
+            siblings: [proof.path[0].clone(), proof.value],
+            path: proof.path[1..].to_vec(),
+            flags: proof.flags[1..].to_vec(),
+            root,
+        })
+    }
+
+    // Generate one non-existence proof for all the nodes.
+    pub fn gen_multi_notinproof(&self, nodes: Vec<BigInt>) -> Result<MultiNotInProof, String> {
+        let mut indices = [Vec::new(), Vec::new()];
+        for node in &nodes {
+            let (tree, i) = self.pair_position(node)?;
+            indices[tree].push(i - 1);
+            indices[tree].push(i);
+        }
+        let [indices0, indices1] = indices;
+        let proof0 = if indices0.is_empty() {
+            None
+        } else {
+            Some(self.tree0.gen_multiproof_raw(indices0)?)
+        };
+        let proof1 = if indices1.is_empty() {
+            None
+        } else {
+            Some(self.tree1.gen_multiproof_raw(indices1)?)
+        };
+
+        Ok(MultiNotInProof {
+            values: nodes,
+            proof0,
+            proof1,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// Leaf of the indexed Merkle tree, the leaves form a linked list sorted by value.
+pub struct IndexedLeaf {
+    pub value: BigInt,
+    // the next larger value in the tree, 0 if this is the largest one
+    pub next_value: BigInt,
+    // position of the next leaf
+    pub next_index: usize,
+}
+
+impl IndexedLeaf {
+    // hash of the leaf
+    pub fn hash(&self) -> BigInt {
+        poseidon_hash(vec![
+            &self.value,
+            &self.next_value,
+            &self.next_index.to_bigint().unwrap(),
+        ])
+        .unwrap()
+    }
+
+    // whether the value lies strictly between this leaf and the next one
+    pub fn covers(&self, value: &BigInt) -> bool {
+        value > &self.value && (self.next_value == *ZERO || value < &self.next_value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// Proof that the value is not in the indexed Merkle tree.
+pub struct IndexedNotInProof {
+    pub value: BigInt,
+    // the leaf whose range covers the value
+    pub low_leaf: IndexedLeaf,
+    pub path: Vec<BigInt>,
+    pub flags: Vec<usize>,
+}
+
+impl IndexedNotInProof {
+    // verify the proof
+    pub fn verify(&self, root: &BigInt) -> bool {
+        if self.value >= *Q || !self.low_leaf.covers(&self.value) {
+            return false;
+        }
+        InProof {
+            value: self.low_leaf.hash(),
+            path: self.path.clone(),
+            flags: self.flags.clone(),
+        }
+        .verify(root)
+    }
+
+    // position of the low leaf
+    pub fn key(&self) -> BigInt {
+        InProof {
+            value: ZERO.clone(),
+            path: Vec::new(),
+            flags: self.flags.clone(),
+        }
+        .key()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// Indexed (linked-list) Merkle tree used for non-existence proofs with a single tree.
+// Leaves are appended in insertion order, so an insertion only rehashes two paths.
+pub struct IndexedTree {
+    pub leaves: Vec<IndexedLeaf>,
+    pub tree: MerkleTree,
+    // position of each value
+    pub positions: BTreeMap<BigInt, usize>,
+}
+
+impl IndexedTree {
+    // Initialize the tree with the zero leaf as the left boundary.
+    pub fn new(tiers: usize) -> Self {
+        let zero = IndexedLeaf {
+            value: ZERO.clone(),
+            next_value: ZERO.clone(),
+            next_index: 0,
+        };
+        let mut tree = MerkleTree::new(tiers);
+        tree.set_node_raw(0, zero.hash()).unwrap();
+        let mut positions = BTreeMap::new();
+        positions.insert(ZERO.clone(), 0);
+
+        Self {
+            leaves: vec![zero],
+            tree,
+            positions,
+        }
+    }
+
+    // root of the merkle tree
+    pub fn root(&self) -> Result<BigInt, String> {
+        self.tree.root()
+    }
+
+    // Position of the leaf whose range covers the value, which must be in (0, Q).
+    fn low_index(&self, value: &BigInt) -> Result<usize, String> {
+        if value <= &*ZERO || value >= &*Q {
+            return Err("Value out of range".to_string());
+        }
+        self.positions
+            .range::<BigInt, _>(..value)
+            .next_back()
+            .map(|(_, idx)| *idx)
+            .ok_or("Missing zero leaf".to_string())
+    }
+
+    // insert a node
+    pub fn insert_node(&mut self, node: BigInt) -> Result<(), String> {
+        let low = self.low_index(&node)?;
+        if self.positions.contains_key(&node) {
+            return Err("Value already in the tree".to_string());
+        }
+        let idx = self.leaves.len();
+        let leaf = IndexedLeaf {
+            value: node.clone(),
+            next_value: self.leaves[low].next_value.clone(),
+            next_index: self.leaves[low].next_index,
+        };
+        self.leaves[low].next_value = node.clone();
+        self.leaves[low].next_index = idx;
+
+        self.tree.set_node_raw(low, self.leaves[low].hash())?;
+        self.tree.set_node_raw(idx, leaf.hash())?;
+        self.leaves.push(leaf);
+        self.positions.insert(node, idx);
+        Ok(())
+    }
+
+    // insert nodes
+    pub fn insert_nodes(&mut self, nodes: Vec<BigInt>) -> Result<(), String> {
+        for node in nodes {
+            self.insert_node(node)?;
+        }
+        Ok(())
+    }
+
+    // Generate non-existence proof for the node, which must be in (0, Q) and not in
+    // the tree.
+    pub fn gen_notinproof(&self, node: BigInt) -> Result<IndexedNotInProof, String> {
+        if let Some(idx) = self.positions.get(&node) {
+            return Err(format!("Value already in the tree at {}", idx));
         }
+        let low = self.low_index(&node)?;
+        let proof = self.tree.gen_inproof_raw(low)?;
+        Ok(IndexedNotInProof {
+            value: node,
+            low_leaf: self.leaves[low].clone(),
+            path: proof.path,
+            flags: proof.flags,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{DualTree, MerkleTree};
-    use num_bigint::ToBigInt;
+    use super::{DualTree, IndexedTree, MerkleTree, ZERO};
+    use crate::tree_store::DiskStore;
+    use baby_jub::{poseidon_hash, Q};
+    use num_bigint::{BigInt, ToBigInt};
+
+    // root computed by rebuilding every level from the sorted leaves
+    fn rebuild_root(tree: &MerkleTree) -> BigInt {
+        let mut level: Vec<BigInt> = (0..tree.len().unwrap())
+            .map(|i| tree.node(0, i).unwrap().unwrap())
+            .collect();
+        for i in 0..(tree.tiers - 1) {
+            if level.len() % 2 != 0 {
+                level.push(tree.empty_nodes[i].clone());
+            }
+            level = level
+                .chunks(2)
+                .map(|x| poseidon_hash(x.iter().collect()).unwrap())
+                .collect();
+        }
+        level
+            .get(0)
+            .unwrap_or(&tree.empty_nodes[tree.tiers - 1])
+            .clone()
+    }
+
+    #[test]
+    // incremental insertion gives the same root as a full rebuild
+    fn test_incremental_insert() {
+        let mut tree = MerkleTree::new(16);
+        assert_eq!(tree.root().unwrap(), rebuild_root(&tree));
+        tree.insert_nodes(
+            (10..20usize)
+                .map(|x| (x * 3).to_bigint().unwrap())
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(tree.root().unwrap(), rebuild_root(&tree));
+        // append on the right
+        tree.insert_node(100.to_bigint().unwrap()).unwrap();
+        assert_eq!(tree.root().unwrap(), rebuild_root(&tree));
+        // insert in the middle and on the left
+        tree.insert_nodes(vec![
+            44.to_bigint().unwrap(),
+            1.to_bigint().unwrap(),
+            31.to_bigint().unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(tree.root().unwrap(), rebuild_root(&tree));
+        assert_eq!(tree.len().unwrap(), 14);
+
+        let proof = tree.gen_inproof(44.to_bigint().unwrap()).unwrap();
+        assert!(proof.verify(&tree.root().unwrap()));
+        // only non-empty nodes are stored
+        for i in 1..tree.tiers {
+            let nodes = &tree.store.nodes;
+            assert_eq!(nodes[i].len(), (nodes[i - 1].len() + 1) / 2);
+        }
+    }
+
+    #[test]
+    // removal and replacement give the same root as a tree built from the result
+    fn test_remove() {
+        let nodes: Vec<BigInt> = (1..20usize).map(|x| (x * 5).to_bigint().unwrap()).collect();
+        let mut tree = MerkleTree::new(16);
+        tree.insert_nodes(nodes.clone()).unwrap();
+
+        // the tree is unchanged if a node is missing
+        let root = tree.root().unwrap();
+        assert!(tree
+            .remove_nodes(vec![10.to_bigint().unwrap(), 11.to_bigint().unwrap()])
+            .is_err());
+        assert_eq!(tree.root().unwrap(), root);
+
+        tree.remove_nodes(vec![10.to_bigint().unwrap(), 95.to_bigint().unwrap()])
+            .unwrap();
+        tree.replace_node(50.to_bigint().unwrap(), 1.to_bigint().unwrap())
+            .unwrap();
+        assert_eq!(tree.root().unwrap(), rebuild_root(&tree));
+        assert_eq!(tree.len().unwrap(), 17);
+
+        let mut expected = MerkleTree::new(16);
+        expected
+            .insert_nodes(
+                nodes
+                    .into_iter()
+                    .filter(|x| *x != 10.to_bigint().unwrap())
+                    .filter(|x| *x != 95.to_bigint().unwrap())
+                    .filter(|x| *x != 50.to_bigint().unwrap())
+                    .chain(vec![1.to_bigint().unwrap()])
+                    .collect(),
+            )
+            .unwrap();
+        assert_eq!(tree, expected);
+        assert!(tree.gen_inproof(50.to_bigint().unwrap()).is_err());
+
+        // a removed node has a valid non-existence proof again
+        let mut dual = DualTree::new(64);
+        dual.insert_nodes(vec![3.to_bigint().unwrap(), 8.to_bigint().unwrap()])
+            .unwrap();
+        assert!(dual.gen_notinproof(8.to_bigint().unwrap()).is_err());
+        dual.remove_nodes(vec![8.to_bigint().unwrap()]).unwrap();
+        let proof = dual.gen_notinproof(8.to_bigint().unwrap()).unwrap();
+        let (root1, root2) = dual.roots().unwrap();
+        assert!(proof.verify([&root1, &root2]));
+        assert!(dual.remove_nodes(vec![0.to_bigint().unwrap()]).is_err());
+    }
 
     #[test]
     // test existence proof
@@ -271,11 +989,12 @@ mod tests {
                 .step_by(2)
                 .map(|x| x.to_bigint().unwrap())
                 .collect(),
-        );
+        )
+        .unwrap();
         let proof = tree.gen_inproof(5.to_bigint().unwrap()).unwrap();
-        assert!(proof.verify(&tree.root()));
+        assert!(proof.verify(&tree.root().unwrap()));
         let proof = tree.gen_inproof(19.to_bigint().unwrap()).unwrap();
-        assert!(proof.verify(&tree.root()));
+        assert!(proof.verify(&tree.root().unwrap()));
     }
 
     #[test]
@@ -287,10 +1006,173 @@ mod tests {
                 .step_by(2)
                 .map(|x| x.to_bigint().unwrap())
                 .collect(),
-        );
+        )
+        .unwrap();
+        let proof = tree.gen_notinproof(8.to_bigint().unwrap()).unwrap();
+
+        let (root1, root2) = tree.roots().unwrap();
+        assert!(proof.verify([&root1, &root2]));
+        // the pair of leaves is in tree1
+        let proof = tree.gen_notinproof(6.to_bigint().unwrap()).unwrap();
+        assert_eq!(proof.root, root2);
+        assert!(proof.verify([&root1, &root2]));
+    }
+
+    #[test]
+    // Non-existence proofs of the values between every pair of adjacent leaves. The
+    // pairs alternate between tree0 and tree1, and the siblings of the proofs in tree1
+    // were once given in reverse order.
+    fn test_notinproof_pairs() {
+        let mut tree = DualTree::new(64);
+        tree.insert_nodes(
+            (1..20usize)
+                .step_by(2)
+                .map(|x| x.to_bigint().unwrap())
+                .collect(),
+        )
+        .unwrap();
+        let (root1, root2) = tree.roots().unwrap();
+        let mut in_tree1 = 0;
+        for x in (2..=20usize).step_by(2) {
+            let proof = tree.gen_notinproof(x.to_bigint().unwrap()).unwrap();
+            assert!(proof.siblings[0] < proof.value && proof.value < proof.siblings[1]);
+            assert!(proof.verify([&root1, &root2]));
+            if proof.root == root2 {
+                in_tree1 += 1;
+            }
+        }
+        assert_eq!(in_tree1, 5);
+    }
+
+    #[test]
+    // test proofs of several leaves
+    fn test_multiproof() {
+        let mut tree = MerkleTree::new(41);
+        tree.insert_nodes((1..40usize).map(|x| (x * 2).to_bigint().unwrap()).collect())
+            .unwrap();
+        let nodes: Vec<BigInt> = vec![4, 6, 30, 78]
+            .into_iter()
+            .map(|x| x.to_bigint().unwrap())
+            .collect();
+        let proof = tree.gen_multiproof(&nodes).unwrap();
+        assert!(proof.verify(&tree.root().unwrap()));
+        assert!(nodes.iter().all(|x| proof.contains(x)));
+        // shared nodes are only included once
+        assert!(proof.nodes.len() < nodes.len() * (tree.tiers - 1));
+        assert!(tree
+            .gen_multiproof(&[4.to_bigint().unwrap(), 5.to_bigint().unwrap()])
+            .is_err());
+
+        let mut forged = proof.clone();
+        forged.leaves[1].1 = 7.to_bigint().unwrap();
+        assert!(!forged.verify(&tree.root().unwrap()));
+        let mut forged = proof;
+        forged.nodes.pop();
+        assert!(!forged.verify(&tree.root().unwrap()));
+
+        // non-existence of several values, with pairs in both trees
+        let mut dual = DualTree::new(41);
+        dual.insert_nodes((1..20usize).map(|x| (x * 2).to_bigint().unwrap()).collect())
+            .unwrap();
+        let (root1, root2) = dual.roots().unwrap();
+        let values: Vec<BigInt> = vec![1, 3, 5, 21, 100]
+            .into_iter()
+            .map(|x| x.to_bigint().unwrap())
+            .collect();
+        let proof = dual.gen_multi_notinproof(values).unwrap();
+        assert!(proof.proof0.is_some() && proof.proof1.is_some());
+        assert!(proof.verify([&root1, &root2]));
+        assert!(!proof.verify([&root2, &root1]));
+        assert!(dual
+            .gen_multi_notinproof(vec![3.to_bigint().unwrap(), 4.to_bigint().unwrap()])
+            .is_err());
+
+        let mut forged = proof;
+        forged.values.push(8.to_bigint().unwrap());
+        assert!(!forged.verify([&root1, &root2]));
+    }
+
+    #[test]
+    // a tree stored on disk matches the in-memory tree and survives reopening
+    fn test_disk_store() {
+        let dir = std::env::temp_dir().join(format!("hades_tree_{}", rand::random::<u64>()));
+        let path = dir.to_str().unwrap();
+        let nodes: Vec<BigInt> = (1..30usize).map(|x| (x * 7).to_bigint().unwrap()).collect();
+
+        let mut mem = DualTree::new(41);
+        let mut disk = DualTree::with_stores(
+            41,
+            DiskStore::open(path, "tree0").unwrap(),
+            DiskStore::open(path, "tree1").unwrap(),
+        )
+        .unwrap();
+        for chunk in nodes.chunks(4) {
+            mem.insert_nodes(chunk.to_vec()).unwrap();
+            disk.insert_nodes(chunk.to_vec()).unwrap();
+        }
+        assert_eq!(mem.roots().unwrap(), disk.roots().unwrap());
+
+        // the nodes are read back from the database
+        let reopened = DualTree::with_stores(
+            41,
+            DiskStore::open(path, "tree0").unwrap(),
+            DiskStore::open(path, "tree1").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(reopened.roots().unwrap(), mem.roots().unwrap());
+        let proof = reopened.gen_notinproof(8.to_bigint().unwrap()).unwrap();
+        assert!(proof.verify([&mem.roots().unwrap().0, &mem.roots().unwrap().1]));
+
+        // an in-memory tree is moved into an empty store only
+        let mut store = DiskStore::open(path, "copy").unwrap();
+        mem.tree0.copy_into(&mut store).unwrap();
+        let copy = MerkleTree::with_store(41, store.clone());
+        assert_eq!(copy.root().unwrap(), mem.tree0.root().unwrap());
+        assert_eq!(copy.len().unwrap(), mem.tree0.len().unwrap());
+        assert!(mem.tree1.copy_into(&mut store).is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    // test non-existence proof of the indexed tree
+    fn test_indexed_notinproof() {
+        let mut tree = IndexedTree::new(41);
+        tree.insert_nodes(
+            (1..20usize)
+                .step_by(2)
+                .rev()
+                .map(|x| x.to_bigint().unwrap())
+                .collect(),
+        )
+        .unwrap();
+        assert!(tree.insert_node(5.to_bigint().unwrap()).is_err());
+
+        let root = tree.root().unwrap();
         let proof = tree.gen_notinproof(8.to_bigint().unwrap()).unwrap();
+        assert_eq!(proof.low_leaf.value, 7.to_bigint().unwrap());
+        assert!(proof.verify(&root));
+        // larger than every value
+        let proof = tree.gen_notinproof(100.to_bigint().unwrap()).unwrap();
+        assert!(proof.verify(&root));
+        assert!(tree.gen_notinproof(9.to_bigint().unwrap()).is_err());
+
+        // the proof is invalid once the value is inserted
+        let proof = tree.gen_notinproof(12.to_bigint().unwrap()).unwrap();
+        tree.insert_node(12.to_bigint().unwrap()).unwrap();
+        assert!(!proof.verify(&tree.root().unwrap()));
 
-        let (root1, root2) = tree.roots();
-        assert!(proof.verify([&root1, &root2]))
+        let mut forged = tree.gen_notinproof(14.to_bigint().unwrap()).unwrap();
+        forged.value = 13.to_bigint().unwrap();
+        assert!(!forged.verify(&tree.root().unwrap()));
+
+        // values out of (0, Q) are rejected instead of panicking
+        for v in [ZERO.clone(), (-1).to_bigint().unwrap(), Q.clone()] {
+            assert!(tree.gen_notinproof(v.clone()).is_err());
+            assert!(tree.insert_node(v).is_err());
+        }
+        let mut forged = tree.gen_notinproof(100.to_bigint().unwrap()).unwrap();
+        forged.value = Q.clone() + 100.to_bigint().unwrap();
+        assert!(!forged.verify(&tree.root().unwrap()));
     }
 }
@@ -0,0 +1,202 @@
+// This file builds the assertions proven in selective disclosure. An assertion is a
+// set of predicates on the attributes of a schema (e.g. age >= 18), compiled to the
+// ranges l <= a <= r checked by the identity circuit. Attributes without predicates
+// are not constrained. The verifier rebuilds the same assertion to compute the lrcm
+// of the proof, which tells what was proven, including the schema of the attributes.
+
+use crate::ca_client::ca_hash;
+use crate::schema::{AttributeType, AttributeValue, Schema, VALUE_BITS};
+use crate::user_client::lrcm_hash;
+use num_bigint::BigInt;
+use num_traits::{One, Zero};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Predicate {
+    // no constraint
+    Any,
+    Eq(AttributeValue),
+    Ge(AttributeValue),
+    Gt(AttributeValue),
+    Le(AttributeValue),
+    Lt(AttributeValue),
+    // inclusive bounds
+    Between(AttributeValue, AttributeValue),
+    // one of the values, which must be consecutive once encoded
+    In(Vec<AttributeValue>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+// The ranges of the attributes of a schema, built from predicates.
+pub struct Assertion {
+    pub schema: Schema,
+    pub l_range: Vec<BigInt>,
+    pub r_range: Vec<BigInt>,
+}
+
+// largest value compared by the identity circuit
+pub fn max_value() -> BigInt {
+    (BigInt::one() << VALUE_BITS) - 1
+}
+
+impl Assertion {
+    // An assertion without constraints.
+    pub fn new(schema: &Schema) -> Self {
+        Self {
+            schema: schema.clone(),
+            l_range: vec![BigInt::zero(); schema.len()],
+            r_range: vec![max_value(); schema.len()],
+        }
+    }
+
+    // Add a predicate on the attribute with the name. Predicates on the same
+    // attribute are combined.
+    pub fn require(mut self, name: &str, predicate: Predicate) -> Result<Self, String> {
+        let i = self.schema.index(name).ok_or("Unknown attribute")?;
+        let ordered = matches!(
+            self.schema.attributes[i].attr_type,
+            AttributeType::Integer | AttributeType::Date
+        );
+        let encode = |v: &AttributeValue| self.schema.encode_value(i, v);
+        let (l, r) = match &predicate {
+            Predicate::Any => return Ok(self),
+            Predicate::Eq(v) => {
+                let v = encode(v)?;
+                (v.clone(), v)
+            }
+            Predicate::In(values) => {
+                let mut values = values.iter().map(encode).collect::<Result<Vec<_>, _>>()?;
+                values.sort();
+                values.dedup();
+                let (first, last) = match (values.first(), values.last()) {
+                    (Some(first), Some(last)) => (first.clone(), last.clone()),
+                    _ => return Err("Empty set".to_string()),
+                };
+                if last.clone() - first.clone() + 1 != BigInt::from(values.len()) {
+                    return Err("The set is not a range".to_string());
+                }
+                (first, last)
+            }
+            _ if !ordered => return Err(format!("{} is not ordered", name)),
+            Predicate::Ge(v) => (encode(v)?, max_value()),
+            Predicate::Gt(v) => (encode(v)? + 1, max_value()),
+            Predicate::Le(v) => (BigInt::zero(), encode(v)?),
+            Predicate::Lt(v) => (BigInt::zero(), encode(v)? - 1),
+            Predicate::Between(a, b) => (encode(a)?, encode(b)?),
+        };
+
+        // intersect with the current range
+        self.l_range[i] = self.l_range[i].clone().max(l);
+        self.r_range[i] = self.r_range[i].clone().min(r);
+        if self.l_range[i] > self.r_range[i] {
+            return Err(format!("The predicates on {} cannot hold", name));
+        }
+        Ok(self)
+    }
+
+    // Check that the attributes satisfy the assertion.
+    pub fn holds(&self, attributes: &[BigInt]) -> bool {
+        attributes.len() == self.schema.len()
+            && attributes
+                .iter()
+                .zip(self.l_range.iter().zip(self.r_range.iter()))
+                .all(|(a, (l, r))| l <= a && a <= r)
+    }
+
+    // The lrcm of a proof of this assertion, for a credential of the schema of the
+    // assertion issued by the CA whose generators hash to `generators_hash`, under the
+    // challenge `ctx`.
+    pub fn lrcm(&self, generators_hash: &BigInt, ctx: &BigInt) -> Result<BigInt, String> {
+        let ca_hash = ca_hash(generators_hash, &self.schema.hash())?;
+        lrcm_hash(&self.l_range, &self.r_range, &ca_hash, ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{max_value, Assertion, Predicate};
+    use crate::schema::{AttributeDef, AttributeType, AttributeValue, Schema};
+    use num_bigint::BigInt;
+
+    #[test]
+    // predicates are compiled to ranges
+    fn test_assertion() {
+        let def = |name: &str, attr_type| AttributeDef {
+            name: name.to_string(),
+            attr_type,
+        };
+        let schema = Schema::new(
+            "passport",
+            vec![
+                def("age", AttributeType::Integer),
+                def(
+                    "country",
+                    AttributeType::Enum(vec!["CN".into(), "FR".into(), "US".into()]),
+                ),
+                def("name", AttributeType::String),
+            ],
+        )
+        .unwrap();
+        let int = |v: i64| AttributeValue::Integer(BigInt::from(v));
+        let country = |v: &str| AttributeValue::Enum(v.to_string());
+
+        let assertion = Assertion::new(&schema)
+            .require("age", Predicate::Ge(int(18)))
+            .unwrap()
+            .require("age", Predicate::Lt(int(65)))
+            .unwrap()
+            .require("country", Predicate::In(vec![country("US"), country("FR")]))
+            .unwrap()
+            .require("name", Predicate::Any)
+            .unwrap();
+        assert_eq!(
+            assertion.l_range,
+            vec![BigInt::from(18), BigInt::from(1), BigInt::from(0)]
+        );
+        assert_eq!(
+            assertion.r_range,
+            vec![BigInt::from(64), BigInt::from(2), max_value()]
+        );
+        assert!(assertion.holds(&[BigInt::from(30), BigInt::from(2), BigInt::from(7)]));
+        assert!(!assertion.holds(&[BigInt::from(17), BigInt::from(2), BigInt::from(7)]));
+
+        // the verifier rebuilds the same lrcm
+        let generators_hash = BigInt::from(1);
+        let rebuilt = Assertion::new(&schema)
+            .require("age", Predicate::Between(int(18), int(64)))
+            .unwrap()
+            .require("country", Predicate::In(vec![country("FR"), country("US")]))
+            .unwrap();
+        let ctx = BigInt::from(2);
+        assert_eq!(
+            assertion.lrcm(&generators_hash, &ctx).unwrap(),
+            rebuilt.lrcm(&generators_hash, &ctx).unwrap()
+        );
+        // the same ranges on another schema are another assertion
+        let renamed = Assertion {
+            schema: Schema::new("visa", schema.attributes.clone()).unwrap(),
+            ..assertion.clone()
+        };
+        assert_ne!(
+            assertion.lrcm(&generators_hash, &ctx).unwrap(),
+            renamed.lrcm(&generators_hash, &ctx).unwrap()
+        );
+
+        // invalid predicates
+        let base = Assertion::new(&schema);
+        assert!(base
+            .clone()
+            .require("country", Predicate::In(vec![country("CN"), country("US")]))
+            .is_err());
+        assert!(base
+            .clone()
+            .require("name", Predicate::Ge(AttributeValue::String("a".into())))
+            .is_err());
+        assert!(base.clone().require("height", Predicate::Any).is_err());
+        assert!(base
+            .require("age", Predicate::Gt(int(30)))
+            .unwrap()
+            .require("age", Predicate::Le(int(30)))
+            .is_err());
+    }
+}
@@ -0,0 +1,284 @@
+// This file implements the presentations of proofs verified off-chain. A presentation
+// is a self-describing JSON object: the circuit and the hash of its verifying key, the
+// statement proven (an assertion, a set, or an access token), the challenge of the
+// verifier, and the proof with its public inputs in the format of snarkjs. A
+// `Verifier` holds only the verifying keys and the trusted CAs exported by the
+// committee (see `Committee::verifier`), so that dapps can check presentations
+// without the proving keys.
+
+use crate::attribute_set::{set_commit, AttributeSet};
+use crate::ca_client::{ca_hash, generators_hash};
+use crate::challenge::Challenge;
+use crate::predicate::Assertion;
+use crate::snarkjs::{
+    inputs_from_json, inputs_to_json, proof_to_json, verify, vk_from_json, vk_to_json,
+};
+use crate::user_client::appkey_ss;
+use ark_bn254::{Bn254, Fr};
+use ark_ff::bytes::ToBytes;
+use ark_groth16::{Proof, VerifyingKey};
+use baby_jub::Point;
+use ethers::utils::{hex, keccak256};
+use num_bigint::{BigInt, ToBigInt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CircuitId {
+    // circuits/pedersen_commit.circom
+    Identity,
+    // circuits/attribute_set.circom
+    AttributeSet,
+    // circuits/sybil_check.circom
+    AppKey,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// What a presentation proves.
+pub enum Statement {
+    // the attributes of a credential of the CA satisfy the assertion
+    Assertion {
+        ca_key: Point,
+        assertion: Assertion,
+    },
+    // the attribute at `index` of a credential of the CA is (or is not) in the set
+    // committed by one of the roots
+    AttributeSet {
+        ca_key: Point,
+        index: usize,
+        member: bool,
+        roots: Vec<BigInt>,
+    },
+    // the access token of the application
+    AppKey {
+        appid: BigInt,
+        appkey: BigInt,
+        c2: Point,
+    },
+}
+
+impl Statement {
+    // statement of a set proof
+    pub fn set(ca_key: &Point, index: usize, set: &AttributeSet) -> Result<Self, String> {
+        Ok(Statement::AttributeSet {
+            ca_key: ca_key.clone(),
+            index,
+            member: set.is_member(),
+            roots: set.roots()?,
+        })
+    }
+
+    // the circuit proving the statement
+    pub fn circuit(&self) -> CircuitId {
+        match self {
+            Statement::Assertion { .. } => CircuitId::Identity,
+            Statement::AttributeSet { .. } => CircuitId::AttributeSet,
+            Statement::AppKey { .. } => CircuitId::AppKey,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Presentation {
+    pub circuit: CircuitId,
+    // hex of the keccak256 hash of the verifying key
+    pub vk_hash: String,
+    pub statement: Statement,
+    pub challenge: Challenge,
+    // proof.json and public.json of snarkjs
+    pub proof: Value,
+    pub public_inputs: Value,
+}
+
+// hash of a verifying key, identifying the parameters of a circuit
+pub fn vk_hash(vk: &VerifyingKey<Bn254>) -> String {
+    let mut bytes = Vec::new();
+    vk.write(&mut bytes).unwrap();
+    hex::encode(keccak256(bytes))
+}
+
+// the public input is the value
+fn input_is(input: &Fr, value: &BigInt) -> bool {
+    Fr::from_str(&value.to_string()).ok() == Some(*input)
+}
+
+impl Presentation {
+    // Create a presentation of the proof of the statement.
+    pub fn new(
+        vk: &VerifyingKey<Bn254>,
+        statement: Statement,
+        challenge: &Challenge,
+        proof: &Proof<Bn254>,
+        public_inputs: &[Fr],
+    ) -> Self {
+        Self {
+            circuit: statement.circuit(),
+            vk_hash: vk_hash(vk),
+            statement,
+            challenge: challenge.clone(),
+            proof: proof_to_json(proof),
+            public_inputs: inputs_to_json(public_inputs),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, String> {
+        serde_json::from_str(s).map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+// Verify presentations off-chain.
+pub struct Verifier {
+    pub vks: HashMap<CircuitId, VerifyingKey<Bn254>>,
+    // hashes of the pedersen generators of the trusted CAs
+    pub ca_hashes: HashMap<Point, BigInt>,
+    // hashes of the schemas of the credentials of the trusted CAs
+    pub ca_schemas: HashMap<Point, BigInt>,
+    // public key of tpke encryption, used in access tokens
+    pub tpke_key: Option<Point>,
+}
+
+#[derive(Serialize, Deserialize)]
+// serialized verifier, with the verifying keys of snarkjs
+struct VerifierData {
+    vks: Vec<(CircuitId, Value)>,
+    ca_hashes: Vec<(Point, BigInt)>,
+    ca_schemas: Vec<(Point, BigInt)>,
+    tpke_key: Option<Point>,
+}
+
+impl Verifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // add the verifying key of a circuit
+    pub fn add_circuit(&mut self, circuit: CircuitId, vk: VerifyingKey<Bn254>) {
+        self.vks.insert(circuit, vk);
+    }
+
+    // trust the CA with its pedersen generators and the hash of its schema
+    pub fn add_ca(
+        &mut self,
+        ca_key: &Point,
+        generators: &[Point],
+        schema_hash: &BigInt,
+    ) -> Result<(), String> {
+        self.ca_hashes
+            .insert(ca_key.clone(), generators_hash(generators)?);
+        self.ca_schemas.insert(ca_key.clone(), schema_hash.clone());
+        Ok(())
+    }
+
+    pub fn remove_ca(&mut self, ca_key: &Point) {
+        self.ca_hashes.remove(ca_key);
+        self.ca_schemas.remove(ca_key);
+    }
+
+    // hash of the generators and the schema of a trusted CA
+    fn ca_hash(&self, ca_key: &Point) -> Result<BigInt, String> {
+        let generators = self.ca_hashes.get(ca_key).ok_or("Unknown CA")?;
+        let schema = self.ca_schemas.get(ca_key).ok_or("Unknown CA")?;
+        ca_hash(generators, schema)
+    }
+
+    // Verify the presentation. The caller checks that the statement is the one it
+    // asked for, and consumes the challenge (see `Challenger::consume`).
+    pub fn verify(&self, presentation: &Presentation) -> Result<bool, String> {
+        let vk = self
+            .vks
+            .get(&presentation.circuit)
+            .ok_or("Unknown circuit")?;
+        if vk_hash(vk) != presentation.vk_hash {
+            return Err("Unknown verifying key".to_string());
+        }
+        let challenge = &presentation.challenge;
+        if presentation.statement.circuit() != presentation.circuit || !challenge.is_fresh() {
+            return Ok(false);
+        }
+
+        // the public inputs must match the statement
+        let ctx = challenge.ctx()?;
+        let inputs = inputs_from_json(&presentation.public_inputs)?;
+        let valid = match &presentation.statement {
+            Statement::Assertion { ca_key, assertion } => {
+                let generators = self.ca_hashes.get(ca_key).ok_or("Unknown CA")?;
+                let schema = self.ca_schemas.get(ca_key).ok_or("Unknown CA")?;
+                *schema == assertion.schema.hash()
+                    && inputs.len() == 3
+                    && input_is(&inputs[2], &assertion.lrcm(generators, &ctx)?)
+            }
+            Statement::AttributeSet {
+                ca_key,
+                index,
+                member,
+                roots,
+            } => {
+                let ca_hash = self.ca_hash(ca_key)?;
+                let index = index.to_bigint().unwrap();
+                let member = (*member as usize).to_bigint().unwrap();
+                let mut scms = Vec::new();
+                for root in roots {
+                    scms.push(set_commit(root, &index, &member, &ca_hash, &ctx)?);
+                }
+                inputs.len() == 3 && scms.iter().any(|scm| input_is(&inputs[2], scm))
+            }
+            Statement::AppKey { appid, appkey, c2 } => {
+                let tpke_key = self.tpke_key.as_ref().ok_or("No tpke key")?;
+                let ss = appkey_ss(appid, tpke_key, c2, &ctx);
+                challenge.domain == *appid
+                    && inputs.len() == 4
+                    && input_is(&inputs[0], appkey)
+                    && input_is(&inputs[1], &ss)
+                    && input_is(&inputs[2], &tpke_key.scalar_y())
+                    && input_is(&inputs[3], &c2.scalar_y())
+            }
+        };
+        if !valid {
+            return Ok(false);
+        }
+        verify(vk, &presentation.proof, &presentation.public_inputs)
+    }
+
+    pub fn to_json(&self) -> String {
+        let data = VerifierData {
+            vks: self
+                .vks
+                .iter()
+                .map(|(c, vk)| (*c, vk_to_json(vk)))
+                .collect(),
+            ca_hashes: self
+                .ca_hashes
+                .iter()
+                .map(|(k, h)| (k.clone(), h.clone()))
+                .collect(),
+            ca_schemas: self
+                .ca_schemas
+                .iter()
+                .map(|(k, h)| (k.clone(), h.clone()))
+                .collect(),
+            tpke_key: self.tpke_key.clone(),
+        };
+        serde_json::to_string(&data).unwrap()
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, String> {
+        let data: VerifierData = serde_json::from_str(s).map_err(|e| e.to_string())?;
+        let mut vks = HashMap::new();
+        for (circuit, vk) in data.vks {
+            vks.insert(circuit, vk_from_json(&vk)?);
+        }
+        Ok(Self {
+            vks,
+            ca_hashes: data.ca_hashes.into_iter().collect(),
+            ca_schemas: data.ca_schemas.into_iter().collect(),
+            tpke_key: data.tpke_key,
+        })
+    }
+}
@@ -0,0 +1,370 @@
+// This file implements the resharing protocol of the tpke key. At least `threshold`
+// members of the old committee deal their shares to the new committee with fresh
+// polynomials, and the new members combine them with Lagrange coefficients. The new
+// committee may have a different member set and threshold, while the tpke public key
+// stays the same, so existing ciphertexts remain decryptable. Running it with the
+// same member set refreshes the shares (proactive security). Old members must erase
+// their shares once the new committee has finalized.
+
+use crate::dkg::{
+    decrypt_share, encrypt_share, eval_commitments, resolve_complaints, verify_share, Complaint,
+    ComplaintResponse, Dealing, DkgOutput,
+};
+use crate::tpke::{
+    eval_polynomial, lagrange_coefficient, modulo, random_scalar, PublicKey, SecretShare,
+};
+use baby_jub::{Point, G};
+use num_bigint::BigInt;
+use num_traits::Zero;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone)]
+// A member of the old committee, dealing its share to the new committee.
+pub struct Resharer {
+    pub share: SecretShare,
+    pub new_threshold: usize,
+    // communication public keys of the new members, indexed by (new index - 1)
+    new_peers: Vec<Point>,
+    // coefficients of the dealt polynomial, coeffs[0] is the old share
+    coeffs: Vec<BigInt>,
+}
+
+#[derive(Debug, Clone)]
+// A member of the new committee, receiving a share of the same tpke key.
+pub struct Receiver {
+    pub index: usize,
+    pub threshold: usize,
+    pub n: usize,
+    // the tpke public key which must not change
+    pub tpke_key: PublicKey,
+    // public key shards of the old members, indexed by (old index - 1)
+    old_shards: Vec<Point>,
+    old_threshold: usize,
+    // private key used to decrypt received shares
+    comm_key: BigInt,
+    // communication public keys of the new members, indexed by (new index - 1)
+    peers: Vec<Point>,
+    // commitments broadcast by each old member
+    commitments: BTreeMap<usize, Vec<Point>>,
+    // valid shares received from each old member
+    received: BTreeMap<usize, BigInt>,
+}
+
+impl Resharer {
+    // Initialize an old member.
+    pub fn new(
+        share: SecretShare,
+        new_threshold: usize,
+        new_peers: Vec<Point>,
+    ) -> Result<Self, String> {
+        if new_threshold == 0 || new_threshold > new_peers.len() {
+            return Err("Invalid threshold".to_string());
+        }
+        Ok(Self {
+            share,
+            new_threshold,
+            new_peers,
+            coeffs: Vec::new(),
+        })
+    }
+
+    // Round 1: share the old share with a fresh polynomial of the new threshold.
+    pub fn deal(&mut self) -> Dealing {
+        self.coeffs = vec![self.share.value.clone()]
+            .into_iter()
+            .chain((1..self.new_threshold).map(|_| random_scalar()))
+            .collect();
+        let commitments = self.coeffs.iter().map(|c| c * G.clone()).collect();
+        let shares = self
+            .new_peers
+            .iter()
+            .enumerate()
+            .map(|(i, pk)| encrypt_share(&eval_polynomial(&self.coeffs, i + 1), i + 1, pk))
+            .collect();
+
+        Dealing {
+            dealer: self.share.index,
+            commitments,
+            shares,
+        }
+    }
+
+    // Round 3: reveal the disputed shares for the complaints against this member.
+    pub fn respond(&self, complaints: &[Complaint]) -> Vec<ComplaintResponse> {
+        complaints
+            .iter()
+            .filter(|c| c.accused == self.share.index && c.verify(&self.new_peers))
+            .map(|c| ComplaintResponse {
+                dealer: self.share.index,
+                accuser: c.accuser,
+                share: eval_polynomial(&self.coeffs, c.accuser),
+            })
+            .collect()
+    }
+}
+
+impl Receiver {
+    // Initialize a new member with index in [1, n].
+    pub fn new(
+        index: usize,
+        threshold: usize,
+        n: usize,
+        tpke_key: PublicKey,
+        old_shards: Vec<Point>,
+        old_threshold: usize,
+    ) -> Result<Self, String> {
+        if index == 0 || index > n {
+            return Err("Invalid member index".to_string());
+        }
+        if threshold == 0 || threshold > n {
+            return Err("Invalid threshold".to_string());
+        }
+        if old_threshold == 0 || old_threshold > old_shards.len() {
+            return Err("Invalid old threshold".to_string());
+        }
+        Ok(Self {
+            index,
+            threshold,
+            n,
+            tpke_key,
+            old_shards,
+            old_threshold,
+            comm_key: random_scalar(),
+            peers: Vec::new(),
+            commitments: BTreeMap::new(),
+            received: BTreeMap::new(),
+        })
+    }
+
+    // public key used by the old members to encrypt shares
+    pub fn comm_pubkey(&self) -> Point {
+        &self.comm_key * G.clone()
+    }
+
+    // set the communication public keys of all new members
+    pub fn set_peers(&mut self, peers: Vec<Point>) -> Result<(), String> {
+        if peers.len() != self.n {
+            return Err("Invalid number of members".to_string());
+        }
+        self.peers = peers;
+        Ok(())
+    }
+
+    // Round 2: verify the shares sent to this member. A dealing is only accepted if
+    // it commits to the old share of its dealer.
+    pub fn receive(&mut self, dealings: &[Dealing]) -> Vec<Complaint> {
+        let mut complaints = Vec::new();
+        for d in dealings {
+            if d.dealer == 0
+                || d.dealer > self.old_shards.len()
+                || self.commitments.contains_key(&d.dealer)
+            {
+                continue;
+            }
+            if d.commitments.len() != self.threshold
+                || d.commitments[0] != self.old_shards[d.dealer - 1]
+            {
+                continue;
+            }
+            self.commitments.insert(d.dealer, d.commitments.clone());
+
+            let share = d
+                .shares
+                .iter()
+                .find(|s| s.receiver == self.index)
+                .map(|s| decrypt_share(s, &self.comm_key));
+            match share {
+                Some(v) if verify_share(&d.commitments, self.index, &v) => {
+                    self.received.insert(d.dealer, v);
+                }
+                _ => complaints.push(Complaint::new(self.index, d.dealer, &self.comm_key)),
+            }
+        }
+        complaints
+    }
+
+    // Resolve the complaints and compute the new share from the first `old_threshold`
+    // qualified old members.
+    pub fn finalize(
+        &mut self,
+        complaints: &[Complaint],
+        responses: &[ComplaintResponse],
+    ) -> Result<DkgOutput, String> {
+        if self.peers.len() != self.n {
+            return Err("Members are not set".to_string());
+        }
+        let disqualified = resolve_complaints(
+            &self.commitments,
+            complaints,
+            responses,
+            &self.peers,
+            self.index,
+            &mut self.received,
+        );
+        let qualified: Vec<usize> = self
+            .commitments
+            .keys()
+            .filter(|i| !disqualified.contains(*i))
+            .take(self.old_threshold)
+            .cloned()
+            .collect();
+        if qualified.len() < self.old_threshold {
+            return Err("Not enough qualified old members".to_string());
+        }
+
+        let lambdas: Vec<BigInt> = qualified
+            .iter()
+            .map(|i| lagrange_coefficient(*i, &qualified))
+            .collect();
+
+        let mut value = BigInt::zero();
+        for (i, l) in qualified.iter().zip(lambdas.iter()) {
+            let share = self
+                .received
+                .get(i)
+                .ok_or(format!("Missing share from old member {}", i))?;
+            value = modulo(&(value + l * share));
+        }
+
+        let secret_g = qualified
+            .iter()
+            .zip(lambdas.iter())
+            .fold(Point::identity(), |sum, (i, l)| {
+                sum + l * &self.commitments[i][0]
+            });
+        let public_key = PublicKey::new(vec![&secret_g]);
+        if public_key != self.tpke_key {
+            return Err("The tpke public key changed".to_string());
+        }
+        let public_shards = (1..=self.n)
+            .map(|j| {
+                qualified
+                    .iter()
+                    .zip(lambdas.iter())
+                    .fold(Point::identity(), |sum, (i, l)| {
+                        sum + l * &eval_commitments(&self.commitments[i], j)
+                    })
+            })
+            .collect();
+
+        Ok(DkgOutput {
+            share: SecretShare {
+                index: self.index,
+                value,
+            },
+            threshold: self.threshold,
+            public_key,
+            public_shards,
+            qualified,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Receiver, Resharer};
+    use crate::dkg::{Complaint, Dealing, DkgOutput};
+    use crate::tpke::{gen_key_shares, random_scalar, PublicKey, SecretShare};
+    use baby_jub::{Point, G};
+
+    // move the shares of the old members to a new committee
+    fn reshare(
+        tpke_key: &PublicKey,
+        old: &[SecretShare],
+        old_threshold: usize,
+        dealers: &[usize],
+        threshold: usize,
+        n: usize,
+    ) -> Vec<DkgOutput> {
+        let old_shards: Vec<Point> = old.iter().map(|s| s.public()).collect();
+        let mut receivers: Vec<Receiver> = (1..=n)
+            .map(|i| {
+                Receiver::new(
+                    i,
+                    threshold,
+                    n,
+                    tpke_key.clone(),
+                    old_shards.clone(),
+                    old_threshold,
+                )
+                .unwrap()
+            })
+            .collect();
+        let peers: Vec<Point> = receivers.iter().map(|r| r.comm_pubkey()).collect();
+        for r in receivers.iter_mut() {
+            r.set_peers(peers.clone()).unwrap();
+        }
+        let mut resharers: Vec<Resharer> = dealers
+            .iter()
+            .map(|i| Resharer::new(old[i - 1].clone(), threshold, peers.clone()).unwrap())
+            .collect();
+
+        let dealings: Vec<Dealing> = resharers.iter_mut().map(|r| r.deal()).collect();
+        let complaints: Vec<Complaint> = receivers
+            .iter_mut()
+            .flat_map(|r| r.receive(&dealings))
+            .collect();
+        let responses: Vec<_> = resharers
+            .iter()
+            .flat_map(|r| r.respond(&complaints))
+            .collect();
+        receivers
+            .iter_mut()
+            .map(|r| r.finalize(&complaints, &responses).unwrap())
+            .collect()
+    }
+
+    #[test]
+    // move a 2-of-3 key to a 3-of-4 committee
+    fn test_reshare() {
+        let (key, old) = gen_key_shares(2, 3).unwrap();
+        let msg = random_scalar() * G.clone();
+        let (cipher, _) = key.encrypt(&msg);
+
+        let outputs = reshare(&key, &old, 2, &[1, 3], 3, 4);
+        for out in &outputs {
+            assert_eq!(out.public_key, key);
+            assert_eq!(out.share.public(), out.public_shards[out.share.index - 1]);
+        }
+
+        let shards: Vec<_> = outputs
+            .iter()
+            .map(|o| o.share.decrypt_shard(&cipher.c1))
+            .collect();
+        let m = cipher
+            .decrypt(vec![&shards[3], &shards[0], &shards[2]], 3)
+            .unwrap();
+        assert_eq!(m, msg);
+
+        // a removed member can not mix its old share with the new ones
+        let old_shard = old[1].decrypt_shard(&cipher.c1);
+        assert_ne!(
+            cipher
+                .decrypt(vec![&old_shard, &shards[2], &shards[3]], 3)
+                .unwrap(),
+            msg
+        );
+    }
+
+    #[test]
+    // refresh the shares of the same committee
+    fn test_refresh() {
+        let (key, old) = gen_key_shares(2, 3).unwrap();
+        let msg = random_scalar() * G.clone();
+        let (cipher, _) = key.encrypt(&msg);
+
+        let outputs = reshare(&key, &old, 2, &[1, 2, 3], 2, 3);
+        for (out, share) in outputs.iter().zip(old.iter()) {
+            assert_eq!(out.public_key, key);
+            assert_ne!(out.share.value, share.value);
+        }
+        let shards: Vec<_> = outputs
+            .iter()
+            .map(|o| o.share.decrypt_shard(&cipher.c1))
+            .collect();
+        assert_eq!(
+            cipher.decrypt(vec![&shards[1], &shards[2]], 2).unwrap(),
+            msg
+        );
+    }
+}
@@ -0,0 +1,255 @@
+// This file defines the schemas of identity attributes. A schema names every attribute
+// of a credential and declares its type, so that the CA, the users and the verifiers
+// agree on what each attribute means and how it is encoded to a field element. The
+// hash of the schema is signed by the CA together with the credential.
+
+use baby_jub::Q;
+use ethers::utils::keccak256;
+use num_bigint::{BigInt, Sign, ToBigInt};
+use num_traits::Zero;
+use postcard::to_stdvec;
+use serde::{Deserialize, Serialize};
+
+// number of bits of an encoded attribute, bounded by the range check of the
+// identity circuit
+pub const VALUE_BITS: usize = 230;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttributeType {
+    // non-negative integer
+    Integer,
+    // unix timestamp in seconds
+    Date,
+    // one of the given variants, encoded by its index
+    Enum(Vec<String>),
+    // string hashed to a field element, only equality can be proven
+    String,
+    Boolean,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttributeValue {
+    Integer(BigInt),
+    Date(u64),
+    Enum(String),
+    String(String),
+    Boolean(bool),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttributeDef {
+    pub name: String,
+    pub attr_type: AttributeType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+// The ordered attributes of a credential.
+pub struct Schema {
+    pub name: String,
+    pub attributes: Vec<AttributeDef>,
+}
+
+impl Schema {
+    // Create a schema, the names of the attributes must be unique.
+    pub fn new(name: &str, attributes: Vec<AttributeDef>) -> Result<Self, String> {
+        if attributes.is_empty() {
+            return Err("No attributes".to_string());
+        }
+        for (i, attr) in attributes.iter().enumerate() {
+            if attributes[..i].iter().any(|a| a.name == attr.name) {
+                return Err(format!("Duplicate attribute {}", attr.name));
+            }
+            if let AttributeType::Enum(variants) = &attr.attr_type {
+                if variants.is_empty() {
+                    return Err(format!("No variants of {}", attr.name));
+                }
+            }
+        }
+        Ok(Self {
+            name: name.to_string(),
+            attributes,
+        })
+    }
+
+    // A schema of integers named attr0, attr1, ..., used when the CA does not
+    // declare one.
+    pub fn untyped(attribute_num: usize) -> Self {
+        Self {
+            name: "untyped".to_string(),
+            attributes: (0..attribute_num)
+                .map(|i| AttributeDef {
+                    name: format!("attr{}", i),
+                    attr_type: AttributeType::Integer,
+                })
+                .collect(),
+        }
+    }
+
+    // number of attributes
+    pub fn len(&self) -> usize {
+        self.attributes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attributes.is_empty()
+    }
+
+    // position of the attribute with the name
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.attributes.iter().position(|a| a.name == name)
+    }
+
+    // Hash of the schema, signed in credentials.
+    pub fn hash(&self) -> BigInt {
+        let bytes = keccak256(to_stdvec(self).unwrap());
+        BigInt::from_bytes_be(Sign::Plus, &bytes) % Q.clone()
+    }
+
+    // Encode the value of the i-th attribute.
+    pub fn encode_value(&self, i: usize, value: &AttributeValue) -> Result<BigInt, String> {
+        let attr = self.attributes.get(i).ok_or("Unknown attribute")?;
+        let invalid = || format!("Invalid value of {}", attr.name);
+        let encoded = match (&attr.attr_type, value) {
+            (AttributeType::Integer, AttributeValue::Integer(v)) => v.clone(),
+            (AttributeType::Date, AttributeValue::Date(t)) => t.to_bigint().unwrap(),
+            (AttributeType::Enum(variants), AttributeValue::Enum(v)) => variants
+                .iter()
+                .position(|x| x == v)
+                .ok_or_else(invalid)?
+                .to_bigint()
+                .unwrap(),
+            (AttributeType::String, AttributeValue::String(s)) => {
+                BigInt::from_bytes_be(Sign::Plus, &keccak256(s.as_bytes())) >> (256 - VALUE_BITS)
+            }
+            (AttributeType::Boolean, AttributeValue::Boolean(b)) => {
+                if *b {
+                    BigInt::from(1)
+                } else {
+                    BigInt::zero()
+                }
+            }
+            _ => return Err(invalid()),
+        };
+        if !check_bits(&encoded) {
+            return Err(invalid());
+        }
+        Ok(encoded)
+    }
+
+    // Encode the value of the attribute with the name.
+    pub fn encode_named(&self, name: &str, value: &AttributeValue) -> Result<BigInt, String> {
+        let i = self.index(name).ok_or("Unknown attribute")?;
+        self.encode_value(i, value)
+    }
+
+    // Encode all the attributes of a credential, in the order of the schema.
+    pub fn encode(&self, values: &[AttributeValue]) -> Result<Vec<BigInt>, String> {
+        if values.len() != self.len() {
+            return Err("Invalid number of attributes".to_string());
+        }
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| self.encode_value(i, v))
+            .collect()
+    }
+
+    // Check that encoded attributes are valid for the schema, used by the CA.
+    pub fn check(&self, attributes: &[BigInt]) -> Result<(), String> {
+        if attributes.len() != self.len() {
+            return Err("Invalid number of attributes".to_string());
+        }
+        for (attr, value) in self.attributes.iter().zip(attributes) {
+            let valid = check_bits(value)
+                && match &attr.attr_type {
+                    AttributeType::Enum(variants) => *value < variants.len().to_bigint().unwrap(),
+                    AttributeType::Boolean => *value <= BigInt::from(1),
+                    AttributeType::Date => *value <= u64::MAX.to_bigint().unwrap(),
+                    AttributeType::Integer | AttributeType::String => true,
+                };
+            if !valid {
+                return Err(format!("Invalid value of {}", attr.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+// the value can be compared by the identity circuit
+fn check_bits(value: &BigInt) -> bool {
+    value.sign() != Sign::Minus && value.bits() <= VALUE_BITS as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AttributeDef, AttributeType, AttributeValue, Schema, VALUE_BITS};
+    use num_bigint::BigInt;
+
+    fn schema() -> Schema {
+        let def = |name: &str, attr_type| AttributeDef {
+            name: name.to_string(),
+            attr_type,
+        };
+        Schema::new(
+            "passport",
+            vec![
+                def("age", AttributeType::Integer),
+                def("birthday", AttributeType::Date),
+                def(
+                    "country",
+                    AttributeType::Enum(vec!["CN".to_string(), "US".to_string()]),
+                ),
+                def("name", AttributeType::String),
+                def("student", AttributeType::Boolean),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    // attributes are encoded deterministically and checked against their types
+    fn test_encode() {
+        let schema = schema();
+        let values = vec![
+            AttributeValue::Integer(BigInt::from(20)),
+            AttributeValue::Date(946684800),
+            AttributeValue::Enum("US".to_string()),
+            AttributeValue::String("alice".to_string()),
+            AttributeValue::Boolean(true),
+        ];
+        let encoded = schema.encode(&values).unwrap();
+        assert_eq!(encoded, schema.encode(&values).unwrap());
+        assert_eq!(encoded[0], BigInt::from(20));
+        assert_eq!(encoded[1], BigInt::from(946684800));
+        assert_eq!(encoded[2], BigInt::from(1));
+        assert!(encoded[3].bits() <= VALUE_BITS as u64);
+        assert_eq!(encoded[4], BigInt::from(1));
+        assert!(schema.check(&encoded).is_ok());
+        assert_eq!(
+            schema
+                .encode_named("country", &AttributeValue::Enum("CN".to_string()))
+                .unwrap(),
+            BigInt::from(0)
+        );
+
+        // wrong types and values
+        assert!(schema
+            .encode_named("age", &AttributeValue::Boolean(true))
+            .is_err());
+        assert!(schema
+            .encode_named("country", &AttributeValue::Enum("FR".to_string()))
+            .is_err());
+        assert!(schema
+            .encode_named("age", &AttributeValue::Integer(BigInt::from(-1)))
+            .is_err());
+        assert!(schema.encode(&values[..4]).is_err());
+        let mut invalid = encoded.clone();
+        invalid[2] = BigInt::from(2);
+        assert!(schema.check(&invalid).is_err());
+
+        // the hash depends on the definitions
+        assert_eq!(schema.hash(), self::schema().hash());
+        assert_ne!(schema.hash(), Schema::untyped(5).hash());
+        assert!(Schema::new("dup", vec![schema.attributes[0].clone(); 2]).is_err());
+    }
+}
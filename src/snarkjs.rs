@@ -0,0 +1,265 @@
+// This file provides compatibility with snarkjs. Proving keys can be imported from
+// .zkey files, and proofs, public inputs and verification keys are converted from and
+// to the JSON format of snarkjs (proof.json, public.json, verification_key.json).
+// The h query of a .zkey file is computed with the circom reduction. The reduction is
+// a property of the key, so every key of this crate is generated with `setup` and
+// every proof with `prove`, whether the key comes from a .zkey file or not.
+
+use ark_bn254::{Bn254, Fq, Fq12, Fq2, Fr, G1Affine, G2Affine};
+use ark_circom::{read_zkey, CircomCircuit, CircomReduction};
+use ark_ec::PairingEngine;
+use ark_ff::{BigInteger, Field, PrimeField, Zero};
+use ark_groth16::{
+    create_random_proof_with_reduction, generate_random_parameters_with_reduction,
+    prepare_verifying_key, verify_proof, Proof, ProvingKey, VerifyingKey,
+};
+use ark_relations::r1cs::SynthesisError;
+use num_bigint::BigUint;
+use rand::Rng;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::BufReader;
+use std::str::FromStr;
+
+// Load the proving key from a .zkey file generated by snarkjs.
+pub fn load_zkey(path: &str) -> std::io::Result<ProvingKey<Bn254>> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+    let (params, _) = read_zkey(&mut reader)?;
+    Ok(params)
+}
+
+// Generate a proving key with the circom reduction, like snarkjs does. Only suitable
+// for testing, see `ceremony`.
+pub fn setup<R: Rng>(
+    circom: CircomCircuit<Bn254>,
+    rng: &mut R,
+) -> Result<ProvingKey<Bn254>, SynthesisError> {
+    generate_random_parameters_with_reduction::<_, _, _, CircomReduction>(circom, rng)
+}
+
+// Generate a proof with a proving key of `setup` or of a .zkey file.
+pub fn prove<R: Rng>(
+    circom: CircomCircuit<Bn254>,
+    params: &ProvingKey<Bn254>,
+    rng: &mut R,
+) -> Result<Proof<Bn254>, SynthesisError> {
+    create_random_proof_with_reduction::<_, _, _, CircomReduction>(circom, params, rng)
+}
+
+// decimal string of a field element
+pub(crate) fn to_dec<F: PrimeField>(f: &F) -> String {
+    BigUint::from_bytes_le(&f.into_repr().to_bytes_le()).to_string()
+}
+
+// field element of a decimal string
+fn from_dec<F: PrimeField>(v: &Value) -> Result<F, String> {
+    v.as_str()
+        .and_then(|s| F::from_str(s).ok())
+        .ok_or_else(|| "Invalid field element".to_string())
+}
+
+// array of the given length
+fn array(v: &Value, len: usize) -> Result<&Vec<Value>, String> {
+    match v.as_array() {
+        Some(a) if a.len() >= len => Ok(a),
+        _ => Err("Invalid array".to_string()),
+    }
+}
+
+fn fq2_to_json(f: &Fq2) -> Value {
+    json!([to_dec(&f.c0), to_dec(&f.c1)])
+}
+
+fn fq2_from_json(v: &Value) -> Result<Fq2, String> {
+    let a = array(v, 2)?;
+    Ok(Fq2::new(from_dec(&a[0])?, from_dec(&a[1])?))
+}
+
+// G1 point in projective coordinates, as used by snarkjs
+fn g1_to_json(p: &G1Affine) -> Value {
+    if p.is_zero() {
+        json!(["0", "1", "0"])
+    } else {
+        json!([to_dec(&p.x), to_dec(&p.y), "1"])
+    }
+}
+
+// affine coordinates of the Jacobian coordinates (x, y, z) of snarkjs
+fn to_affine<F: Field>(x: F, y: F, z: F) -> (F, F) {
+    let z_inv = z.inverse().unwrap();
+    let z_inv2 = z_inv.square();
+    (x * z_inv2, y * z_inv2 * z_inv)
+}
+
+fn g1_from_json(v: &Value) -> Result<G1Affine, String> {
+    let a = array(v, 3)?;
+    let z: Fq = from_dec(&a[2])?;
+    if z.is_zero() {
+        return Ok(G1Affine::zero());
+    }
+    let (x, y) = to_affine(from_dec(&a[0])?, from_dec(&a[1])?, z);
+    let p = G1Affine::new(x, y, false);
+    if !p.is_on_curve() || !p.is_in_correct_subgroup_assuming_on_curve() {
+        return Err("Invalid G1 point".to_string());
+    }
+    Ok(p)
+}
+
+fn g2_to_json(p: &G2Affine) -> Value {
+    if p.is_zero() {
+        json!([["0", "0"], ["1", "0"], ["0", "0"]])
+    } else {
+        json!([fq2_to_json(&p.x), fq2_to_json(&p.y), ["1", "0"]])
+    }
+}
+
+fn g2_from_json(v: &Value) -> Result<G2Affine, String> {
+    let a = array(v, 3)?;
+    let z = fq2_from_json(&a[2])?;
+    if z.is_zero() {
+        return Ok(G2Affine::zero());
+    }
+    let (x, y) = to_affine(fq2_from_json(&a[0])?, fq2_from_json(&a[1])?, z);
+    let p = G2Affine::new(x, y, false);
+    if !p.is_on_curve() || !p.is_in_correct_subgroup_assuming_on_curve() {
+        return Err("Invalid G2 point".to_string());
+    }
+    Ok(p)
+}
+
+fn fq12_to_json(f: &Fq12) -> Value {
+    let fq6 =
+        |c: &ark_bn254::Fq6| json!([fq2_to_json(&c.c0), fq2_to_json(&c.c1), fq2_to_json(&c.c2)]);
+    json!([fq6(&f.c0), fq6(&f.c1)])
+}
+
+// proof.json of snarkjs
+pub fn proof_to_json(proof: &Proof<Bn254>) -> Value {
+    json!({
+        "pi_a": g1_to_json(&proof.a),
+        "pi_b": g2_to_json(&proof.b),
+        "pi_c": g1_to_json(&proof.c),
+        "protocol": "groth16",
+        "curve": "bn128",
+    })
+}
+
+// Parse the proof.json of snarkjs.
+pub fn proof_from_json(v: &Value) -> Result<Proof<Bn254>, String> {
+    Ok(Proof {
+        a: g1_from_json(&v["pi_a"])?,
+        b: g2_from_json(&v["pi_b"])?,
+        c: g1_from_json(&v["pi_c"])?,
+    })
+}
+
+// public.json of snarkjs
+pub fn inputs_to_json(inputs: &[Fr]) -> Value {
+    Value::Array(inputs.iter().map(|x| Value::String(to_dec(x))).collect())
+}
+
+// Parse the public.json of snarkjs.
+pub fn inputs_from_json(v: &Value) -> Result<Vec<Fr>, String> {
+    v.as_array()
+        .ok_or("Invalid public inputs")?
+        .iter()
+        .map(from_dec)
+        .collect()
+}
+
+// verification_key.json of snarkjs
+pub fn vk_to_json(vk: &VerifyingKey<Bn254>) -> Value {
+    json!({
+        "protocol": "groth16",
+        "curve": "bn128",
+        "nPublic": vk.gamma_abc_g1.len() - 1,
+        "vk_alpha_1": g1_to_json(&vk.alpha_g1),
+        "vk_beta_2": g2_to_json(&vk.beta_g2),
+        "vk_gamma_2": g2_to_json(&vk.gamma_g2),
+        "vk_delta_2": g2_to_json(&vk.delta_g2),
+        "vk_alphabeta_12": fq12_to_json(&Bn254::pairing(vk.alpha_g1, vk.beta_g2)),
+        "IC": vk.gamma_abc_g1.iter().map(g1_to_json).collect::<Vec<_>>(),
+    })
+}
+
+// Parse the verification_key.json of snarkjs.
+pub fn vk_from_json(v: &Value) -> Result<VerifyingKey<Bn254>, String> {
+    let ic = v["IC"]
+        .as_array()
+        .ok_or("Invalid IC")?
+        .iter()
+        .map(g1_from_json)
+        .collect::<Result<Vec<_>, String>>()?;
+    if ic.is_empty() {
+        return Err("Invalid IC".to_string());
+    }
+    Ok(VerifyingKey {
+        alpha_g1: g1_from_json(&v["vk_alpha_1"])?,
+        beta_g2: g2_from_json(&v["vk_beta_2"])?,
+        gamma_g2: g2_from_json(&v["vk_gamma_2"])?,
+        delta_g2: g2_from_json(&v["vk_delta_2"])?,
+        gamma_abc_g1: ic,
+    })
+}
+
+// Verify a proof in the JSON format of snarkjs.
+pub fn verify(vk: &VerifyingKey<Bn254>, proof: &Value, inputs: &Value) -> Result<bool, String> {
+    let proof = proof_from_json(proof)?;
+    let inputs = inputs_from_json(inputs)?;
+    if inputs.len() + 1 != vk.gamma_abc_g1.len() {
+        return Err("Invalid number of public inputs".to_string());
+    }
+    let pvk = prepare_verifying_key(vk);
+    verify_proof(&pvk, &proof, &inputs).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        fq2_to_json, g1_from_json, g2_from_json, inputs_from_json, inputs_to_json, proof_from_json,
+        proof_to_json, to_dec,
+    };
+    use ark_bn254::{Bn254, Fq, Fq2, Fr, G1Affine, G2Affine};
+    use ark_ec::{AffineCurve, ProjectiveCurve};
+    use ark_ff::{Field, PrimeField, UniformRand};
+    use ark_groth16::Proof;
+    use serde_json::json;
+
+    #[test]
+    // proofs and inputs survive a round trip through JSON
+    fn test_json() {
+        let mut rng = rand::thread_rng();
+        let proof = Proof::<Bn254> {
+            a: G1Affine::prime_subgroup_generator()
+                .mul(Fr::rand(&mut rng).into_repr())
+                .into_affine(),
+            b: G2Affine::prime_subgroup_generator()
+                .mul(Fr::rand(&mut rng).into_repr())
+                .into_affine(),
+            c: G1Affine::prime_subgroup_generator()
+                .mul(Fr::rand(&mut rng).into_repr())
+                .into_affine(),
+        };
+        assert_eq!(proof_from_json(&proof_to_json(&proof)).unwrap(), proof);
+
+        let inputs: Vec<Fr> = (0..5).map(|_| Fr::rand(&mut rng)).collect();
+        assert_eq!(inputs_from_json(&inputs_to_json(&inputs)).unwrap(), inputs);
+
+        // the generator of snarkjs
+        let g = g1_from_json(&json!(["1", "2", "1"])).unwrap();
+        assert_eq!(g, G1Affine::prime_subgroup_generator());
+        assert!(g1_from_json(&json!(["1", "3", "1"])).is_err());
+
+        // points not normalized to z = 1
+        let z = Fq::rand(&mut rng);
+        let (z2, z3) = (z.square(), z.square() * z);
+        let p = g1_from_json(&json!([to_dec(&z2), to_dec(&(z3 + z3)), to_dec(&z)])).unwrap();
+        assert_eq!(p, g);
+        let q = proof.b;
+        let z = Fq2::rand(&mut rng);
+        let (x, y) = (q.x * z.square(), q.y * z.square() * z);
+        let p = json!([fq2_to_json(&x), fq2_to_json(&y), fq2_to_json(&z)]);
+        assert_eq!(g2_from_json(&p).unwrap(), q);
+    }
+}
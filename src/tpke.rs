@@ -2,13 +2,50 @@
 // with capabilities to encrypt and decrypt data.
 
 use baby_jub::{poseidon_hash, Point, G, Q};
+use lazy_static::lazy_static;
 use num_bigint::{BigInt, RandBigInt, ToBigInt};
+use num_traits::{One, Zero};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::ops::Deref;
 
+lazy_static! {
+    // order of the subgroup generated by G, shares are computed modulo this value
+    pub static ref ORDER: BigInt = BigInt::parse_bytes(
+        b"2736030358979909402780800718157159386076813972158567259200215660948447373041",
+        10
+    )
+    .unwrap();
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PublicKey(Point);
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// Shamir share of the tpke private key, `index` is the x-coordinate (starting from 1).
+// Index 0 marks an additive share of a key built with `PublicKey::new`, where all the
+// members are needed to decrypt.
+pub struct SecretShare {
+    pub index: usize,
+    pub value: BigInt,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// Decryption shard produced by the committee member holding share `index`.
+pub struct DecryptShard {
+    pub index: usize,
+    pub shard: Point,
+    // proof that log_G(public shard) == log_C1(shard)
+    pub proof: DleqProof,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// Non-interactive Chaum-Pedersen proof of equal discrete logarithms.
+pub struct DleqProof {
+    pub c: BigInt,
+    pub z: BigInt,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 // The ciphertext of a point
 pub struct Cipher {
@@ -71,6 +108,220 @@ impl PublicKey {
     }
 }
 
+impl SecretShare {
+    // shard of the public key
+    pub fn public(&self) -> Point {
+        &self.value * G.clone()
+    }
+
+    // get decryption shard of a cipher
+    pub fn decrypt_shard(&self, c1: &Point) -> DecryptShard {
+        let shard = &self.value * c1;
+        let proof = DleqProof::new(&self.value, &self.public(), c1, &shard);
+        DecryptShard {
+            index: self.index,
+            shard,
+            proof,
+        }
+    }
+}
+
+impl DleqProof {
+    // Fiat-Shamir challenge
+    fn challenge(y: &Point, c1: &Point, d: &Point, a1: &Point, a2: &Point) -> BigInt {
+        let h1 = poseidon_hash(vec![
+            &y.scalar_x(),
+            &y.scalar_y(),
+            &c1.scalar_x(),
+            &c1.scalar_y(),
+            &d.scalar_x(),
+            &d.scalar_y(),
+        ])
+        .unwrap();
+        let h2 = poseidon_hash(vec![
+            &h1,
+            &a1.scalar_x(),
+            &a1.scalar_y(),
+            &a2.scalar_x(),
+            &a2.scalar_y(),
+        ])
+        .unwrap();
+        modulo(&h2)
+    }
+
+    // Prove that y = x * G and d = x * c1.
+    pub fn new(x: &BigInt, y: &Point, c1: &Point, d: &Point) -> Self {
+        let w = random_scalar();
+        let a1 = &w * G.clone();
+        let a2 = &w * c1;
+        let c = Self::challenge(y, c1, d, &a1, &a2);
+        let z = modulo(&(w + &c * x));
+        DleqProof { c, z }
+    }
+
+    // verify the proof
+    pub fn verify(&self, y: &Point, c1: &Point, d: &Point) -> bool {
+        let a1 = &self.z * G.clone() + (&self.c * y).negative();
+        let a2 = &self.z * c1 + (&self.c * d).negative();
+        self.c == Self::challenge(y, c1, d, &a1, &a2)
+    }
+}
+
+impl DecryptShard {
+    // Verify the shard against the public key shard of its member.
+    pub fn verify(&self, public_shard: &Point, c1: &Point) -> bool {
+        self.proof.verify(public_shard, c1, &self.shard)
+    }
+}
+
+// reduce a scalar into [0, ORDER)
+pub fn modulo(a: &BigInt) -> BigInt {
+    let r = a % &*ORDER;
+    if r < BigInt::zero() {
+        r + &*ORDER
+    } else {
+        r
+    }
+}
+
+// modular inverse, ORDER is a prime
+pub fn mod_inverse(a: &BigInt) -> BigInt {
+    modulo(a).modpow(&(ORDER.clone() - 2), &ORDER)
+}
+
+// random scalar in [0, ORDER)
+pub fn random_scalar() -> BigInt {
+    let mut rng = rand::thread_rng();
+    rng.gen_biguint(256).to_bigint().unwrap() % ORDER.clone()
+}
+
+// evaluate the polynomial with the given coefficients at x
+pub fn eval_polynomial(coeffs: &[BigInt], x: usize) -> BigInt {
+    let x = x.to_bigint().unwrap();
+    coeffs
+        .iter()
+        .rev()
+        .fold(BigInt::zero(), |acc, c| modulo(&(acc * &x + c)))
+}
+
+// Lagrange coefficient of `index` at x = 0 for the given set of indices.
+pub fn lagrange_coefficient(index: usize, indices: &[usize]) -> BigInt {
+    let xi = index.to_bigint().unwrap();
+    let (num, den) = indices.iter().filter(|j| **j != index).fold(
+        (BigInt::one(), BigInt::one()),
+        |(num, den), j| {
+            let xj = j.to_bigint().unwrap();
+            (modulo(&(num * &xj)), modulo(&(den * (&xj - &xi))))
+        },
+    );
+    modulo(&(num * mod_inverse(&den)))
+}
+
+// Check that the indices are non-zero and distinct.
+fn check_indices(indices: &[usize]) -> Result<(), String> {
+    if indices.is_empty() {
+        return Err("No shards provided".to_string());
+    }
+    let mut seen = HashSet::new();
+    for i in indices {
+        if *i == 0 {
+            return Err("Shard index must start from 1".to_string());
+        }
+        if !seen.insert(*i) {
+            return Err(format!("Duplicate shard index {}", i));
+        }
+    }
+    Ok(())
+}
+
+// Split the secret into n shares, any `threshold` of them can recover it.
+pub fn split_secret(
+    secret: &BigInt,
+    threshold: usize,
+    n: usize,
+) -> Result<Vec<SecretShare>, String> {
+    if threshold == 0 || threshold > n {
+        return Err("Invalid threshold".to_string());
+    }
+    let coeffs: Vec<BigInt> = vec![modulo(secret)]
+        .into_iter()
+        .chain((1..threshold).map(|_| random_scalar()))
+        .collect();
+
+    Ok((1..=n)
+        .map(|index| SecretShare {
+            index,
+            value: eval_polynomial(&coeffs, index),
+        })
+        .collect())
+}
+
+// Generate a tpke key with a trusted dealer, returning the public key and the shares.
+pub fn gen_key_shares(threshold: usize, n: usize) -> Result<(PublicKey, Vec<SecretShare>), String> {
+    let secret = random_scalar();
+    let shares = split_secret(&secret, threshold, n)?;
+    Ok((PublicKey(&secret * G.clone()), shares))
+}
+
+// Combine decryption shards using Lagrange interpolation in the exponent.
+// At least `threshold` shards of the same cipher are required. Shards of additive
+// shares (index 0) are summed, `threshold` is then the number of members.
+pub fn combine_shards(shards: Vec<&DecryptShard>, threshold: usize) -> Result<Point, String> {
+    if threshold == 0 || shards.len() < threshold {
+        return Err(format!(
+            "Not enough shards: {} < {}",
+            shards.len(),
+            threshold
+        ));
+    }
+    if shards.iter().all(|s| s.index == 0) {
+        for (i, s) in shards.iter().enumerate() {
+            if shards[..i].iter().any(|t| t.shard == s.shard) {
+                return Err("Duplicate shard".to_string());
+            }
+        }
+        return Ok(shards
+            .into_iter()
+            .fold(Point::identity(), |sum, s| sum + &s.shard));
+    }
+    let indices: Vec<usize> = shards.iter().map(|s| s.index).collect();
+    check_indices(&indices)?;
+    Ok(shards.into_iter().fold(Point::identity(), |sum, s| {
+        sum + &lagrange_coefficient(s.index, &indices) * &s.shard
+    }))
+}
+
+// Verify the shards against the public key shards (indexed by member index - 1),
+// and combine the valid ones. Returns the combined shard and the indices of the
+// rejected shards, or an error if fewer than `threshold` shards are valid. A shard
+// sent several times is only counted once.
+pub fn combine_verified(
+    c1: &Point,
+    shards: Vec<&DecryptShard>,
+    public_shards: &[Point],
+    threshold: usize,
+) -> Result<(Point, Vec<usize>), String> {
+    let (valid, invalid): (Vec<&DecryptShard>, Vec<&DecryptShard>) =
+        shards.into_iter().partition(|s| {
+            s.index >= 1
+                && s.index <= public_shards.len()
+                && s.verify(&public_shards[s.index - 1], c1)
+        });
+    let rejected: Vec<usize> = invalid.iter().map(|s| s.index).collect();
+    let mut seen = HashSet::new();
+    let valid: Vec<&DecryptShard> = valid.into_iter().filter(|s| seen.insert(s.index)).collect();
+    if valid.len() < threshold {
+        return Err(format!(
+            "Not enough valid shards: {} < {}, rejected: {:?}",
+            valid.len(),
+            threshold,
+            rejected
+        ));
+    }
+    let c = combine_shards(valid.into_iter().take(threshold).collect(), threshold)?;
+    Ok((c, rejected))
+}
+
 // deref
 impl Deref for PublicKey {
     type Target = Point;
@@ -80,26 +331,179 @@ impl Deref for PublicKey {
 }
 
 impl Cipher {
-    // Decrypt using decryption shards.
-    // TODO: use ss
-    pub fn decrypt(&self, shards: Vec<&Point>) -> Point {
-        let c = shards
-            .into_iter()
-            .fold(Point::identity(), |sum, val| sum + val);
-        self.c2.clone() + c.negative()
+    // Decrypt using at least `threshold` decryption shards.
+    pub fn decrypt(&self, shards: Vec<&DecryptShard>, threshold: usize) -> Result<Point, String> {
+        let c = combine_shards(shards, threshold)?;
+        Ok(self.c2.clone() + c.negative())
+    }
+
+    // Decrypt using verified shards, the indices of invalid shards are returned.
+    pub fn decrypt_verified(
+        &self,
+        shards: Vec<&DecryptShard>,
+        public_shards: &[Point],
+        threshold: usize,
+    ) -> Result<(Point, Vec<usize>), String> {
+        let (c, rejected) = combine_verified(&self.c1, shards, public_shards, threshold)?;
+        Ok((self.c2.clone() + c.negative(), rejected))
     }
 }
 
 impl CipherDual {
-    // Decrypt using decryption shards.
-    // TODO: use ss
-    pub fn decrypt(&self, shards: Vec<&Point>, salt: &BigInt) -> (Point, Point) {
-        let c = shards
-            .into_iter()
-            .fold(Point::identity(), |sum, val| sum + val);
+    // Decrypt using at least `threshold` decryption shards.
+    pub fn decrypt(
+        &self,
+        shards: Vec<&DecryptShard>,
+        salt: &BigInt,
+        threshold: usize,
+    ) -> Result<(Point, Point), String> {
+        let c = combine_shards(shards, threshold)?;
+        self.decrypt_with(&c, salt)
+    }
+
+    // Decrypt using verified shards, the indices of invalid shards are returned.
+    pub fn decrypt_verified(
+        &self,
+        shards: Vec<&DecryptShard>,
+        salt: &BigInt,
+        public_shards: &[Point],
+        threshold: usize,
+    ) -> Result<((Point, Point), Vec<usize>), String> {
+        let (c, rejected) = combine_verified(&self.c1, shards, public_shards, threshold)?;
+        Ok((self.decrypt_with(&c, salt)?, rejected))
+    }
+
+    // decrypt using the combined shard
+    fn decrypt_with(&self, c: &Point, salt: &BigInt) -> Result<(Point, Point), String> {
         let res1 = self.c2.clone() + c.negative();
-        let c3_1 = poseidon_hash(vec![&c.scalar_x(), &c.scalar_y(), salt]).unwrap() * G.clone();
+        let c3_1 = poseidon_hash(vec![&c.scalar_x(), &c.scalar_y(), salt])? * G.clone();
         let res2 = self.c3.clone() + c3_1.negative();
-        (res1, res2)
+        Ok((res1, res2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gen_key_shares, random_scalar, PublicKey, SecretShare};
+    use baby_jub::G;
+    use num_bigint::ToBigInt;
+
+    #[test]
+    // any t of n shards can decrypt
+    fn test_threshold_decrypt() {
+        let (key, shares) = gen_key_shares(3, 5).unwrap();
+        let msg = random_scalar() * G.clone();
+        let (cipher, _) = key.encrypt(&msg);
+
+        let shards: Vec<_> = shares.iter().map(|s| s.decrypt_shard(&cipher.c1)).collect();
+        assert_eq!(
+            cipher
+                .decrypt(vec![&shards[0], &shards[2], &shards[4]], 3)
+                .unwrap(),
+            msg
+        );
+        assert_eq!(
+            cipher
+                .decrypt(vec![&shards[3], &shards[1], &shards[0]], 3)
+                .unwrap(),
+            msg
+        );
+        // not enough shards
+        assert!(cipher.decrypt(vec![&shards[0], &shards[1]], 3).is_err());
+        // duplicated shards
+        assert!(cipher
+            .decrypt(vec![&shards[0], &shards[0], &shards[1]], 3)
+            .is_err());
+    }
+
+    #[test]
+    // all the additive shares of a key built from public key shards are needed
+    fn test_additive_decrypt() {
+        let shares: Vec<_> = (0..3)
+            .map(|_| SecretShare {
+                index: 0,
+                value: random_scalar(),
+            })
+            .collect();
+        let public: Vec<_> = shares.iter().map(|s| s.public()).collect();
+        let key = PublicKey::new(public.iter().collect());
+        let msg = random_scalar() * G.clone();
+        let (cipher, _) = key.encrypt(&msg);
+
+        let shards: Vec<_> = shares.iter().map(|s| s.decrypt_shard(&cipher.c1)).collect();
+        assert_eq!(cipher.decrypt(shards.iter().collect(), 3).unwrap(), msg);
+        assert!(cipher.decrypt(vec![&shards[0], &shards[1]], 3).is_err());
+        assert!(cipher
+            .decrypt(vec![&shards[0], &shards[0], &shards[1]], 3)
+            .is_err());
+    }
+
+    #[test]
+    // decrypt two points
+    fn test_threshold_decrypt_dual() {
+        let (key, shares) = gen_key_shares(2, 3).unwrap();
+        let msg1 = random_scalar() * G.clone();
+        let msg2 = random_scalar() * G.clone();
+        let salt = 7.to_bigint().unwrap();
+        let (cipher, _) = key.encrypt_dual(&msg1, &msg2, &salt);
+
+        let shards: Vec<_> = shares.iter().map(|s| s.decrypt_shard(&cipher.c1)).collect();
+        let (m1, m2) = cipher
+            .decrypt(vec![&shards[2], &shards[0]], &salt, 2)
+            .unwrap();
+        assert_eq!(m1, msg1);
+        assert_eq!(m2, msg2);
+    }
+
+    #[test]
+    // invalid shards are rejected and reported
+    fn test_verified_decrypt() {
+        let (key, shares) = gen_key_shares(2, 4).unwrap();
+        let public_shards: Vec<_> = shares.iter().map(|s| s.public()).collect();
+        let msg1 = random_scalar() * G.clone();
+        let msg2 = random_scalar() * G.clone();
+        let salt = 9.to_bigint().unwrap();
+        let (cipher, _) = key.encrypt_dual(&msg1, &msg2, &salt);
+
+        let mut shards: Vec<_> = shares.iter().map(|s| s.decrypt_shard(&cipher.c1)).collect();
+        assert!(shards[0].verify(&public_shards[0], &cipher.c1));
+        // garbage shard
+        shards[1].shard = random_scalar() * G.clone();
+        // shard computed with a wrong key
+        let fake = SecretShare {
+            index: 3,
+            value: random_scalar(),
+        };
+        shards[2] = fake.decrypt_shard(&cipher.c1);
+
+        let ((m1, m2), rejected) = cipher
+            .decrypt_verified(shards.iter().collect(), &salt, &public_shards, 2)
+            .unwrap();
+        assert_eq!(m1, msg1);
+        assert_eq!(m2, msg2);
+        assert_eq!(rejected, vec![2, 3]);
+
+        assert!(cipher
+            .decrypt_verified(shards[1..].iter().collect(), &salt, &public_shards, 2)
+            .is_err());
+    }
+
+    #[test]
+    // a valid shard sent twice does not count twice
+    fn test_verified_decrypt_duplicate() {
+        let (key, shares) = gen_key_shares(2, 3).unwrap();
+        let public_shards: Vec<_> = shares.iter().map(|s| s.public()).collect();
+        let msg = random_scalar() * G.clone();
+        let (cipher, _) = key.encrypt(&msg);
+
+        let shards: Vec<_> = shares.iter().map(|s| s.decrypt_shard(&cipher.c1)).collect();
+        assert!(cipher
+            .decrypt_verified(vec![&shards[0], &shards[0]], &public_shards, 2)
+            .is_err());
+        let (m, rejected) = cipher
+            .decrypt_verified(vec![&shards[0], &shards[0], &shards[2]], &public_shards, 2)
+            .unwrap();
+        assert_eq!(m, msg);
+        assert!(rejected.is_empty());
     }
 }
@@ -0,0 +1,230 @@
+// This file implements the storage of Merkle tree nodes. Nodes can be kept in memory,
+// or in an embedded key-value database (sled) so that large trees do not have to be
+// loaded into memory when the committee client restarts. All the nodes changed by an
+// insertion are written in one atomic batch, so a crash never leaves a half-updated tree.
+
+use lazy_static::lazy_static;
+use num_bigint::BigInt;
+use serde::de::Deserializer;
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+lazy_static! {
+    // databases opened by this process, a database can only be opened once
+    static ref DATABASES: Mutex<HashMap<String, sled::Db>> = Mutex::new(HashMap::new());
+}
+
+// key of the number of leaves
+const LEAF_COUNT_KEY: &[u8] = b"leaf_count";
+
+// Storage of the nodes of a Merkle tree, addressed by (level, index). Reads may fail
+// for stores backed by a database.
+pub trait NodeStore {
+    // get the node at (level, idx)
+    fn get(&self, level: usize, idx: usize) -> Result<Option<BigInt>, String>;
+
+    // number of leaves
+    fn leaf_count(&self) -> Result<usize, String>;
+
+    // Write the nodes and the new number of leaves in one atomic update.
+    fn commit(
+        &mut self,
+        nodes: Vec<((usize, usize), BigInt)>,
+        leaf_count: usize,
+    ) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+// Nodes kept in memory by layers.
+pub struct MemoryStore {
+    pub nodes: Vec<Vec<BigInt>>,
+    pub leaf_count: usize,
+}
+
+impl MemoryStore {
+    // Initialize an empty store for a tree with the given number of layers.
+    pub fn new(tiers: usize) -> Self {
+        Self {
+            nodes: vec![Vec::new(); tiers],
+            leaf_count: 0,
+        }
+    }
+}
+
+impl NodeStore for MemoryStore {
+    fn get(&self, level: usize, idx: usize) -> Result<Option<BigInt>, String> {
+        Ok(self.nodes.get(level).and_then(|l| l.get(idx)).cloned())
+    }
+
+    fn leaf_count(&self) -> Result<usize, String> {
+        Ok(self.leaf_count)
+    }
+
+    fn commit(
+        &mut self,
+        nodes: Vec<((usize, usize), BigInt)>,
+        leaf_count: usize,
+    ) -> Result<(), String> {
+        for ((level, idx), node) in nodes {
+            if level >= self.nodes.len() {
+                self.nodes.resize(level + 1, Vec::new());
+            }
+            let layer = &mut self.nodes[level];
+            if idx >= layer.len() {
+                layer.resize(idx + 1, BigInt::default());
+            }
+            layer[idx] = node;
+        }
+        self.leaf_count = leaf_count;
+        // only keep the non-empty nodes
+        for (level, layer) in self.nodes.iter_mut().enumerate() {
+            layer.truncate(level_len(leaf_count, level));
+        }
+        Ok(())
+    }
+}
+
+// number of non-empty nodes in the level
+pub fn level_len(leaf_count: usize, level: usize) -> usize {
+    if leaf_count == 0 {
+        0
+    } else {
+        ((leaf_count - 1) >> level) + 1
+    }
+}
+
+#[derive(Debug, Clone)]
+// Nodes kept in a named tree of an embedded sled database.
+pub struct DiskStore {
+    pub path: String,
+    pub name: String,
+    tree: sled::Tree,
+}
+
+impl DiskStore {
+    // Open (or create) the named tree in the database at `path`.
+    pub fn open(path: &str, name: &str) -> Result<Self, String> {
+        let mut dbs = DATABASES.lock().map_err(|e| e.to_string())?;
+        let db = match dbs.get(path) {
+            Some(db) => db.clone(),
+            None => {
+                let db = sled::open(path).map_err(|e| e.to_string())?;
+                dbs.insert(path.to_string(), db.clone());
+                db
+            }
+        };
+        let tree = db.open_tree(name).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            path: path.to_string(),
+            name: name.to_string(),
+            tree,
+        })
+    }
+
+    // key of the node at (level, idx)
+    fn key(level: usize, idx: usize) -> Vec<u8> {
+        (level as u32)
+            .to_be_bytes()
+            .iter()
+            .chain((idx as u64).to_be_bytes().iter())
+            .cloned()
+            .collect()
+    }
+}
+
+impl NodeStore for DiskStore {
+    fn get(&self, level: usize, idx: usize) -> Result<Option<BigInt>, String> {
+        Ok(self
+            .tree
+            .get(Self::key(level, idx))
+            .map_err(|e| e.to_string())?
+            .map(|v| BigInt::from_signed_bytes_be(&v)))
+    }
+
+    fn leaf_count(&self) -> Result<usize, String> {
+        match self.tree.get(LEAF_COUNT_KEY).map_err(|e| e.to_string())? {
+            Some(v) => {
+                let bytes: [u8; 8] = v
+                    .as_ref()
+                    .try_into()
+                    .map_err(|_| "Invalid leaf count".to_string())?;
+                Ok(u64::from_be_bytes(bytes) as usize)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn commit(
+        &mut self,
+        nodes: Vec<((usize, usize), BigInt)>,
+        leaf_count: usize,
+    ) -> Result<(), String> {
+        let mut batch = sled::Batch::default();
+        for ((level, idx), node) in nodes {
+            batch.insert(Self::key(level, idx), node.to_signed_bytes_be());
+        }
+        batch.insert(LEAF_COUNT_KEY, (leaf_count as u64).to_be_bytes().to_vec());
+        self.tree.apply_batch(batch).map_err(|e| e.to_string())?;
+        self.tree.flush().map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+// two stores are equal if they refer to the same tree
+impl PartialEq for DiskStore {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path && self.name == other.name
+    }
+}
+
+// only the location of the tree is serialized
+impl Serialize for DiskStore {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (&self.path, &self.name).serialize(serializer)
+    }
+}
+
+// reopen the tree when deserialized
+impl<'de> Deserialize<'de> for DiskStore {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (path, name) = <(String, String)>::deserialize(deserializer)?;
+        DiskStore::open(&path, &name).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// Store selected at runtime, used by the committee client.
+pub enum TreeStore {
+    Memory(MemoryStore),
+    Disk(DiskStore),
+}
+
+impl NodeStore for TreeStore {
+    fn get(&self, level: usize, idx: usize) -> Result<Option<BigInt>, String> {
+        match self {
+            TreeStore::Memory(s) => s.get(level, idx),
+            TreeStore::Disk(s) => s.get(level, idx),
+        }
+    }
+
+    fn leaf_count(&self) -> Result<usize, String> {
+        match self {
+            TreeStore::Memory(s) => s.leaf_count(),
+            TreeStore::Disk(s) => s.leaf_count(),
+        }
+    }
+
+    fn commit(
+        &mut self,
+        nodes: Vec<((usize, usize), BigInt)>,
+        leaf_count: usize,
+    ) -> Result<(), String> {
+        match self {
+            TreeStore::Memory(s) => s.commit(nodes, leaf_count),
+            TreeStore::Disk(s) => s.commit(nodes, leaf_count),
+        }
+    }
+}
@@ -6,25 +6,38 @@
 use ark_bn254::Bn254;
 use ark_bn254::Fr;
 use ark_circom::CircomBuilder;
-use ark_groth16::{create_random_proof as prove, Proof};
+use ark_groth16::Proof;
 use baby_jub::{new_key, poseidon_hash, Point, G, Q};
 use color_eyre::Result;
 use num_bigint::{BigInt, RandBigInt, ToBigInt};
 use std::collections::HashMap;
 use std::time::SystemTime;
 
-use crate::ca_client::{Credential, CredentialRequest, CA};
+use crate::attestation::Evidence;
+use crate::attribute_set::{set_commit, AttributeSet};
+use crate::ca_client::{
+    ca_hash, generators_hash, Credential, CredentialRequest, PossessionProof, RenewalRequest, CA,
+};
+use crate::challenge::{short_ctx, Challenge};
 use crate::committee_client::Committee;
-use crate::tpke::{CipherDual, PublicKey};
+use crate::get_timestamp;
+use crate::merkle_tree::{DualTree, MerkleTree};
+use crate::predicate::Assertion;
+use crate::presentation::{Presentation, Statement};
+use crate::snarkjs::prove;
+use crate::tpke::{random_scalar, CipherDual, PublicKey};
+use crate::tree_store::NodeStore;
 use crate::IdentityManager;
-use num_traits::One;
+use num_traits::{One, Zero};
 use postcard::{from_bytes, to_stdvec};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::fs::File;
 use std::io::Write;
 
-use ethers::{prelude::SignerMiddleware, providers::Middleware, signers::Signer, types::Address};
+use ethers::{
+    prelude::SignerMiddleware, providers::Middleware, signers::Signer, types::Address, types::U256,
+};
 use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -53,11 +66,13 @@ pub struct CredentialStore {
     pub credential: Option<Credential>,
     pub derived_keys: HashMap<BigInt, KeyStore>,
     pub ca_key: Point,
+    // generators of the pedersen commitment, published by the CA
+    pub generators: Vec<Point>,
 }
 
 impl CredentialStore {
     // set up
-    pub fn new(attributes: Vec<BigInt>, ca_key: Point) -> Self {
+    pub fn new(attributes: Vec<BigInt>, ca_key: Point, generators: Vec<Point>) -> Self {
         let mut rng = rand::thread_rng();
         let master_key = rng.gen_biguint(256).to_bigint().unwrap() % Q.clone();
         let mut beta = rng.gen_biguint(256).to_bigint().unwrap() % Q.clone();
@@ -75,6 +90,7 @@ impl CredentialStore {
             credential: None,
             derived_keys: HashMap::new(),
             ca_key,
+            generators,
         }
     }
 
@@ -122,6 +138,8 @@ pub struct ApplicationKey {
     // onchain address
     pub address: BigInt,
     pub cipher: CipherDual,
+    // hash of the challenge of the verifier, 0 if the proof is not bound to one
+    pub ctx: BigInt,
     // zero knowledge proof
     pub proof: Proof<Bn254>,
     pub pub_inputs: Vec<Fr>,
@@ -168,7 +186,19 @@ impl Client {
         expiration: u64,
         ca: &CA,
     ) -> CredentialRequest {
-        let raw_credential = CredentialStore::new(attributes.clone(), ca.pubkey());
+        self.request_credential_with_evidence(attributes, Vec::new(), expiration, ca)
+    }
+
+    // Request a credential from CA, with documents attesting the attributes.
+    pub fn request_credential_with_evidence(
+        &mut self,
+        attributes: Vec<BigInt>,
+        evidence: Vec<Evidence>,
+        expiration: u64,
+        ca: &CA,
+    ) -> CredentialRequest {
+        let raw_credential =
+            CredentialStore::new(attributes.clone(), ca.pubkey(), ca.generators.clone());
         let beta_encode = Point::from_y(&raw_credential.beta, false).unwrap();
         // encrypt the trapdoor
         let (cipher, k) = self.tpke_key.encrypt(&beta_encode);
@@ -199,15 +229,27 @@ impl Client {
             time_start.elapsed().unwrap().as_millis()
         );
 
-        let request = CredentialRequest {
+        let mut request = CredentialRequest {
             master_key_g: raw_credential.master_key_g(),
             beta_g: raw_credential.beta_g(),
             attributes,
             expiration,
+            schema_hash: ca.schema.hash(),
             cipher,
             ca_key: ca.pubkey(),
             cipher_proof: proof,
+            nonce: random_scalar(),
+            timestamp: get_timestamp(),
+            possession: PossessionProof::default(),
+            evidence,
         };
+        // prove the knowledge of the keys to the CA, bound to the request
+        request.possession = PossessionProof::new(
+            &ca.pubkey(),
+            &request.hash().unwrap(),
+            &raw_credential.master_key,
+            &raw_credential.beta,
+        );
 
         self.credentials
             .insert(raw_credential.master_key_g(), raw_credential);
@@ -223,6 +265,51 @@ impl Client {
             });
     }
 
+    // Request the renewal of the credential of the master key, e.g. before it expires.
+    pub fn request_renewal(
+        &self,
+        master_key: &Point,
+        expiration: u64,
+        ca: &CA,
+    ) -> Result<RenewalRequest, String> {
+        let cs = self
+            .credentials
+            .get(master_key)
+            .ok_or("Unknown credential")?;
+        let credential = cs.credential.as_ref().ok_or("No credential to renew")?;
+        let mut request = RenewalRequest {
+            master_key_g: cs.master_key_g(),
+            beta_g: cs.beta_g(),
+            expiration,
+            schema_hash: credential.schema_hash.clone(),
+            ca_key: ca.pubkey(),
+            nonce: random_scalar(),
+            timestamp: get_timestamp(),
+            possession: PossessionProof::default(),
+        };
+        // prove the knowledge of the keys to the CA, bound to the request
+        request.possession =
+            PossessionProof::new(&ca.pubkey(), &request.hash()?, &cs.master_key, &cs.beta);
+        Ok(request)
+    }
+
+    // Replace the credential with its renewal. The derived pseudonyms are kept.
+    pub fn renew_credential(&mut self, credential: Credential) -> Result<(), String> {
+        let cs = self
+            .credentials
+            .get_mut(&credential.master_key_g)
+            .ok_or("Unknown credential")?;
+        let current = cs.credential.as_ref().ok_or("No credential to renew")?;
+        if current.beta_g != credential.beta_g
+            || current.attr_commit != credential.attr_commit
+            || current.schema_hash != credential.schema_hash
+        {
+            return Err("The renewal does not match the credential".to_string());
+        }
+        cs.update_credential(credential);
+        Ok(())
+    }
+
     // register a pseudonyms
     pub fn derive_identity(
         &mut self,
@@ -231,6 +318,52 @@ impl Client {
         time_reserve: u64,
         address: &BigInt,
         n: u64,
+    ) -> IdentityRequest {
+        self.derive_identity_with(
+            committee,
+            &committee.ca_tree,
+            &committee.block_tree,
+            master_key,
+            time_reserve,
+            address,
+            n,
+        )
+    }
+
+    // Register a pseudonym with proofs against the trees of a retained roots version,
+    // e.g. the version currently accepted by the contract.
+    pub fn derive_identity_at(
+        &mut self,
+        committee: &Committee,
+        version: u64,
+        master_key: &Point,
+        time_reserve: u64,
+        address: &BigInt,
+        n: u64,
+    ) -> Result<IdentityRequest, String> {
+        let (ca_tree, block_tree) = committee.snapshot_trees(version)?;
+        Ok(self.derive_identity_with(
+            committee,
+            &ca_tree,
+            &block_tree,
+            master_key,
+            time_reserve,
+            address,
+            n,
+        ))
+    }
+
+    // register a pseudonym with proofs from the given trees
+    #[allow(clippy::too_many_arguments)]
+    fn derive_identity_with<S1: NodeStore, S2: NodeStore>(
+        &mut self,
+        committee: &Committee,
+        ca_tree: &MerkleTree<S1>,
+        block_tree: &DualTree<S2>,
+        master_key: &Point,
+        time_reserve: u64,
+        address: &BigInt,
+        n: u64,
     ) -> IdentityRequest {
         let time_start = SystemTime::now();
         // the credential
@@ -253,16 +386,15 @@ impl Client {
         let attr_blind = attr_commit + &r * G.clone();
 
         // merkle proof of CA in the trust list
-        let proof1 = committee.ca_tree.gen_inproof(cs.ca_key.scalar_y()).unwrap();
+        let proof1 = ca_tree.gen_inproof(cs.ca_key.scalar_y()).unwrap();
         // merkle proof that the credential is not in the revocation list.
-        let proof2 = committee
-            .block_tree
+        let proof2 = block_tree
             .gen_notinproof(credential.master_key_g.scalar_y())
             .unwrap();
         // root hash
         let rh = poseidon_hash(vec![
             &proof2.root,
-            &committee.ca_tree.root(),
+            &ca_tree.root().unwrap(),
             &self.tpke_key.scalar_y(),
         ])
         .unwrap();
@@ -286,7 +418,7 @@ impl Client {
         builder.push_input("rh", rh);
 
         // private input
-        builder.push_input("rc", committee.ca_tree.root());
+        builder.push_input("rc", ca_tree.root().unwrap());
         builder.push_input("rb", proof2.root.clone());
         builder.push_input("Yy", self.tpke_key.scalar_y());
 
@@ -304,6 +436,7 @@ impl Client {
         builder.push_input("k", sn.clone());
         builder.push_input("x", cs.master_key.clone());
         builder.push_input("e", e);
+        builder.push_input("sh", credential.schema_hash.clone());
         builder.push_input("b", cs.beta.clone());
         builder.push_input("Ax", attr_commit.scalar_x());
         builder.push_input("Ay", attr_commit.scalar_y());
@@ -368,6 +501,7 @@ impl Client {
         master_key: &Point,
         sn: &BigInt,
         appid: &BigInt,
+        challenge: Option<&Challenge>,
     ) -> ApplicationKey {
         // get credential information
         let cs = self.credentials.get(master_key).unwrap();
@@ -375,11 +509,11 @@ impl Client {
         let ks = cs.derived_keys.get(sn).unwrap();
         // generate the series number
         let appkey = poseidon_hash(vec![&cs.master_key, appid]).unwrap();
+        // bind the proof to the challenge
+        let ctx = challenge.map_or(BigInt::zero(), |c| c.ctx().unwrap());
         // generate proof
         let mut builder = CircomBuilder::new(committee.app_cfg.clone());
-        let ss = (appid & ((BigInt::one() << 160) - BigInt::one()))
-            + ((self.tpke_key.scalar_x() & BigInt::one()) << 160)
-            + ((ks.cipher.c2.scalar_x() & BigInt::one()) << 161);
+        let ss = appkey_ss(appid, &self.tpke_key, &ks.cipher.c2, &ctx);
         builder.push_input("key", appkey.clone());
         builder.push_input("ss", ss);
 
@@ -404,6 +538,7 @@ impl Client {
             appkey,
             sn: sn.clone(),
             cipher: ks.cipher.clone(),
+            ctx,
             proof,
             pub_inputs,
             address: ks.address.clone(),
@@ -418,25 +553,34 @@ impl Client {
         sn: &BigInt,
         l_range: Vec<BigInt>,
         r_range: Vec<BigInt>,
-    ) -> (Point, BigInt, Proof<Bn254>, Vec<Fr>) {
+        challenge: &Challenge,
+    ) -> Result<(Point, BigInt, Proof<Bn254>, Vec<Fr>), String> {
         // get credential information
         let cs = self.credentials.get(master_key).unwrap();
+        // the ranges and the attributes must match the circuit
+        if cs.attributes.len() != committee.attribute_num {
+            return Err("The credential does not match the identity circuit".to_string());
+        }
+        if l_range.len() != committee.attribute_num || r_range.len() != committee.attribute_num {
+            return Err("Invalid number of ranges".to_string());
+        }
+        let credential = cs.credential.as_ref().unwrap();
+        // the generators must be the ones used by the CA for this credential
+        let attr_commit = cs
+            .attributes
+            .iter()
+            .zip(cs.generators.iter())
+            .fold(Point::identity(), |sum, val| sum + val.0 * val.1);
+        if cs.generators.len() != cs.attributes.len() || attr_commit != credential.attr_commit {
+            return Err("The generators do not match the credential".to_string());
+        }
         // get pseudonyms information
         let ks = cs.derived_keys.get(sn).unwrap();
         // compute the hash of public inputs
-        let hash1 = poseidon_hash(l_range.iter().take(6).collect()).unwrap();
-        let hash2 = poseidon_hash(r_range.iter().take(6).collect()).unwrap();
-        let lrcm = poseidon_hash(vec![
-            &l_range[6],
-            &l_range[7],
-            &r_range[6],
-            &r_range[7],
-            &hash1,
-            &hash2,
-        ])
-        .unwrap();
+        let ctx = challenge.ctx()?;
+        let ca_hash = ca_hash(&generators_hash(&cs.generators)?, &credential.schema_hash)?;
+        let lrcm = lrcm_hash(&l_range, &r_range, &ca_hash, &ctx)?;
 
-        let credential = cs.credential.as_ref().unwrap();
         // pedersen commitment
         let attr_blind = &credential.attr_commit + &ks.commit_nonce * G.clone();
 
@@ -458,6 +602,13 @@ impl Client {
             builder.push_input("r", r.clone());
         }
 
+        for g in &cs.generators {
+            builder.push_input("Gx", g.scalar_x());
+            builder.push_input("Gy", g.scalar_y());
+        }
+        builder.push_input("sh", credential.schema_hash.clone());
+        builder.push_input("ctx", ctx);
+
         let mut rng = rand::thread_rng();
 
         let circom = builder.build().unwrap();
@@ -466,7 +617,189 @@ impl Client {
         // the proof
         let proof = prove(circom, &committee.pedersen_params, &mut rng).unwrap();
 
-        (attr_blind, lrcm, proof, pub_inputs)
+        Ok((attr_blind, lrcm, proof, pub_inputs))
+    }
+
+    // Prove an assertion built from predicates, see `predicate::Assertion`.
+    pub fn prove_assertion(
+        &self,
+        committee: &Committee,
+        master_key: &Point,
+        sn: &BigInt,
+        assertion: &Assertion,
+        challenge: &Challenge,
+    ) -> Result<(Point, BigInt, Proof<Bn254>, Vec<Fr>), String> {
+        let cs = self.credentials.get(master_key).unwrap();
+        let credential = cs.credential.as_ref().unwrap();
+        if assertion.schema.hash() != credential.schema_hash {
+            return Err("The assertion does not match the schema of the credential".to_string());
+        }
+        if !assertion.holds(&cs.attributes) {
+            return Err("The attributes do not satisfy the assertion".to_string());
+        }
+        self.gen_identity_proof(
+            committee,
+            master_key,
+            sn,
+            assertion.l_range.clone(),
+            assertion.r_range.clone(),
+            challenge,
+        )
+    }
+
+    // Generate a proof that the attribute at `index` is (or is not) in the set, see
+    // `attribute_set::AttributeSet`.
+    pub fn prove_set(
+        &self,
+        committee: &Committee,
+        master_key: &Point,
+        sn: &BigInt,
+        index: usize,
+        set: &AttributeSet,
+        challenge: &Challenge,
+    ) -> Result<(Point, BigInt, Proof<Bn254>, Vec<Fr>), String> {
+        // get credential information
+        let cs = self.credentials.get(master_key).unwrap();
+        // the attributes must match the circuit
+        if cs.attributes.len() != committee.attribute_num {
+            return Err("The credential does not match the set circuit".to_string());
+        }
+        let value = cs.attributes.get(index).ok_or("Unknown attribute")?;
+        let credential = cs.credential.as_ref().unwrap();
+        let attr_commit = cs
+            .attributes
+            .iter()
+            .zip(cs.generators.iter())
+            .fold(Point::identity(), |sum, val| sum + val.0 * val.1);
+        if cs.generators.len() != cs.attributes.len() || attr_commit != credential.attr_commit {
+            return Err("The generators do not match the credential".to_string());
+        }
+        // get pseudonyms information
+        let ks = cs.derived_keys.get(sn).unwrap();
+        // path of the value in the set
+        let witness = set.witness(value)?;
+        let index = index.to_bigint().unwrap();
+        let member = (set.is_member() as usize).to_bigint().unwrap();
+        let ctx = challenge.ctx()?;
+        let ca_hash = ca_hash(&generators_hash(&cs.generators)?, &credential.schema_hash)?;
+        let scm = set_commit(&witness.root, &index, &member, &ca_hash, &ctx)?;
+
+        // pedersen commitment
+        let attr_blind = &credential.attr_commit + &ks.commit_nonce * G.clone();
+
+        let mut builder = CircomBuilder::new(committee.set_cfg.clone());
+        builder.push_input("Ax", attr_blind.scalar_x());
+        builder.push_input("Ay", attr_blind.scalar_y());
+        builder.push_input("scm", scm.clone());
+        builder.push_input("k", ks.commit_nonce.clone());
+
+        for a in &cs.attributes {
+            builder.push_input("a", a.clone());
+        }
+
+        for g in &cs.generators {
+            builder.push_input("Gx", g.scalar_x());
+            builder.push_input("Gy", g.scalar_y());
+        }
+        builder.push_input("sh", credential.schema_hash.clone());
+
+        builder.push_input("index", index);
+        builder.push_input("member", member);
+        for p in &witness.path {
+            builder.push_input("path", p.clone());
+        }
+        builder.push_input("key", witness.key);
+        builder.push_input("leaf", witness.leaf);
+        builder.push_input("root", witness.root);
+        builder.push_input("ctx", ctx);
+
+        let mut rng = rand::thread_rng();
+
+        let circom = builder.build().unwrap();
+        // public inputs
+        let pub_inputs = circom.get_public_inputs().unwrap();
+        // the proof
+        let proof = prove(circom, &committee.set_params, &mut rng).unwrap();
+
+        Ok((attr_blind, scm, proof, pub_inputs))
+    }
+
+    // Prove the assertion in a presentation verified off-chain, see
+    // `presentation::Verifier`.
+    pub fn present_assertion(
+        &self,
+        committee: &Committee,
+        master_key: &Point,
+        sn: &BigInt,
+        assertion: &Assertion,
+        challenge: &Challenge,
+    ) -> Result<Presentation, String> {
+        let (_, _, proof, pub_inputs) =
+            self.prove_assertion(committee, master_key, sn, assertion, challenge)?;
+        let cs = self.credentials.get(master_key).unwrap();
+        let statement = Statement::Assertion {
+            ca_key: cs.ca_key.clone(),
+            assertion: assertion.clone(),
+        };
+        Ok(Presentation::new(
+            &committee.pedersen_params.vk,
+            statement,
+            challenge,
+            &proof,
+            &pub_inputs,
+        ))
+    }
+
+    // Prove that the attribute at `index` is (or is not) in the set in a presentation.
+    pub fn present_set(
+        &self,
+        committee: &Committee,
+        master_key: &Point,
+        sn: &BigInt,
+        index: usize,
+        set: &AttributeSet,
+        challenge: &Challenge,
+    ) -> Result<Presentation, String> {
+        let (_, _, proof, pub_inputs) =
+            self.prove_set(committee, master_key, sn, index, set, challenge)?;
+        let cs = self.credentials.get(master_key).unwrap();
+        Ok(Presentation::new(
+            &committee.set_params.vk,
+            Statement::set(&cs.ca_key, index, set)?,
+            challenge,
+            &proof,
+            &pub_inputs,
+        ))
+    }
+
+    // Present the access token of the application, the domain of the challenge is the
+    // application id.
+    pub fn present_appkey(
+        &self,
+        committee: &Committee,
+        master_key: &Point,
+        sn: &BigInt,
+        challenge: &Challenge,
+    ) -> Result<Presentation, String> {
+        let app_key = self.gen_appkey(
+            committee,
+            master_key,
+            sn,
+            &challenge.domain,
+            Some(challenge),
+        );
+        let statement = Statement::AppKey {
+            appid: app_key.appid,
+            appkey: app_key.appkey,
+            c2: app_key.cipher.c2,
+        };
+        Ok(Presentation::new(
+            &committee.app_params.vk,
+            statement,
+            challenge,
+            &app_key.proof,
+            &app_key.pub_inputs,
+        ))
     }
 
     // register a pseudonym
@@ -502,13 +835,17 @@ impl Client {
         Ok(req.sn)
     }
 
-    // Respond to a sybil resistance instance on-chain.
+    // Respond to a sybil resistance instance on-chain. The proof is bound to a
+    // challenge the app issued in the identity contract (see
+    // `Challenger::issue_on_chain`), which accepts it once before it expires.
+    #[allow(clippy::too_many_arguments)]
     pub async fn send_appkey<M: Middleware + 'static, S: Signer + 'static>(
         &mut self,
         committee: &Committee,
         master_key: &Point,
         sn: &BigInt,
         appid: &BigInt,
+        challenge: &Challenge,
         contract_address: &str,
         client: Arc<SignerMiddleware<M, S>>,
     ) -> Result<()> {
@@ -517,7 +854,7 @@ impl Client {
 
         let time_start = SystemTime::now();
         // generate data
-        let req = self.gen_appkey(committee, master_key, sn, appid);
+        let req = self.gen_appkey(committee, master_key, sn, appid, Some(challenge));
         println!(
             "Sybil-resistance proof time: {:?} ms",
             time_start.elapsed().unwrap().as_millis()
@@ -525,12 +862,13 @@ impl Client {
 
         // send transaction
         let _res = contract
-            .do_set_appkey(&req.address, &req.appkey, appid, req.proof)
+            .do_set_appkey(&req.address, &req.appkey, appid, &req.ctx, req.proof)
             .await?;
         Ok(())
     }
 
     // Verify that the identity attribute meets a certain assertion on-chain.
+    #[allow(clippy::too_many_arguments)]
     pub async fn verify_identity<M: Middleware + 'static, S: Signer + 'static>(
         &mut self,
         committee: &Committee,
@@ -538,25 +876,216 @@ impl Client {
         sn: &BigInt,
         l_range: Vec<BigInt>,
         r_range: Vec<BigInt>,
+        challenge: &Challenge,
         contract_address: &str,
         client: Arc<SignerMiddleware<M, S>>,
     ) -> Result<()> {
         let address = contract_address.parse::<Address>()?;
         let contract = IdentityManager::new(address, client.clone());
 
+        // the contract is generated for a number of attributes
+        let attribute_num = contract.attribute_num().call().await?;
+        if attribute_num != U256::from(committee.attribute_num) {
+            return Err(eyre::eyre!(
+                "The contract does not match the number of attributes"
+            ));
+        }
+
         let time_start = SystemTime::now();
         // generate data
-        let (a, lrcm, proof, _) =
-            self.gen_identity_proof(committee, master_key, sn, l_range, r_range);
+        let (a, _, proof, _) = self
+            .gen_identity_proof(
+                committee,
+                master_key,
+                sn,
+                l_range.clone(),
+                r_range.clone(),
+                challenge,
+            )
+            .map_err(|e| eyre::eyre!(e))?;
         println!(
             "Identity proof time: {:?} ms",
             time_start.elapsed().unwrap().as_millis()
         );
 
+        // the contract recomputes lrcm, and checks that the CA is trusted
+        let cs = self
+            .credentials
+            .get(master_key)
+            .ok_or_else(|| eyre::eyre!("Unknown credential"))?;
+        let credential = cs
+            .credential
+            .as_ref()
+            .ok_or_else(|| eyre::eyre!("No credential"))?;
+        let ca_hash = generators_hash(&cs.generators)
+            .and_then(|h| ca_hash(&h, &credential.schema_hash))
+            .map_err(|e| eyre::eyre!(e))?;
+        let range_hash = range_hash(&l_range, &r_range).map_err(|e| eyre::eyre!(e))?;
+        let ctx = challenge.ctx().map_err(|e| eyre::eyre!(e))?;
+
         // send transaction
         let _res = contract
-            .do_veriy_identity(&a.scalar_x(), &a.scalar_y(), &lrcm, proof)
+            .do_veriy_identity(
+                &a.scalar_x(),
+                &a.scalar_y(),
+                &range_hash,
+                &ca_hash,
+                &ctx,
+                proof,
+            )
             .await?;
         Ok(())
     }
+
+    // Verify that the attribute at `index` is (or is not) in the set on-chain.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn verify_attribute_set<M: Middleware + 'static, S: Signer + 'static>(
+        &mut self,
+        committee: &Committee,
+        master_key: &Point,
+        sn: &BigInt,
+        index: usize,
+        set: &AttributeSet,
+        challenge: &Challenge,
+        contract_address: &str,
+        client: Arc<SignerMiddleware<M, S>>,
+    ) -> Result<()> {
+        let address = contract_address.parse::<Address>()?;
+        let contract = IdentityManager::new(address, client.clone());
+
+        let time_start = SystemTime::now();
+        // generate data
+        let (a, _, proof, _) = self
+            .prove_set(committee, master_key, sn, index, set, challenge)
+            .map_err(|e| eyre::eyre!(e))?;
+        println!(
+            "Set proof time: {:?} ms",
+            time_start.elapsed().unwrap().as_millis()
+        );
+
+        // the contract recomputes scm, and checks that the CA is trusted
+        let cs = self
+            .credentials
+            .get(master_key)
+            .ok_or_else(|| eyre::eyre!("Unknown credential"))?;
+        let credential = cs
+            .credential
+            .as_ref()
+            .ok_or_else(|| eyre::eyre!("No credential"))?;
+        let ca_hash = generators_hash(&cs.generators)
+            .and_then(|h| ca_hash(&h, &credential.schema_hash))
+            .map_err(|e| eyre::eyre!(e))?;
+        let root = set
+            .witness(&cs.attributes[index])
+            .map_err(|e| eyre::eyre!(e))?
+            .root;
+        let member = (set.is_member() as usize).to_bigint().unwrap();
+        let ctx = challenge.ctx().map_err(|e| eyre::eyre!(e))?;
+
+        // send transaction
+        let _res = contract
+            .do_verify_attribute_set(
+                &a.scalar_x(),
+                &a.scalar_y(),
+                &root,
+                &index.to_bigint().unwrap(),
+                &member,
+                &ca_hash,
+                &ctx,
+                proof,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+// Public input of an identity proof (lrcm), binding the ranges to the generators of
+// the CA which issued the credential and the schema of the credential, given by
+// `ca_hash` (see `ca_client::ca_hash`), and to the challenge of the verifier `ctx`
+// (see `Challenge::ctx`).
+pub fn lrcm_hash(
+    l_range: &[BigInt],
+    r_range: &[BigInt],
+    ca_hash: &BigInt,
+    ctx: &BigInt,
+) -> Result<BigInt, String> {
+    poseidon_hash(vec![&range_hash(l_range, r_range)?, ca_hash, ctx])
+}
+
+// Public input ss of a sybil-resistance proof: the application id, the signs of the
+// tpke key and of the cipher, and the challenge truncated to CTX_BITS.
+pub fn appkey_ss(appid: &BigInt, tpke_key: &Point, c2: &Point, ctx: &BigInt) -> BigInt {
+    (appid & ((BigInt::one() << 160) - BigInt::one()))
+        + ((tpke_key.scalar_x() & BigInt::one()) << 160)
+        + ((c2.scalar_x() & BigInt::one()) << 161)
+        + (short_ctx(ctx) << 162)
+}
+
+// Hash of the ranges of an identity proof. The first six bounds of l and r are hashed
+// separately, the remaining bounds are absorbed two by two together with the previous
+// hash, in the same way as `RangeHash` in circuits/pedersen_commit.circom.
+pub fn range_hash(l_range: &[BigInt], r_range: &[BigInt]) -> Result<BigInt, String> {
+    if l_range.is_empty() || l_range.len() != r_range.len() {
+        return Err("Invalid number of ranges".to_string());
+    }
+    let head = l_range.len().min(6);
+    let hash1 = poseidon_hash(l_range.iter().take(head).collect())?;
+    let hash2 = poseidon_hash(r_range.iter().take(head).collect())?;
+
+    let (l, r) = (&l_range[head..], &r_range[head..]);
+    let first = l.len().min(2);
+    let mut hash = poseidon_hash(
+        l[..first]
+            .iter()
+            .chain(&r[..first])
+            .chain([&hash1, &hash2])
+            .collect(),
+    )?;
+    for (l, r) in l[first..].chunks(2).zip(r[first..].chunks(2)) {
+        hash = poseidon_hash(l.iter().chain(r).chain([&hash]).collect())?;
+    }
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lrcm_hash, range_hash};
+    use baby_jub::poseidon_hash;
+    use num_bigint::{BigInt, ToBigInt};
+
+    #[test]
+    // the hash of eight ranges is the one of the original identity circuit
+    fn test_range_hash() {
+        let l: Vec<BigInt> = (0..8).map(|x| (x + 1).to_bigint().unwrap()).collect();
+        let r: Vec<BigInt> = (0..8).map(|x| (x + 20).to_bigint().unwrap()).collect();
+        let hash1 = poseidon_hash(l.iter().take(6).collect()).unwrap();
+        let hash2 = poseidon_hash(r.iter().take(6).collect()).unwrap();
+        let lrcm = poseidon_hash(vec![&l[6], &l[7], &r[6], &r[7], &hash1, &hash2]).unwrap();
+        assert_eq!(range_hash(&l, &r).unwrap(), lrcm);
+
+        // fewer or more attributes
+        let lrcm = poseidon_hash(vec![&hash1, &hash2]).unwrap();
+        assert_eq!(range_hash(&l[..6], &r[..6]).unwrap(), lrcm);
+        assert!(range_hash(&l[..3], &r[..3]).is_ok());
+        let l2: Vec<BigInt> = (0..11).map(|x| (x + 1).to_bigint().unwrap()).collect();
+        let r2: Vec<BigInt> = (0..11).map(|x| (x + 20).to_bigint().unwrap()).collect();
+        assert_ne!(range_hash(&l2, &r2).unwrap(), range_hash(&l, &r).unwrap());
+
+        // mismatched or empty ranges
+        assert!(range_hash(&l, &r[..7]).is_err());
+        assert!(range_hash(&[], &[]).is_err());
+
+        // the ranges are bound to the generators
+        let gh1 = 1.to_bigint().unwrap();
+        let gh2 = 2.to_bigint().unwrap();
+        assert_ne!(
+            lrcm_hash(&l, &r, &gh1, &gh1).unwrap(),
+            lrcm_hash(&l, &r, &gh2, &gh1).unwrap()
+        );
+        // and to the challenge
+        assert_ne!(
+            lrcm_hash(&l, &r, &gh1, &gh1).unwrap(),
+            lrcm_hash(&l, &r, &gh1, &gh2).unwrap()
+        );
+    }
 }
@@ -0,0 +1,60 @@
+// This is a test file, mainly used to test the trusted setup ceremony.
+
+use baby_jub::{new_key, Point, PrivateKey};
+use hades::ca_client::CA;
+use hades::ceremony::{
+    contribute, finalize, load_params, load_transcript, save_transcript, verify_contribution,
+    verify_transcript,
+};
+use hades::tpke::PublicKey;
+use hades::user_client::Client;
+use num_bigint::{BigInt, ToBigInt};
+
+#[test]
+// Test credential issuance with parameters produced by a ceremony. The initial
+// parameters are generated locally, so this only checks the mechanics.
+fn test_ceremony() {
+    let keys: Vec<PrivateKey> = (0..10).map(|_| new_key()).collect();
+    let shards: Vec<Point> = keys.iter().map(|x| x.public()).collect();
+    let tpke_key = PublicKey::new(shards.iter().collect());
+    let mut ca = CA::init(8, tpke_key.clone());
+    let initial = ca.zkp_params.clone();
+
+    // two participants contribute in turn
+    let mut rng = rand::thread_rng();
+    let (params1, c1) = contribute(&initial, &mut rng);
+    assert!(verify_contribution(&initial, &params1, &c1).is_ok());
+    let (params2, c2) = contribute(&params1, &mut rng);
+    assert!(verify_contribution(&params1, &params2, &c2).is_ok());
+
+    // contributions can not be skipped or reordered
+    assert!(verify_contribution(&initial, &params2, &c2).is_err());
+    assert!(verify_transcript(&initial, &params2, &[c2.clone(), c1.clone()]).is_err());
+    // the parameters can not be changed without a contribution
+    let mut forged = params2.clone();
+    forged.h_query.swap(0, 1);
+    assert!(verify_transcript(&initial, &forged, &[c1.clone(), c2.clone()]).is_err());
+
+    let dir = std::env::temp_dir().join(format!("hades_ceremony_{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let transcript_path = dir.join("transcript");
+    let transcript_path = transcript_path.to_str().unwrap();
+    let params_path = dir.join("params");
+    let params_path = params_path.to_str().unwrap();
+    save_transcript(&[c1, c2], transcript_path).unwrap();
+    let transcript = load_transcript(transcript_path).unwrap();
+    finalize(&initial, &params2, &transcript, params_path).unwrap();
+
+    // every client loads the final parameters
+    ca.zkp_params = load_params(params_path).unwrap();
+    assert_eq!(ca.zkp_params, params2);
+    // a corrupted file is an error
+    std::fs::write(transcript_path, [1u8, 2, 3]).unwrap();
+    assert!(load_transcript(transcript_path).is_err());
+    std::fs::remove_dir_all(&dir).ok();
+
+    let mut user = Client::new(tpke_key);
+    let attributes: Vec<BigInt> = (0..8).map(|x| (x + 10).to_bigint().unwrap()).collect();
+    let req = user.request_credential(attributes, 31536000, &ca);
+    assert!(ca.gen_credential(req).is_ok());
+}
@@ -3,10 +3,12 @@
 
 use color_eyre::Result;
 
+use hades::bench;
 use hades::ca_client::CA;
+use hades::challenge::{Challenge, Challenger};
 use hades::committee_client::Committee;
 use hades::get_timestamp;
-use hades::tpke::PublicKey;
+use hades::tpke::gen_key_shares;
 use hades::user_client::Client;
 use hades::IdentityManager;
 use num_bigint::{BigInt, Sign, ToBigInt};
@@ -96,9 +98,15 @@ async fn test_contract() -> Result<()> {
     let client = SignerMiddleware::new(provider.clone(), wallet.with_chain_id(97u64));
     let client = Arc::new(client);
 
-    let mut cm1 = Committee::new();
-    let mut cm2 = Committee::new();
-    let tpke_key = PublicKey::new(vec![&cm1.tpke_shard(), &cm2.tpke_shard()]);
+    let mut cm1 = Committee::new(8);
+    let mut cm2 = Committee::new(8);
+    // 2-of-3 tpke key, the third member is offline
+    let (tpke_key, shares) = gen_key_shares(2, 3).unwrap();
+    cm1.update_tpke_share(shares[0].clone(), 2);
+    cm2.update_tpke_share(shares[1].clone(), 2);
+    let tpke_shards: Vec<_> = shares.iter().map(|s| s.public()).collect();
+    cm1.update_tpke_shards(tpke_shards.clone());
+    cm2.update_tpke_shards(tpke_shards);
     cm1.update_tpke_key(tpke_key.clone());
     cm2.update_tpke_key(tpke_key.clone());
     cm2.update_zk_param(cm1.zkp_params.clone());
@@ -117,7 +125,9 @@ async fn test_contract() -> Result<()> {
     println!("Init committee finish!");
     // create ca
     let mut ca = CA::init(8, tpke_key.clone());
-    cm1.ca_tree.insert_nodes(vec![ca.pubkey().scalar_y()]);
+    cm1.ca_tree
+        .insert_nodes(vec![ca.pubkey().scalar_y()])
+        .unwrap();
 
     println!("start to set root:");
     let _res = cm1
@@ -159,13 +169,18 @@ async fn test_contract() -> Result<()> {
         .await?;
     println!("Identity derive finish!");
 
-    let appid = BigInt::from_str("994862232198212916674956859767646391285724603386").unwrap();
+    // the application id is the address of the app, which issues the challenge
+    let appid = BigInt::from_bytes_be(Sign::Plus, client.address().as_bytes());
+    let challenge = Challenger::new(&appid, 600)
+        .issue_on_chain(contract_address, client.clone())
+        .await?;
     let _res = user
         .send_appkey(
             &cm1,
             &req.master_key_g,
             &sn,
             &appid,
+            &challenge,
             contract_address,
             client.clone(),
         )
@@ -204,8 +219,12 @@ async fn test_contract() -> Result<()> {
     let cipher1 = user_meta.to_cipher();
     let k1 = cm1.decrypt_shard(&cipher1.c1);
     let k2 = cm2.decrypt_shard(&cipher1.c1);
+    // check the shard of the other member
+    assert!(cm1.verify_decrypt_shard(&cipher1.c1, &k2));
 
-    let (m1, m2) = cipher1.decrypt(vec![&k1, &k2], &user_address);
+    let (m1, m2) = cipher1
+        .decrypt(vec![&k1, &k2], &user_address, cm1.tpke_threshold)
+        .unwrap();
 
     assert_eq!(m2, ca.pubkey());
 
@@ -214,7 +233,11 @@ async fn test_contract() -> Result<()> {
     let k2_1 = cm1.decrypt_shard(&user_info.cipher.c1);
     let k2_2 = cm2.decrypt_shard(&user_info.cipher.c1);
 
-    let beta = user_info.cipher.decrypt(vec![&k2_1, &k2_2]).scalar_y();
+    let beta = user_info
+        .cipher
+        .decrypt(vec![&k2_1, &k2_2], cm1.tpke_threshold)
+        .unwrap()
+        .scalar_y();
 
     let derived_address = cm1
         .get_derived_address(&beta, contract_address, client.clone())
@@ -228,6 +251,14 @@ async fn test_contract() -> Result<()> {
     Ok(())
 }
 
+#[test]
+#[ignore]
+// Regenerate the committee and CA data loaded by `bench_all`. Run it with
+// `cargo test --test contract gen_fixtures -- --ignored`.
+fn gen_fixtures() -> Result<()> {
+    bench::gen_fixtures()
+}
+
 #[tokio::test]
 // bench on-chain contract interactions.
 async fn bench_all() -> Result<()> {
@@ -243,22 +274,23 @@ async fn bench_all() -> Result<()> {
     let client = Arc::new(client);
 
     println!("1. Start setting up the committee: ");
-    let mut cm1 = Committee::load("./data/test_cm1")?;
-    let mut cm2 = Committee::load("./data/test_cm2")?;
+    let (mut cm1, mut cm2, mut ca) = bench::load_fixtures()?;
     println!("1. The committee has been set up.");
 
     println!("2. Start setting up CA: ");
-    let mut ca = CA::load("./data/test_ca.bak")?;
     ca.tpke_key = cm1.tpke_key.as_ref().unwrap().clone();
     println!("2.1 Start adding CA to trusted list: ");
-    cm1.ca_tree.insert_nodes(vec![ca.pubkey().scalar_y()]);
-    cm2.ca_tree.insert_nodes(vec![ca.pubkey().scalar_y()]);
+    ca.register(&mut cm1).unwrap();
+    ca.register(&mut cm2).unwrap();
     println!("2. CA has been set up.");
 
     println!("3. Start setting up the identity contract:");
     // let _res = cm1.set_derive_vk(contract_address, client.clone()).await?;
     // let _res = cm1.set_appkey_vk(contract_address, client.clone()).await?;
     let _res = cm1.set_tpke_pub(contract_address, client.clone()).await?;
+    let _res = cm1
+        .set_trusted_ca(&ca.pubkey(), true, contract_address, client.clone())
+        .await?;
     println!("3. The identity contract has been set up.");
 
     println!("4. Start updating the identity contract:");
@@ -317,13 +349,18 @@ async fn bench_all() -> Result<()> {
     println!("6. pseudonym 2 generated.");
 
     println!("7. Start to response to Sybil-resistance: ");
-    let appid = BigInt::from_str("994862232198212916674956859767646391285724603386").unwrap();
+    // the application id is the address of the app, which issues the challenge
+    let appid = BigInt::from_bytes_be(Sign::Plus, client.address().as_bytes());
+    let challenge = Challenger::new(&appid, 600)
+        .issue_on_chain(contract_address, client.clone())
+        .await?;
     let _res = user
         .send_appkey(
             &cm1,
             &req.master_key_g,
             &sn,
             &appid,
+            &challenge,
             contract_address,
             client.clone(),
         )
@@ -338,6 +375,7 @@ async fn bench_all() -> Result<()> {
             &sn,
             (0..8).map(|x| (x + 1).to_bigint().unwrap()).collect(),
             (0..8).map(|x| (x + 20).to_bigint().unwrap()).collect(),
+            &Challenge::new(&appid, 600),
             contract_address,
             client.clone(),
         )
@@ -357,7 +395,9 @@ async fn bench_all() -> Result<()> {
     let k1 = cm1.decrypt_shard(&cipher1.c1);
     let k2 = cm2.decrypt_shard(&cipher1.c1);
 
-    let (m1, m2) = cipher1.decrypt(vec![&k1, &k2], &user_address);
+    let (m1, m2) = cipher1
+        .decrypt(vec![&k1, &k2], &user_address, cm1.tpke_threshold)
+        .unwrap();
 
     assert_eq!(m2, ca.pubkey());
 
@@ -368,7 +408,11 @@ async fn bench_all() -> Result<()> {
     let k2_1 = cm1.decrypt_shard(&user_info.cipher.c1);
     let k2_2 = cm2.decrypt_shard(&user_info.cipher.c1);
 
-    let beta = user_info.cipher.decrypt(vec![&k2_1, &k2_2]).scalar_y();
+    let beta = user_info
+        .cipher
+        .decrypt(vec![&k2_1, &k2_2], cm1.tpke_threshold)
+        .unwrap()
+        .scalar_y();
 
     let derived_address = cm1
         .get_derived_address(&beta, contract_address, client.clone())
@@ -0,0 +1,87 @@
+// This is a test file, mainly used to test the generation of the identity contract.
+
+use ark_groth16::{prepare_verifying_key, verify_proof};
+use core::str::FromStr;
+use hades::ca_client::CA;
+use hades::committee_client::Committee;
+use hades::contract_gen::{
+    gen_manager, read_constants, vk_from_constants, APP, ATTRIBUTE_NUM, DERIVE, IDENTITY,
+};
+use hades::tpke::PublicKey;
+use hades::user_client::Client;
+use num_bigint::{BigInt, ToBigInt};
+use std::fs;
+
+#[test]
+// Test that a pseudonym proof verifies with the keys written into the contract.
+fn test_gen_contract() {
+    let mut cm1 = Committee::new(8);
+    let cm2 = cm1.clone();
+    let tpke_key = PublicKey::new(vec![&cm1.tpke_shard(), &cm2.tpke_shard()]);
+    cm1.update_tpke_key(tpke_key.clone());
+
+    let mut ca = CA::init(8, tpke_key.clone());
+    cm1.ca_tree
+        .insert_nodes(vec![ca.pubkey().scalar_y()])
+        .unwrap();
+    let mut user = Client::new(tpke_key);
+    let attributes: Vec<BigInt> = (0..8).map(|x| (x + 10).to_bigint().unwrap()).collect();
+    let req = user.request_credential(attributes, 31536000, &ca);
+    let cred = ca.gen_credential(req.clone()).unwrap();
+    user.fill_credential(cred);
+    let address = BigInt::from_str("328659427551853837776595111020800456678649075473").unwrap();
+    let req2 = user.derive_identity(&cm1, &req.master_key_g, 1000, &address, 10);
+
+    let dir = std::env::temp_dir().join(format!("hades_contract_{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+    let out_path = dir.join("manager.sol");
+    cm1.gen_contract("./contracts/manager.sol.tpl", out_path.to_str().unwrap())
+        .unwrap();
+    let template = fs::read_to_string("./contracts/manager.sol.tpl").unwrap();
+    let source = fs::read_to_string(&out_path).unwrap();
+    fs::remove_dir_all(&dir).ok();
+    let constants = read_constants(&template, &source).unwrap();
+
+    assert_eq!(constants[ATTRIBUTE_NUM], "8");
+    let derive_vk = vk_from_constants(DERIVE, &constants).unwrap();
+    let pvk = prepare_verifying_key(&derive_vk);
+    assert!(verify_proof(&pvk, &req2.proof, &req2.pub_inputs).unwrap());
+
+    // the keys must match the public inputs of the template
+    assert!(gen_manager(
+        &template,
+        &cm1.app_params.vk,
+        &cm1.zkp_params.vk,
+        &cm1.pedersen_params.vk,
+        8
+    )
+    .is_err());
+    assert!(gen_manager(
+        &template,
+        &cm1.zkp_params.vk,
+        &cm1.app_params.vk,
+        &cm1.pedersen_params.vk,
+        0
+    )
+    .is_err());
+}
+
+#[test]
+// Test that the checked-in contract is the template filled with its verifying keys.
+fn test_deployed_contract() {
+    let template = fs::read_to_string("./contracts/manager.sol.tpl").unwrap();
+    let deployed = fs::read_to_string("./contracts/manager.sol").unwrap();
+    let constants = read_constants(&template, &deployed).unwrap();
+
+    let source = gen_manager(
+        &template,
+        &vk_from_constants(DERIVE, &constants).unwrap(),
+        &vk_from_constants(APP, &constants).unwrap(),
+        &vk_from_constants(IDENTITY, &constants).unwrap(),
+        constants[ATTRIBUTE_NUM].parse().unwrap(),
+    )
+    .unwrap();
+    assert_eq!(source, deployed);
+    // the keys are placeholders until the contract is generated after the trusted setup
+    assert_eq!(constants["IDENTITY_ALPHA_X"], "0");
+}
@@ -1,15 +1,57 @@
 // This is a test file, mainly used to test the application of credentials
 // and pseudonym registration.
 
-use baby_jub::G;
+use ark_bn254::Fr;
 use baby_jub::{new_key, Point, PrivateKey};
-use hades::ca_client::CA;
+use hades::attestation::{document_hash, AttestationError, DocumentHashVerifier, Evidence};
+use hades::attribute_set::AttributeSet;
+use hades::ca_client::{
+    CredentialError, CredentialRequest, IssuancePolicy, PossessionProof, Reissue, RevocationReason,
+    CA, REQUEST_TTL,
+};
+use hades::challenge::{Challenge, Challenger};
 use hades::committee_client::Committee;
+use hades::predicate::{Assertion, Predicate};
+use hades::presentation::{Presentation, Statement, Verifier};
+use hades::schema::{AttributeDef, AttributeType, AttributeValue, Schema};
+use hades::snarkjs;
 use hades::tpke::PublicKey;
-use hades::user_client::Client;
+use hades::user_client::{Client, IdentityRequest};
 use num_bigint::{BigInt, ToBigInt};
 
 use core::str::FromStr;
+use std::sync::Arc;
+
+// a tpke key of random shards, for the tests without a committee
+fn tpke_key() -> PublicKey {
+    let shards: Vec<Point> = (0..10).map(|_| new_key().public()).collect();
+    PublicKey::new(shards.iter().collect())
+}
+
+// a committee of 8 identity attributes, a CA registered with it and a user
+fn setup() -> (Committee, CA, Client) {
+    let mut cm1 = Committee::new(8);
+    let tpke_key = PublicKey::new(vec![&cm1.tpke_shard()]);
+    cm1.update_tpke_key(tpke_key.clone());
+    let mut ca = CA::init(8, tpke_key.clone());
+    ca.register(&mut cm1).unwrap();
+    (cm1, ca, Client::new(tpke_key))
+}
+
+// issue a credential of the attributes 10, ..., 17 to the user, returns the request
+fn issue(user: &mut Client, ca: &mut CA, expiration: u64) -> CredentialRequest {
+    let attributes: Vec<BigInt> = (0..8).map(|x| (x + 10).to_bigint().unwrap()).collect();
+    let req = user.request_credential(attributes, expiration, ca);
+    let cred = ca.gen_credential(req.clone()).unwrap();
+    user.fill_credential(cred);
+    req
+}
+
+// derive 10 pseudonyms of the credential, bound to the address
+fn derive(user: &mut Client, committee: &Committee, req: &CredentialRequest) -> IdentityRequest {
+    let address = BigInt::from_str("328659427551853837776595111020800456678649075473").unwrap();
+    user.derive_identity(committee, &req.master_key_g, 1000, &address, 10)
+}
 
 #[test]
 // Test the credential application.
@@ -29,18 +71,104 @@ fn test_credential_request() {
     let expiration = 31536000;
 
     // generate request
-    let req = user.request_credential(attributes, expiration, &ca);
+    let req = user.request_credential(attributes.clone(), expiration, &ca);
+
+    // the keys of another user cannot be bound in a credential
+    let mut forged = req.clone();
+    forged.master_key_g = new_key().public();
+    assert!(ca.gen_credential(forged).is_err());
+    // the proof of possession is bound to the CA and the request
+    let mut other = CA::init(8, ca.tpke_key.clone());
+    assert!(other.gen_credential(req.clone()).is_err());
+    let mut forged = req.clone();
+    forged.nonce += 1;
+    assert!(ca.gen_credential(forged).is_err());
+    let mut forged = req.clone();
+    forged.attributes[0] += 1;
+    assert!(ca.gen_credential(forged).is_err());
+    let mut forged = req.clone();
+    forged.expiration -= 1;
+    assert!(ca.gen_credential(forged).is_err());
+    // a request is only accepted around its timestamp
+    let cs = &user.credentials[&req.master_key_g];
+    let mut stale = req.clone();
+    stale.timestamp -= REQUEST_TTL + 1;
+    stale.possession = PossessionProof::new(
+        &ca.pubkey(),
+        &stale.hash().unwrap(),
+        &cs.master_key,
+        &cs.beta,
+    );
+    assert_eq!(
+        ca.gen_credential(stale).unwrap_err(),
+        CredentialError::InvalidRequest("Expired request".to_string())
+    );
+
     // generate credential
-    let cred = ca.gen_credential(req).unwrap();
+    let cred = ca.gen_credential(req.clone()).unwrap();
     // save credential
     user.fill_credential(cred);
+    // the request cannot be replayed
+    assert!(ca.gen_credential(req).is_err());
+}
+
+#[test]
+// Test the credential application with typed attributes.
+fn test_schema_credential() {
+    let keys: Vec<PrivateKey> = (0..10).map(|_| new_key()).collect();
+    let shards: Vec<Point> = keys.iter().map(|x| x.public()).collect();
+    let tpke_key = PublicKey::new(shards.iter().map(|x| x).collect());
+
+    let def = |name: &str, attr_type| AttributeDef {
+        name: name.to_string(),
+        attr_type,
+    };
+    let schema = Schema::new(
+        "student card",
+        vec![
+            def("age", AttributeType::Integer),
+            def("birthday", AttributeType::Date),
+            def(
+                "degree",
+                AttributeType::Enum(vec!["bachelor".to_string(), "master".to_string()]),
+            ),
+            def("name", AttributeType::String),
+            def("graduated", AttributeType::Boolean),
+        ],
+    )
+    .unwrap();
+    let mut ca = CA::init(5, tpke_key.clone());
+    assert!(ca.update_schema(Schema::untyped(8)).is_err());
+    ca.update_schema(schema.clone()).unwrap();
+
+    let mut user = Client::new(tpke_key);
+    let attributes = schema
+        .encode(&[
+            AttributeValue::Integer(BigInt::from(22)),
+            AttributeValue::Date(946684800),
+            AttributeValue::Enum("master".to_string()),
+            AttributeValue::String("alice".to_string()),
+            AttributeValue::Boolean(false),
+        ])
+        .unwrap();
+    let req = user.request_credential(attributes.clone(), 31536000, &ca);
+    let cred = ca.gen_credential(req.clone()).unwrap();
+    assert_eq!(cred.schema_hash, schema.hash());
+
+    // the request must use the schema of the CA, with valid values
+    let mut req2 = req.clone();
+    req2.schema_hash = Schema::untyped(5).hash();
+    assert!(ca.gen_credential(req2).is_err());
+    let mut req3 = req;
+    req3.attributes[2] = BigInt::from(2);
+    assert!(ca.gen_credential(req3).is_err());
 }
 
 #[test]
 // test pseudonym register
 fn test_key_derive() {
     // init commit
-    let mut cm1 = Committee::new();
+    let mut cm1 = Committee::new(8);
     let mut cm2 = cm1.clone();
     let tpke_key = PublicKey::new(vec![&cm1.tpke_shard(), &cm2.tpke_shard()]);
     cm1.update_tpke_key(tpke_key.clone());
@@ -50,7 +178,19 @@ fn test_key_derive() {
     println!("Init committee finish!");
     // create ca
     let mut ca = CA::init(8, tpke_key.clone());
-    cm1.ca_tree.insert_nodes(vec![ca.pubkey().scalar_y()]);
+    // the CA must match the identity circuit
+    assert!(cm1
+        .add_ca(&ca.pubkey(), &ca.generators[..7], &ca.schema.hash())
+        .is_err());
+    let mut small = CA::init(7, tpke_key.clone());
+    assert_eq!(
+        small.register(&mut cm1),
+        Err(CredentialError::AttributeNum {
+            expected: 8,
+            found: 7
+        })
+    );
+    ca.register(&mut cm1).unwrap();
 
     println!("Init CA finish!");
 
@@ -59,6 +199,15 @@ fn test_key_derive() {
     let attributes: Vec<BigInt> = (0..8).map(|x| (x + 10).to_bigint().unwrap()).collect();
     let expiration = 31536000;
     let req = user.request_credential(attributes, expiration, &ca);
+    let mut short = req.clone();
+    short.attributes.pop();
+    assert_eq!(
+        ca.gen_credential(short).err(),
+        Some(CredentialError::AttributeNum {
+            expected: 8,
+            found: 7
+        })
+    );
     let cred = ca.gen_credential(req.clone()).unwrap();
     user.fill_credential(cred);
 
@@ -74,10 +223,73 @@ fn test_key_derive() {
     assert!(cm1.verify_key_request(&req2));
 }
 
+#[test]
+// Test the pseudonym register proof in the format of snarkjs.
+fn test_snarkjs_proof() {
+    let (cm1, mut ca, mut user) = setup();
+    let req = issue(&mut user, &mut ca, 31536000);
+    let req2 = derive(&mut user, &cm1, &req);
+
+    // the key, proof and inputs survive a round trip through JSON
+    let vk = snarkjs::vk_from_json(&snarkjs::vk_to_json(&cm1.zkp_params.vk)).unwrap();
+    assert_eq!(vk, cm1.zkp_params.vk);
+    let proof = snarkjs::proof_to_json(&req2.proof);
+    let inputs = snarkjs::inputs_to_json(&req2.pub_inputs);
+    assert!(snarkjs::verify(&vk, &proof, &inputs).unwrap());
+
+    // a tampered input is rejected
+    let mut tampered = req2.pub_inputs.clone();
+    tampered[0] += Fr::from(1u64);
+    let tampered = snarkjs::inputs_to_json(&tampered);
+    assert!(!snarkjs::verify(&vk, &proof, &tampered).unwrap());
+    let missing = snarkjs::inputs_to_json(&req2.pub_inputs[1..]);
+    assert!(snarkjs::verify(&vk, &proof, &missing).is_err());
+}
+
+#[test]
+// Test pseudonym register against a retained snapshot of the roots.
+fn test_root_history() {
+    let (mut cm1, mut ca, mut user) = setup();
+    let req = issue(&mut user, &mut ca, 31536000);
+
+    // the roots of version 1 are on chain, then another credential is revoked
+    let roots_hash = cm1.snapshot_roots(1).unwrap().roots_hash.clone();
+    let (root1, root2) = cm1.block_tree.roots().unwrap();
+    cm1.block_tree
+        .insert_nodes(vec![new_key().public().scalar_y()])
+        .unwrap();
+    assert_ne!(cm1.block_tree.roots().unwrap(), (root1, root2));
+    assert!(cm1.snapshot_roots(0).is_err());
+
+    let address = BigInt::from_str("328659427551853837776595111020800456678649075473").unwrap();
+    assert!(user
+        .derive_identity_at(&cm1, 2, &req.master_key_g, 1000, &address, 10)
+        .is_err());
+    let req2 = user
+        .derive_identity_at(&cm1, 1, &req.master_key_g, 1000, &address, 10)
+        .unwrap();
+    assert!(cm1.verify_key_request(&req2));
+    assert_eq!(cm1.roots_snapshot(1).unwrap().roots_hash, roots_hash);
+
+    // only the latest snapshots are retained, older trees are rebuilt from the diffs
+    cm1.history_size = 2;
+    cm1.snapshot_roots(2).unwrap();
+    let roots = cm1.block_tree.roots().unwrap();
+    cm1.block_tree
+        .insert_nodes(vec![new_key().public().scalar_y()])
+        .unwrap();
+    cm1.snapshot_roots(3).unwrap();
+    assert!(cm1.roots_snapshot(1).is_none());
+    assert_eq!(cm1.latest_snapshot().unwrap().version, 3);
+    let (_, block_tree) = cm1.snapshot_trees(2).unwrap();
+    assert_eq!(block_tree.roots().unwrap(), roots);
+    assert!(cm1.snapshot_trees(1).is_err());
+}
+
 #[test]
 // Test the response to sybil resistance.
 fn test_app_key() {
-    let mut cm1 = Committee::new();
+    let mut cm1 = Committee::new(8);
     let mut cm2 = cm1.clone();
     let tpke_key = PublicKey::new(vec![&cm1.tpke_shard(), &cm2.tpke_shard()]);
     cm1.update_tpke_key(tpke_key.clone());
@@ -88,7 +300,9 @@ fn test_app_key() {
     println!("Init committee finish!");
     // create ca
     let mut ca = CA::init(8, tpke_key.clone());
-    cm1.ca_tree.insert_nodes(vec![ca.pubkey().scalar_y()]);
+    cm1.ca_tree
+        .insert_nodes(vec![ca.pubkey().scalar_y()])
+        .unwrap();
 
     println!("Init CA finish!");
 
@@ -112,16 +326,26 @@ fn test_app_key() {
     // application id
     let appid = BigInt::from_str("628659427551853837776595111020800456678649075473").unwrap();
     // generate proof
-    let app_key = user.gen_appkey(&cm1, &req.master_key_g, &req2.sn, &appid);
+    let app_key = user.gen_appkey(&cm1, &req.master_key_g, &req2.sn, &appid, None);
 
     assert!(cm1.verify_app_key(&app_key));
+
+    // a proof bound to the challenge of the application is not accepted by another one
+    let mut challenger = Challenger::new(&appid, 60);
+    let challenge = challenger.issue();
+    let app_key = user.gen_appkey(&cm1, &req.master_key_g, &req2.sn, &appid, Some(&challenge));
+    assert!(cm1.verify_app_key_challenge(&app_key, &challenge).unwrap());
+    assert!(challenger.consume(&challenge).is_ok());
+    assert!(!cm1
+        .verify_app_key_challenge(&app_key, &challenger.issue())
+        .unwrap());
 }
 
 #[test]
 // Test the proof that identity attributes meet the assertion.
 fn test_identity_proof() {
     // init a committee
-    let mut cm1 = Committee::new();
+    let mut cm1 = Committee::new(8);
     let mut cm2 = cm1.clone();
     let tpke_key = PublicKey::new(vec![&cm1.tpke_shard(), &cm2.tpke_shard()]);
     cm1.update_tpke_key(tpke_key.clone());
@@ -131,13 +355,9 @@ fn test_identity_proof() {
 
     println!("Init committee finish!");
     // create ca
-    let mut ca = CA::load("./data/test_ca.bak").unwrap();
-    ca.tpke_key = tpke_key.clone();
-    for p in &ca.generators {
-        println!("x: {:?}, y: {:?}", p.scalar_x(), p.scalar_y());
-    }
-    println!("x: {:?}, y: {:?}", G.scalar_x(), G.scalar_y());
-    cm1.ca_tree.insert_nodes(vec![ca.pubkey().scalar_y()]);
+    // any CA can be used, its generators are inputs of the identity proof
+    let mut ca = CA::init(8, tpke_key.clone());
+    ca.register(&mut cm1).unwrap();
 
     println!("Init CA finish!");
 
@@ -158,12 +378,491 @@ fn test_identity_proof() {
     let req2 = user.derive_identity(&cm1, &req.master_key_g, time_reserve, &address, num);
     println!("Identity derive finish!");
 
-    let (_a, _lrcm, proof, pub_inputs) = user.gen_identity_proof(
+    // the challenge of the verifier
+    let domain = BigInt::from(1);
+    let challenge = Challenge::new(&domain, 60);
+    let (_a, _lrcm, proof, pub_inputs) = user
+        .gen_identity_proof(
+            &cm1,
+            &req.master_key_g,
+            &req2.sn,
+            (0..8).map(|x| (x + 1).to_bigint().unwrap()).collect(),
+            (0..8).map(|x| (x + 20).to_bigint().unwrap()).collect(),
+            &challenge,
+        )
+        .unwrap();
+    assert!(cm1.verify_identity_proof(pub_inputs.clone(), &proof));
+
+    // the assertion is checked with the generators of the CA
+    let l_range: Vec<BigInt> = (0..8).map(|x| (x + 1).to_bigint().unwrap()).collect();
+    let r_range: Vec<BigInt> = (0..8).map(|x| (x + 20).to_bigint().unwrap()).collect();
+    let verify = |cm: &Committee, ca_key: &Point, l: &[BigInt], r: &[BigInt], c: &Challenge| {
+        cm.verify_identity_assertion(ca_key, l, r, c, pub_inputs.clone(), &proof)
+            .unwrap()
+    };
+    assert!(verify(&cm1, &ca.pubkey(), &l_range, &r_range, &challenge));
+    assert!(!verify(&cm1, &ca.pubkey(), &r_range, &l_range, &challenge));
+    // the proof is bound to the schema of the CA
+    let mut renamed = cm1.clone();
+    renamed
+        .add_ca(&ca.pubkey(), &ca.generators, &BigInt::from(1))
+        .unwrap();
+    assert!(!verify(
+        &renamed,
+        &ca.pubkey(),
+        &l_range,
+        &r_range,
+        &challenge
+    ));
+    // the proof cannot be replayed to another verifier, or after the challenge expired
+    assert!(!verify(
+        &cm1,
+        &ca.pubkey(),
+        &l_range,
+        &r_range,
+        &Challenge::new(&domain, 60)
+    ));
+    let expired = Challenge {
+        expiry: 0,
+        ..challenge.clone()
+    };
+    assert!(!verify(&cm1, &ca.pubkey(), &l_range, &r_range, &expired));
+    let mut other = CA::init(8, ca.tpke_key.clone());
+    other.register(&mut cm1).unwrap();
+    assert!(!verify(
         &cm1,
-        &req.master_key_g,
-        &req2.sn,
-        (0..8).map(|x| (x + 1).to_bigint().unwrap()).collect(),
-        (0..8).map(|x| (x + 20).to_bigint().unwrap()).collect(),
+        &other.pubkey(),
+        &l_range,
+        &r_range,
+        &challenge
+    ));
+    // the proofs of a removed CA are rejected
+    let mut removed = cm1.clone();
+    removed.remove_cas(&[ca.pubkey()]).unwrap();
+    assert!(!removed.ca_tree.contains(&ca.pubkey().scalar_y()).unwrap());
+    assert!(removed
+        .verify_identity_assertion(
+            &ca.pubkey(),
+            &l_range,
+            &r_range,
+            &challenge,
+            pub_inputs.clone(),
+            &proof
+        )
+        .is_err());
+    assert!(removed.remove_cas(&[ca.pubkey()]).is_err());
+
+    // prove an assertion built from predicates
+    let assertion = Assertion::new(&ca.schema)
+        .require(
+            "attr0",
+            Predicate::Ge(AttributeValue::Integer(BigInt::from(10))),
+        )
+        .unwrap()
+        .require(
+            "attr3",
+            Predicate::Eq(AttributeValue::Integer(BigInt::from(13))),
+        )
+        .unwrap();
+    let (_a, _lrcm, proof, pub_inputs) = user
+        .prove_assertion(&cm1, &req.master_key_g, &req2.sn, &assertion, &challenge)
+        .unwrap();
+    assert!(cm1
+        .verify_assertion(
+            &ca.pubkey(),
+            &assertion,
+            &challenge,
+            pub_inputs.clone(),
+            &proof
+        )
+        .unwrap());
+    // the same ranges over another schema are not proven
+    let renamed = Assertion {
+        schema: Schema::new("renamed", ca.schema.attributes.clone()).unwrap(),
+        ..assertion.clone()
+    };
+    assert!(!cm1
+        .verify_assertion(&ca.pubkey(), &renamed, &challenge, pub_inputs, &proof)
+        .unwrap());
+    let unsatisfied = Assertion::new(&ca.schema)
+        .require(
+            "attr1",
+            Predicate::Gt(AttributeValue::Integer(BigInt::from(11))),
+        )
+        .unwrap();
+    assert!(user
+        .prove_assertion(&cm1, &req.master_key_g, &req2.sn, &unsatisfied, &challenge)
+        .is_err());
+
+    // the ranges must match the number of attributes
+    assert!(user
+        .gen_identity_proof(
+            &cm1,
+            &req.master_key_g,
+            &req2.sn,
+            (0..7).map(|x| (x + 1).to_bigint().unwrap()).collect(),
+            (0..7).map(|x| (x + 20).to_bigint().unwrap()).collect(),
+            &challenge,
+        )
+        .is_err());
+}
+
+#[test]
+// Test the proof that an identity attribute is (or is not) in a set.
+fn test_set_proof() {
+    let (cm1, mut ca, mut user) = setup();
+    let req = issue(&mut user, &mut ca, 31536000);
+    let req2 = derive(&mut user, &cm1, &req);
+
+    // attr2 = 12 is in the allowed set and not in the blocked set
+    let values = |v: &[i64]| v.iter().map(|x| BigInt::from(*x)).collect::<Vec<_>>();
+    let allowed = AttributeSet::member(&values(&[3, 12, 40])).unwrap();
+    let blocked = AttributeSet::non_member(&values(&[5, 11, 13, 50])).unwrap();
+    let challenge = Challenge::new(&BigInt::from(1), 60);
+    for set in [&allowed, &blocked] {
+        let (_a, _scm, proof, pub_inputs) = user
+            .prove_set(&cm1, &req.master_key_g, &req2.sn, 2, set, &challenge)
+            .unwrap();
+        assert!(cm1
+            .verify_set_proof(&ca.pubkey(), 2, set, &challenge, pub_inputs.clone(), &proof)
+            .unwrap());
+        // the proof is bound to the attribute and to the challenge
+        assert!(!cm1
+            .verify_set_proof(&ca.pubkey(), 3, set, &challenge, pub_inputs.clone(), &proof)
+            .unwrap());
+        let other = Challenge::new(&BigInt::from(1), 60);
+        assert!(!cm1
+            .verify_set_proof(&ca.pubkey(), 2, set, &other, pub_inputs, &proof)
+            .unwrap());
+    }
+
+    // attr1 = 11 is blocked, attr3 = 13 is not allowed
+    assert!(user
+        .prove_set(&cm1, &req.master_key_g, &req2.sn, 1, &blocked, &challenge)
+        .is_err());
+    assert!(user
+        .prove_set(&cm1, &req.master_key_g, &req2.sn, 3, &allowed, &challenge)
+        .is_err());
+    assert!(user
+        .prove_set(&cm1, &req.master_key_g, &req2.sn, 8, &allowed, &challenge)
+        .is_err());
+}
+
+#[test]
+// Test the presentations verified off-chain, without the committee.
+fn test_presentation() {
+    let (cm1, mut ca, mut user) = setup();
+    let req = issue(&mut user, &mut ca, 31536000);
+    let req2 = derive(&mut user, &cm1, &req);
+
+    // the verifier of the dapp only holds the exported keys
+    let verifier = Verifier::from_json(&cm1.verifier().to_json()).unwrap();
+    let appid = BigInt::from_str("628659427551853837776595111020800456678649075473").unwrap();
+    let mut challenger = Challenger::new(&appid, 60);
+
+    // selective disclosure
+    let assertion = Assertion::new(&ca.schema)
+        .require(
+            "attr0",
+            Predicate::Ge(AttributeValue::Integer(BigInt::from(10))),
+        )
+        .unwrap();
+    let challenge = challenger.issue();
+    let presentation = user
+        .present_assertion(&cm1, &req.master_key_g, &req2.sn, &assertion, &challenge)
+        .unwrap();
+    let received = Presentation::from_json(&presentation.to_json()).unwrap();
+    assert_eq!(received, presentation);
+    assert!(verifier.verify(&received).unwrap());
+    assert!(challenger.consume(&received.challenge).is_ok());
+
+    // the statement cannot be changed
+    let mut forged = received.clone();
+    if let Statement::Assertion { assertion, .. } = &mut forged.statement {
+        *assertion = assertion
+            .clone()
+            .require(
+                "attr0",
+                Predicate::Ge(AttributeValue::Integer(BigInt::from(11))),
+            )
+            .unwrap();
+    }
+    assert!(!verifier.verify(&forged).unwrap());
+
+    // set membership
+    let values: Vec<BigInt> = [3, 12, 40].iter().map(|x| BigInt::from(*x)).collect();
+    let set = AttributeSet::member(&values).unwrap();
+    let challenge = challenger.issue();
+    let presentation = user
+        .present_set(&cm1, &req.master_key_g, &req2.sn, 2, &set, &challenge)
+        .unwrap();
+    assert_eq!(
+        presentation.statement,
+        Statement::set(&ca.pubkey(), 2, &set).unwrap()
+    );
+    assert!(verifier.verify(&presentation).unwrap());
+
+    // access token
+    let challenge = challenger.issue();
+    let presentation = user
+        .present_appkey(&cm1, &req.master_key_g, &req2.sn, &challenge)
+        .unwrap();
+    assert!(verifier.verify(&presentation).unwrap());
+
+    // untrusted CAs are rejected
+    let mut strict = verifier;
+    strict.remove_ca(&ca.pubkey());
+    assert!(strict.verify(&received).is_err());
+}
+
+#[test]
+// Test the attestation of the attributes by the CA.
+fn test_attribute_verifier() {
+    let tpke_key = tpke_key();
+    // setup CA, attr3 must be attested by a known document issued to the master key
+    let mut ca = CA::init(8, tpke_key.clone());
+    let passport = b"passport of alice".to_vec();
+    let mut verifier = DocumentHashVerifier::new();
+    verifier.register(
+        document_hash(&passport, &new_key().public()),
+        "attr3",
+        BigInt::from(13),
+    );
+    ca.set_attribute_verifier(Arc::new(verifier.clone()));
+    // setup user client
+    let mut user = Client::new(tpke_key);
+
+    let attributes: Vec<BigInt> = (0..8).map(|x| (x + 10).to_bigint().unwrap()).collect();
+    let expiration = 31536000;
+
+    // the attributes are rejected without evidence
+    let req = user.request_credential(attributes.clone(), expiration, &ca);
+    assert_eq!(
+        ca.gen_credential(req).unwrap_err(),
+        CredentialError::Attestation(AttestationError::MissingEvidence("attr3".to_string()))
     );
-    assert!(cm1.verify_identity_proof(pub_inputs, &proof));
+
+    // or with a document issued to another master key
+    let evidence = vec![Evidence {
+        attribute: "attr3".to_string(),
+        document: passport.clone(),
+    }];
+    let req = user.request_credential_with_evidence(
+        attributes.clone(),
+        evidence.clone(),
+        expiration,
+        &ca,
+    );
+    assert_eq!(
+        ca.gen_credential(req).unwrap_err(),
+        CredentialError::Attestation(AttestationError::UnknownEvidence("attr3".to_string()))
+    );
+
+    // or with the evidence of other values
+    let mut changed = attributes.clone();
+    changed[3] = BigInt::from(14);
+    let req = user.request_credential_with_evidence(changed, evidence.clone(), expiration, &ca);
+    verifier.register(
+        document_hash(&passport, &req.master_key_g),
+        "attr3",
+        BigInt::from(13),
+    );
+    ca.set_attribute_verifier(Arc::new(verifier.clone()));
+    assert_eq!(
+        ca.gen_credential(req).unwrap_err(),
+        CredentialError::Attestation(AttestationError::Mismatch("attr3".to_string()))
+    );
+
+    let req = user.request_credential_with_evidence(attributes, evidence, expiration, &ca);
+    verifier.register(
+        document_hash(&passport, &req.master_key_g),
+        "attr3",
+        BigInt::from(13),
+    );
+    ca.set_attribute_verifier(Arc::new(verifier));
+    let cred = ca.gen_credential(req).unwrap();
+    user.fill_credential(cred);
+}
+
+#[test]
+// Test the issuance policy and the blacklist of the CA.
+fn test_issuance_policy() {
+    let tpke_key = tpke_key();
+    // setup CA and user client
+    let mut ca = CA::init(8, tpke_key.clone());
+    let mut user = Client::new(tpke_key);
+
+    let attributes: Vec<BigInt> = (0..8).map(|x| (x + 10).to_bigint().unwrap()).collect();
+    let expiration = 31536000;
+
+    // re-issuance is versioned by default
+    let req = user.request_credential(attributes.clone(), expiration, &ca);
+    let key = req.master_key_g.clone();
+    ca.gen_credential(req.clone()).unwrap();
+    // a new request of the same keys, with a fresh nonce
+    let cs = &user.credentials[&key];
+    let mut again = req.clone();
+    again.nonce += 1;
+    again.possession = PossessionProof::new(
+        &ca.pubkey(),
+        &again.hash().unwrap(),
+        &cs.master_key,
+        &cs.beta,
+    );
+    let cred = ca.gen_credential(again).unwrap();
+    let history = ca.get_user_history(&key);
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].version, 0);
+    assert_eq!(ca.get_user_info(&key).unwrap().version, 1);
+    assert_eq!(ca.get_user_info(&key).unwrap().expiration, cred.expiration);
+
+    // or refused
+    ca.set_policy(IssuancePolicy {
+        reissue: Reissue::Refuse,
+        max_expiration: Some(expiration),
+    });
+    assert_eq!(
+        ca.gen_credential(req.clone()).unwrap_err(),
+        CredentialError::AlreadyIssued
+    );
+    assert_eq!(ca.get_user_history(&key).len(), 2);
+
+    // the expiration is capped
+    let req = user.request_credential(attributes.clone(), expiration + 1, &ca);
+    assert_eq!(
+        ca.gen_credential(req).unwrap_err(),
+        CredentialError::ExpirationTooLong(expiration)
+    );
+
+    // blacklisted master keys are refused
+    let req = user.request_credential(attributes, expiration, &ca);
+    ca.add_blacklist(&req.master_key_g);
+    assert_eq!(
+        ca.gen_credential(req.clone()).unwrap_err(),
+        CredentialError::Blacklisted
+    );
+    ca.remove_blacklist(&req.master_key_g);
+    assert!(ca.gen_credential(req).is_ok());
+}
+
+#[test]
+// Test the renewal of a credential, keeping the pseudonyms.
+fn test_credential_renewal() {
+    let (cm1, mut ca, mut user) = setup();
+    ca.set_policy(IssuancePolicy {
+        reissue: Reissue::Refuse,
+        max_expiration: Some(31536000),
+    });
+    let key = issue(&mut user, &mut ca, 1000).master_key_g;
+    let cred = user.credentials[&key].credential.clone().unwrap();
+
+    // register a pseudonym
+    let address = BigInt::from_str("328659427551853837776595111020800456678649075473").unwrap();
+    let req1 = user.derive_identity(&cm1, &key, 100, &address, 10);
+    assert!(cm1.verify_key_request(&req1));
+
+    // renew the credential, even if the policy refuses re-issuance
+    let renewal = user.request_renewal(&key, 31536000, &ca).unwrap();
+    let renewed = ca.renew_credential(renewal.clone()).unwrap();
+    assert!(ca.renew_credential(renewal.clone()).is_err());
+    assert_eq!(renewed.attr_commit, cred.attr_commit);
+    assert!(renewed.expiration > cred.expiration);
+    assert_eq!(ca.get_user_history(&key).len(), 2);
+    user.renew_credential(renewed.clone()).unwrap();
+    assert_eq!(user.credentials[&key].credential.as_ref(), Some(&renewed));
+
+    // the pseudonyms are kept, and new ones are derived with the renewed credential
+    let req2 = user.derive_identity(&cm1, &key, 100, &address, 10);
+    assert!(cm1.verify_key_request(&req2));
+    assert_eq!(user.credentials[&key].derived_keys.len(), 2);
+    assert_ne!(req1.sn, req2.sn);
+    assert!(req2.expir > req1.expir);
+
+    // the renewal requires the keys of the credential
+    let mut forged = renewal.clone();
+    forged.beta_g = ca.pubkey();
+    assert!(ca.renew_credential(forged).is_err());
+    let other = user.request_credential((0..8).map(BigInt::from).collect(), 1000, &ca);
+    let mut forged = renewal;
+    forged.master_key_g = other.master_key_g;
+    assert_eq!(
+        ca.renew_credential(forged).unwrap_err(),
+        CredentialError::NotIssued
+    );
+    // and keeps the attributes
+    let mut changed = renewed;
+    changed.attr_commit = cred.beta_g;
+    assert!(user.renew_credential(changed).is_err());
+}
+
+#[test]
+// Test the revocation requests of CAs to the committee.
+fn test_ca_revocation() {
+    // only the first CA is trusted
+    let (mut cm1, mut ca, mut user) = setup();
+    let mut other_ca = CA::init(8, ca.tpke_key.clone());
+    let req = issue(&mut user, &mut ca, 31536000);
+    let key = req.master_key_g.clone();
+    let cred = user.credentials[&key].credential.clone().unwrap();
+
+    // only credentials issued by the CA can be revoked
+    let mut forged = cred.clone();
+    forged.expiration += 1;
+    assert_eq!(
+        ca.revocation_request(&forged, RevocationReason::Fraud)
+            .unwrap_err(),
+        CredentialError::NotIssued
+    );
+    assert_eq!(
+        other_ca
+            .revocation_request(&cred, RevocationReason::Fraud)
+            .unwrap_err(),
+        CredentialError::NotIssued
+    );
+    let revocation = ca
+        .revocation_request(&cred, RevocationReason::KeyCompromise)
+        .unwrap();
+    assert!(revocation.verify());
+    // the master key is refused new credentials
+    assert!(ca.is_blacklisted(&key));
+    assert_eq!(
+        ca.gen_credential(req).unwrap_err(),
+        CredentialError::Blacklisted
+    );
+
+    // the request is checked against the CA tree, the signature and the credential
+    let other_key = issue(&mut user, &mut other_ca, 31536000).master_key_g;
+    let other_cred = user.credentials[&other_key].credential.clone().unwrap();
+    let untrusted = other_ca
+        .revocation_request(&other_cred, RevocationReason::KeyCompromise)
+        .unwrap();
+    assert!(cm1.apply_revocations(&[untrusted]).is_err());
+    let mut forged = revocation.clone();
+    forged.reason = RevocationReason::Other;
+    assert!(cm1.apply_revocations(&[forged]).is_err());
+    let mut forged = revocation.clone();
+    forged.credential.expiration += 1;
+    assert!(!forged.verify());
+    assert!(cm1.apply_revocations(&[forged]).is_err());
+    assert!(cm1.block_tree.gen_notinproof(key.scalar_y()).is_ok());
+
+    // a batch holds at most one request for each master key, whatever their order
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    let newer = ca
+        .revocation_request(&cred, RevocationReason::Fraud)
+        .unwrap();
+    assert!(newer.timestamp > revocation.timestamp);
+    assert!(cm1
+        .apply_revocations(&[newer.clone(), revocation.clone()])
+        .is_err());
+    assert!(cm1.block_tree.gen_notinproof(key.scalar_y()).is_ok());
+    assert!(cm1.ca_revocations.get(&key).is_none());
+
+    cm1.apply_revocations(&[revocation.clone()]).unwrap();
+    assert!(cm1.block_tree.gen_notinproof(key.scalar_y()).is_err());
+
+    // the request cannot be replayed after the credential is reinstated
+    cm1.block_tree.remove_nodes(vec![key.scalar_y()]).unwrap();
+    assert!(cm1.apply_revocations(&[revocation]).is_err());
+    assert!(cm1.block_tree.gen_notinproof(key.scalar_y()).is_ok());
 }
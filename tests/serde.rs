@@ -7,27 +7,24 @@ use hades::convert;
 use hades::tpke::PublicKey;
 use hades::user_client::Client;
 use num_bigint::{BigInt, ToBigInt};
+use std::path::Path;
 // use std::time::SystemTime;
 
 #[test]
 // Test the serialization of the R1cs file.
 fn test_convert() {
-    convert(
-        "./circuits/pseudonym_check_js/pseudonym_check.wasm",
-        "./circuits/pseudonym_check.so",
-    );
-    convert(
-        "./circuits/sybil_check_js/sybil_check.wasm",
-        "./circuits/sybil_check.so",
-    );
-    convert(
-        "./circuits/tpke_single_js/tpke_single.wasm",
-        "./circuits/tpke_single.so",
-    );
-    convert(
-        "./circuits/pedersen_commit_js/pedersen_commit.wasm",
-        "./circuits/pedersen_commit.so",
-    );
+    // the circuits are compiled into ./circuits, see "Building circom" in the README
+    for name in [
+        "pseudonym_check",
+        "sybil_check",
+        "tpke_single",
+        "pedersen_commit",
+        "attribute_set",
+    ] {
+        let wasm = format!("./circuits/{}_js/{}.wasm", name, name);
+        assert!(Path::new(&wasm).exists(), "{} is not compiled", name);
+        convert(wasm, format!("./circuits/{}.so", name));
+    }
 }
 
 #[test]
@@ -72,7 +69,7 @@ fn test_ca_serde() {
 // test the serialization of committee client
 fn test_committee_serde() {
     // Committee client setup
-    let mut cm1 = Committee::new();
+    let mut cm1 = Committee::new(8);
     let tpke_key = PublicKey::new(vec![&cm1.tpke_shard(), &cm1.tpke_shard()]);
     cm1.update_tpke_key(tpke_key.clone());
 
@@ -82,4 +79,9 @@ fn test_committee_serde() {
     assert_eq!(cm1.part1(), cm2.part1());
     assert_eq!(cm1.app_params, cm2.app_params);
     assert_eq!(cm1.zkp_params, cm2.zkp_params);
+    assert_eq!(cm1.set_params, cm2.set_params);
+
+    // data of another format is rejected instead of panicking
+    std::fs::write("committee.tmp.dat", [0xff; 8]).unwrap();
+    assert!(Committee::load("committee.tmp").is_err());
 }